@@ -0,0 +1,164 @@
+use std::io::Write;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::keyboard_config::{Key, Chord, Layout, TypedChord};
+use super::gather_chords::{TrialResults, TypingError};
+
+// which chords get typed in place of which others. this comes from the substitutions in the alignment of each trial's input
+// with the expected sequence (see classify_errors), so missed, extra and swapped chords don't count as confusions:
+// they are mistakes in timing or order rather than in fingering.
+
+// the probability that one chord is typed when another was intended, e.g. for the layout optimizer
+pub trait ConfusionModel<K: Key, const N: usize, L: Layout<K, N>> {
+    fn confusion_probability(&self, intended: &Chord<K, N, L>, typed: &Chord<K, N, L>) -> f64;
+    // the chords which may be typed instead of intended, with their probabilities (the ones not listed have probability 0)
+    fn confusions(&self, intended: &Chord<K, N, L>) -> Vec<(Chord<K, N, L>, f64)>;
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct ConfusionEntry<K: Key, const N: usize, L: Layout<K, N>> {
+    pub expected: Chord<K, N, L>,
+    pub typed: Chord<K, N, L>,
+    pub count: usize,
+    // the number of times expected was supposed to be typed, in all the trials
+    pub attempts: usize,
+    // the keys in typed which aren't in expected, and the keys in expected which aren't in typed
+    pub extra_keys: Vec<K>,
+    pub missing_keys: Vec<K>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> ConfusionEntry<K, N, L> {
+    pub fn rate(&self) -> f64 {
+        // the estimated probability that typed is typed when expected was intended
+        self.count as f64 / self.attempts.max(1) as f64
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct ConfusionTable<K: Key, const N: usize, L: Layout<K, N>> {
+    pub n_trials: usize,
+    // how many times each chord was supposed to be typed
+    pub attempts: Vec<(Chord<K, N, L>, usize)>,
+    // most frequent first
+    pub entries: Vec<ConfusionEntry<K, N, L>>,
+}
+
+pub fn key_difference<K: Key, const N: usize, L: Layout<K, N>>(expected: &Chord<K, N, L>, typed: &Chord<K, N, L>) -> (Vec<K>, Vec<K>) {
+    // (extra keys, missing keys)
+    let extra = typed.difference(expected).keys().collect();
+    let missing = expected.difference(typed).keys().collect();
+    (extra, missing)
+}
+
+pub fn csv_field(field: String) -> String {
+    // quote the field if it would otherwise be misread
+    if field.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+pub fn keys_string<K: Key>(keys: &[K]) -> String {
+    keys.iter().map(|key| key.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> ConfusionTable<K, N, L> {
+    pub fn new() -> Self {
+        Self { n_trials: 0, attempts: Vec::new(), entries: Vec::new() }
+    }
+
+    pub fn from_results(results: &[TrialResults<K, N, L>]) -> Self {
+        let mut table = Self::new();
+        for r in results {
+            table.add_results(r);
+        }
+        table
+    }
+
+    fn attempts_of(&self, chord: &Chord<K, N, L>) -> usize {
+        self.attempts.iter().find(|(c, _)| c == chord).map_or(0, |(_, n)| *n)
+    }
+
+    pub fn add_results(&mut self, results: &TrialResults<K, N, L>) {
+        // impossible trials weren't typed, so they tell us nothing about confusions
+        for trial in &results.data {
+            let Some(errors) = trial.typing_errors() else { continue };
+            self.n_trials += 1;
+            for chord in &trial.chord_pair {
+                match self.attempts.iter_mut().find(|(c, _)| c == chord) {
+                    Some((_, n)) => *n += trial.n_repetitions,
+                    None => self.attempts.push((chord.clone(), trial.n_repetitions)),
+                }
+            }
+            for error in errors {
+                let TypingError::Substituted { expected, typed } = error else { continue };
+                // typed text which wasn't the output of any chord doesn't say which chord was typed instead
+                let (TypedChord::Chord(expected), TypedChord::Chord(typed)) = (expected, typed) else { continue };
+                match self.entries.iter_mut().find(|e| e.expected == expected && e.typed == typed) {
+                    Some(entry) => entry.count += 1,
+                    None => {
+                        let (extra_keys, missing_keys) = key_difference(&expected, &typed);
+                        self.entries.push(ConfusionEntry { expected, typed, count: 1, attempts: 0, extra_keys, missing_keys });
+                    },
+                }
+            }
+        }
+        for i in 0..self.entries.len() {
+            self.entries[i].attempts = self.attempts_of(&self.entries[i].expected);
+        }
+        self.entries.sort_by(|a, b| b.count.cmp(&a.count).then(b.rate().total_cmp(&a.rate())));
+    }
+
+    pub fn save_json(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(serde_json::to_writer_pretty(std::fs::File::create(path)?, self)?)
+    }
+
+    pub fn load_json(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        // one row per entry, with the chords in their text format and the keys separated by spaces
+        writeln!(writer, "expected,typed,count,attempts,rate,extra_keys,missing_keys")?;
+        for entry in &self.entries {
+            writeln!(writer, "{},{},{},{},{},{},{}",
+                     csv_field(entry.expected.to_string()),
+                     csv_field(entry.typed.to_string()),
+                     entry.count,
+                     entry.attempts,
+                     entry.rate(),
+                     csv_field(keys_string(&entry.extra_keys)),
+                     csv_field(keys_string(&entry.missing_keys)))?;
+        }
+        Ok(())
+    }
+
+    pub fn save_csv(&self, path: &str) -> std::io::Result<()> {
+        self.write_csv(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> ConfusionModel<K, N, L> for ConfusionTable<K, N, L> {
+    fn confusion_probability(&self, intended: &Chord<K, N, L>, typed: &Chord<K, N, L>) -> f64 {
+        // the fraction of the attempts at intended in which typed was typed instead (0 if intended was never attempted)
+        self.entries.iter().find(|e| e.expected == *intended && e.typed == *typed).map_or(0.0, |e| e.rate())
+    }
+
+    fn confusions(&self, intended: &Chord<K, N, L>) -> Vec<(Chord<K, N, L>, f64)> {
+        self.entries.iter().filter(|e| e.expected == *intended).map(|e| (e.typed.clone(), e.rate())).collect()
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> std::fmt::Display for ConfusionEntry<K, N, L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} typed as {}: {} of {} attempts (extra keys: {}; missing keys: {})",
+               self.expected, self.typed, self.count, self.attempts,
+               if self.extra_keys.is_empty() { "none".to_string() } else { keys_string(&self.extra_keys) },
+               if self.missing_keys.is_empty() { "none".to_string() } else { keys_string(&self.missing_keys) })
+    }
+}
@@ -0,0 +1,131 @@
+use serde::{Serialize, Deserialize};
+
+use crate::keyboard_config::{Key, Layout};
+use super::gather_chords::{accuracy_from_chord_pair, TrialResults};
+
+// finding trials which would distort a model trained on them: times far from the participant's usual times (e.g. they got
+// distracted in the middle of the trial), implausibly low accuracy, and the same trial recorded more than once.
+// trials are only flagged here; it's up to the caller whether to drop them, down-weight them, or just report them.
+
+// the constant which makes the median absolute deviation a consistent estimator of the standard deviation of a normal distribution
+const MAD_TO_STD: f64 = 1.4826;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutlierConfig {
+    // flag trials whose log switching time has a robust z-score (using the median and the median absolute deviation
+    // of the participant's trials) with absolute value above this
+    pub max_robust_z: f64,
+    // flag trials with accuracy below this
+    pub min_accuracy: f64,
+    // flag trials which are identical to an earlier trial
+    pub flag_duplicates: bool,
+}
+
+impl Default for OutlierConfig {
+    fn default() -> Self {
+        Self { max_robust_z: 3.5, min_accuracy: 0.5, flag_duplicates: true }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OutlierReason {
+    SlowTime { switching_time: f64, robust_z: f64 },
+    FastTime { switching_time: f64, robust_z: f64 },
+    LowAccuracy { accuracy: f64 },
+    Duplicate { of: usize },  // the index of the earlier trial
+}
+
+impl std::fmt::Display for OutlierReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutlierReason::SlowTime { switching_time, robust_z } => write!(f, "slow switching time {:.3}s (robust z-score {:.1})", switching_time, robust_z),
+            OutlierReason::FastTime { switching_time, robust_z } => write!(f, "fast switching time {:.3}s (robust z-score {:.1})", switching_time, robust_z),
+            OutlierReason::LowAccuracy { accuracy } => write!(f, "low accuracy {:.2}", accuracy),
+            OutlierReason::Duplicate { of } => write!(f, "duplicate of trial {}", of),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlaggedTrial {
+    pub index: usize,  // the index into TrialResults::data
+    pub reasons: Vec<OutlierReason>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutlierReport {
+    pub participant: Option<String>,
+    pub n_trials: usize,
+    pub flagged: Vec<FlaggedTrial>,  // in order of index
+}
+
+impl OutlierReport {
+    pub fn is_flagged(&self, index: usize) -> bool {
+        self.flagged.binary_search_by_key(&index, |trial| trial.index).is_ok()
+    }
+}
+
+impl std::fmt::Display for OutlierReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "participant {}: {} of {} trials flagged", self.participant.as_deref().unwrap_or("(unknown)"), self.flagged.len(), self.n_trials)?;
+        for trial in &self.flagged {
+            writeln!(f, "  trial {}: {}", trial.index, trial.reasons.iter().map(|r| r.to_string()).collect::<Vec<String>>().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    Some(if values.len() % 2 == 0 { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+}
+
+pub fn switching_time(time: f64, n_repetitions: usize) -> f64 {
+    // the average time between chords; a trial is the pair typed n_repetitions times, so there are 2 * n_repetitions - 1 switches
+    time / (2 * n_repetitions).saturating_sub(1).max(1) as f64
+}
+
+pub fn find_outliers<K: Key, const N: usize, L: Layout<K, N>>(results: &TrialResults<K, N, L>, config: &OutlierConfig) -> OutlierReport {
+    // results should all come from the same participant, since the times are compared with each other.
+    // impossible trials have no time or accuracy, so they can only be flagged as duplicates
+    let log_times: Vec<Option<f64>> = results.data.iter()
+        .map(|trial| trial.performance.as_ref().ok().map(|perf| switching_time(perf.time, trial.n_repetitions).ln()))
+        .collect();
+    let median_log_time = median(&mut log_times.iter().flatten().copied().collect::<Vec<f64>>());
+    let mad = median_log_time.and_then(|m| median(&mut log_times.iter().flatten().map(|t| (t - m).abs()).collect::<Vec<f64>>()));
+
+    let mut flagged = Vec::new();
+    for (index, trial) in results.data.iter().enumerate() {
+        let mut reasons = Vec::new();
+        if let (Ok(perf), Some(log_time), Some(median_log_time), Some(mad)) = (&trial.performance, log_times[index], median_log_time, mad) {
+            // when most times are identical the deviation is 0, and no times are treated as outliers
+            if mad > 0.0 {
+                let robust_z = (log_time - median_log_time) / (MAD_TO_STD * mad);
+                let switching_time = log_time.exp();
+                if robust_z > config.max_robust_z {
+                    reasons.push(OutlierReason::SlowTime { switching_time, robust_z });
+                } else if robust_z < -config.max_robust_z {
+                    reasons.push(OutlierReason::FastTime { switching_time, robust_z });
+                }
+            }
+            let accuracy = accuracy_from_chord_pair(&perf.input, &trial.chord_pair);
+            if accuracy < config.min_accuracy {
+                reasons.push(OutlierReason::LowAccuracy { accuracy });
+            }
+        }
+        if config.flag_duplicates {
+            if let Some(of) = results.data[..index].iter().position(|earlier| earlier == trial) {
+                reasons.push(OutlierReason::Duplicate { of });
+            }
+        }
+        if !reasons.is_empty() {
+            flagged.push(FlaggedTrial { index, reasons });
+        }
+    }
+    OutlierReport { participant: results.participant.clone(), n_trials: results.data.len(), flagged }
+}
@@ -1,45 +1,33 @@
 use std::error::Error;
-use rand::rngs::ThreadRng as R;
+use rand::SeedableRng;
+use rand::rngs::StdRng as R;
 
 use crate::keyboard_config::{Key, Layout, ChordTrialUtils, ChordSampler};
-use crate::local_env::DATA_PATH;
+use crate::chord_preferences::session::{self, Provenance};
+use crate::data_store::{Artifact, DataStore};
 
-pub fn gen_random_config_with_trial_decoder<K: Key, const N: usize, L: Layout<K,N>, I, S: ChordSampler<K, N, L, R, I>, C: ChordTrialUtils<K, N, L, R, I, S>>(initialization_info: &I) -> Result<(Vec<u8>, C), Box<dyn Error>> {
+pub fn gen_random_config_with_trial_decoder<K: Key, const N: usize, L: Layout<K,N>, I, S: ChordSampler<K, N, L, R, I>, C: ChordTrialUtils<K, N, L, R, I, S>>(initialization_info: &I, code_config: &C::CodeConfig, seed: u64) -> Result<(Vec<u8>, C), Box<dyn Error>> {
     // create a legal vocabulary of chords, and a decoder for the trial output.
-    // return the text of a keyboard config file and the decoder used to parse trial output
-    let chord_trial_utils = C::new(S::new(rand::thread_rng(), initialization_info)?);
+    // return the text of a keyboard config file and the decoder used to parse trial output.
+    // the same seed, initialization info and code config always give the same config and decoder
+    let chord_trial_utils = C::new(S::new(R::seed_from_u64(seed), initialization_info)?, Some(seed), code_config)?;
     Ok((chord_trial_utils.get_config()?, chord_trial_utils))
 }
 
-pub fn run<'a, K: Key, const N: usize, L: Layout<K,N>, I, S: ChordSampler<K, N, L, R, I>, C: ChordTrialUtils<K, N, L, R, I, S>>(initialization_info: &I) {
-    let current_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
-    let results_path = format!("{}/config_{}.cfg", DATA_PATH, current_time);
-
-    let (config, trial_decoder) = match gen_random_config_with_trial_decoder::<K, N, L, I, S, C>(initialization_info) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("error generating config: {}", e);
-            return;
-        }
+pub fn run<'a, K: Key, const N: usize, L: Layout<K,N>, I, S: ChordSampler<K, N, L, R, I>, C: ChordTrialUtils<K, N, L, R, I, S>>(initialization_info: &I, code_config: &C::CodeConfig, seed: u64, provenance: Provenance, store: &DataStore, package_path: Option<&str>) -> Result<(), Box<dyn Error>> {
+    // the config and decoder are written as a session package to package_path if it is given,
+    // and otherwise to a timestamped directory in the store
+    let package_path = match package_path {
+        Some(path) => path.to_string(),
+        None => store.new_path(Artifact::Decoder, "session", "").map_err(|e| format!("error creating the decoders directory: {}", e))?,
     };
 
-    match std::fs::write(&results_path, config) {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("error writing config to file: {}", e);
-            return;
-        }
-    }
-
-    let decoder_path = format!("{}/decoder_{}.json", DATA_PATH, current_time);
-    match std::fs::write(&decoder_path, serde_json::to_string(&trial_decoder).unwrap()) {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("error writing decoder to file: {}", e);
-            return;
-        }
-    }
+    let (config, trial_decoder) = gen_random_config_with_trial_decoder::<K, N, L, I, S, C>(initialization_info, code_config, seed)
+        .map_err(|e| format!("error generating config: {}", e))?;
 
-    println!("generated config file:\n{}", results_path);
-    println!("generated decoder file:\n{}", decoder_path);
+    let manifest = session::write_package(&package_path, &config, &trial_decoder, seed, provenance)
+        .map_err(|e| format!("error writing session package: {}", e))?;
+    println!("generated session package {}:\n{}", manifest.id, package_path);
+    println!("load {} onto the keyboard, and pass the package to gather", session::CONFIG_FILE);
+    Ok(())
 }
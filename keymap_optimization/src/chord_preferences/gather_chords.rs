@@ -1,15 +1,18 @@
 use rand::prelude::SliceRandom;
-use rand::rngs::ThreadRng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use serde::{Serialize, Deserialize, de::DeserializeOwned};
 use std::{array, vec};
 use std::collections::HashMap;
+use std::error::Error;
 
-use crate::keyboard_config::{Key, Chord, Layout, ChordTrialUtils, GraphicalChord, ChordSampler};
-use crate::local_env::DATA_PATH;
+use crate::keyboard_config::{Key, Chord, Layout, ChordTrialUtils, GraphicalChord, ChordSampler, TypedChord};
+use crate::chord_preferences::session;
+use crate::data_store::{Artifact, DataStore};
 
-const N_REPETITIONS_PER_TRIAL: usize = 5;
+pub const N_REPETITIONS_PER_TRIAL: usize = 5;
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 #[derive(Serialize, Deserialize)]
 pub enum ErrCode {
     Impossible,
@@ -20,11 +23,15 @@ pub enum ErrCode {
 #[derive(Serialize, Deserialize)]
 #[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
 pub struct Performance<K: Key, const N: usize, L: Layout<K, N>> {
-    pub input: Vec<Chord<K, N, L>>,
+    pub input: Vec<TypedChord<K, N, L>>,
     pub time: f64,
+    // the mistakes found by aligning the input with the expected sequence.
+    // results files from before these were recorded don't have them; see TrialData::typing_errors
+    #[serde(default)]
+    pub errors: Option<Vec<TypingError<TypedChord<K, N, L>>>>,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 #[derive(Serialize, Deserialize)]
 #[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
 pub struct TrialData<K: Key, const N: usize, L: Layout<K, N>> {
@@ -38,12 +45,36 @@ pub struct TrialData<K: Key, const N: usize, L: Layout<K, N>> {
 #[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
 pub struct TrialResults<K: Key, const N: usize, L: Layout<K, N>> {
     pub data: Vec<TrialData<K, N, L>>,
+    // the seeds used to choose the chord pairs and to generate the decoder, if known.
+    // results files from before seeds were recorded don't have them
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub decoder_seed: Option<u64>,
+    // who typed the trials, so that their times can be compared with each other (e.g. to find outliers)
+    #[serde(default)]
+    pub participant: Option<String>,
+    // the id of the session package the decoder came from, if it came from one
+    #[serde(default)]
+    pub session: Option<String>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> TrialData<K, N, L> {
+    pub fn typing_errors(&self) -> Option<Vec<TypingError<TypedChord<K, N, L>>>> {
+        // the errors stored with the trial, or recomputed from the input if they weren't stored. None for impossible trials
+        let perf = self.performance.as_ref().ok()?;
+        Some(perf.errors.clone().unwrap_or_else(|| errors_from_chord_pair(&perf.input, &self.chord_pair)))
+    }
 }
 
 impl<K: Key, const N: usize, L: Layout<K, N>> TrialResults<K, N, L> {
     pub fn new() -> Self {
         Self {
             data: Vec::new(),
+            seed: None,
+            decoder_seed: None,
+            participant: None,
+            session: None,
         }
     }
 
@@ -64,7 +95,7 @@ impl<K: Key, const N: usize, L: Layout<K, N>> TrialResults<K, N, L> {
     }
 }
 
-pub fn alignment_quality<T: PartialEq>(seq_predicted: &Vec<T>, seq_corrupted: &Vec<T>) -> (u8, u8) {
+pub fn alignment_quality<T: PartialEq>(seq_predicted: &[T], seq_corrupted: &[T]) -> (u8, u8) {
     // returns the number of correct chords and the number of incorrect chords after alignment.
     let (correct, incorrect, _) = align(seq_predicted, seq_corrupted);
     (correct, incorrect)
@@ -98,7 +129,7 @@ pub fn best_candidate(candidates: &Vec<(u8, u8, Direction)>) -> &(u8, u8, Direct
               .unwrap()
 }
 
-pub fn align<T: PartialEq>(seq_predicted: &Vec<T>, seq_corrupted: &Vec<T>) -> (u8, u8, Vec<Vec<Vec<(u8, u8, Direction)>>>) {
+pub fn align<T: PartialEq>(seq_predicted: &[T], seq_corrupted: &[T]) -> (u8, u8, Vec<Vec<Vec<(u8, u8, Direction)>>>) {
     // currently we treat the two sequences identically, using a dynamic programming algorithm
     // similar to needleman-wunch but optimizing for the fraction of the total chords that are correct.
     // however, it may be desirable to treat the sequences asymmetrically, since we know that one of them
@@ -236,7 +267,115 @@ pub fn align<T: PartialEq>(seq_predicted: &Vec<T>, seq_corrupted: &Vec<T>) -> (u
     (*correct, *incorrect, nw_matrix)
 }
 
-pub fn compute_accuracy<K: Key, const N: usize, L: Layout<K, N>>(actual_input: &Vec<Chord<K, N, L>>, expected_input: &Vec<Chord<K, N, L>>) -> f64 {
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+pub enum AlignedPair<T> {
+    Match(T),
+    Substitution { expected: T, typed: T },
+    Missed(T),  // expected, but nothing was typed in its place
+    Inserted(T),  // typed, but nothing was expected in its place
+}
+
+pub fn traceback<T: PartialEq + Clone>(seq_expected: &[T], seq_typed: &[T]) -> Vec<AlignedPair<T>> {
+    // the alignment whose quality align reports, as a sequence of aligned pairs from the start of both sequences.
+    // we follow the best final candidate back through the matrix: each candidate was built from one in the previous cell
+    // (given by its direction) with a known number of correct and incorrect elements, so we look for exactly that one
+    let (correct, incorrect, nw_matrix) = align(seq_expected, seq_typed);
+    let (mut i, mut j) = (seq_expected.len(), seq_typed.len());
+    let (mut nc, mut ni) = (correct, incorrect);
+    let mut aligned = Vec::new();
+    while i > 0 || j > 0 {
+        // this unwrap is safe because the counts we're looking for are always those of the candidate the current one was built from
+        let (_, _, dirn) = nw_matrix[i][j].iter().find(|(c, n, _)| *c == nc && *n == ni).unwrap();
+        match dirn {
+            Direction::Diag if seq_expected[i - 1] == seq_typed[j - 1] => {
+                aligned.push(AlignedPair::Match(seq_typed[j - 1].clone()));
+                nc -= 1;
+            },
+            Direction::Diag => {
+                aligned.push(AlignedPair::Substitution { expected: seq_expected[i - 1].clone(), typed: seq_typed[j - 1].clone() });
+                ni -= 1;
+            },
+            Direction::Vert => {
+                aligned.push(AlignedPair::Missed(seq_expected[i - 1].clone()));
+                ni -= 1;
+            },
+            Direction::Horz => {
+                aligned.push(AlignedPair::Inserted(seq_typed[j - 1].clone()));
+                // repeated insertions of the same chord are only counted once (see align)
+                if !(1 < j && j < seq_typed.len() && seq_typed[j - 1] == seq_typed[j - 2]) {
+                    ni -= 1;
+                }
+            },
+        }
+        match dirn {
+            Direction::Diag => { i -= 1; j -= 1; },
+            Direction::Vert => i -= 1,
+            Direction::Horz => j -= 1,
+        }
+    }
+    aligned.reverse();
+    aligned
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+pub enum TypingError<T> {
+    Substituted { expected: T, typed: T },
+    Missed { expected: T },
+    Doubled { chord: T },  // typed again right after itself, usually from holding a key too long
+    Extra { typed: T },  // any other insertion
+    Swapped { first: T, second: T },  // two consecutive expected chords typed in the opposite order
+}
+
+pub fn classify_errors<T: PartialEq + Clone>(alignment: &[AlignedPair<T>]) -> Vec<TypingError<T>> {
+    // a swap can appear in the alignment either as two substitutions which exchange the chords, or as one of the chords
+    // being inserted before the other and then missed after it (or vice versa); these are each counted as a single error
+    use AlignedPair::*;
+    let mut errors = Vec::new();
+    let mut last_typed: Option<&T> = None;
+    let mut k = 0;
+    while k < alignment.len() {
+        let swap = match (&alignment[k], alignment.get(k + 1), alignment.get(k + 2)) {
+            (Substitution { expected: e1, typed: t1 }, Some(Substitution { expected: e2, typed: t2 }), _) if t1 == e2 && t2 == e1 =>
+                Some((e1, e2, t2, 2)),
+            (Inserted(x), Some(Match(y)), Some(Missed(x2))) if x == x2 && x != y => Some((y, x, y, 3)),
+            (Missed(x), Some(Match(y)), Some(Inserted(x2))) if x == x2 && x != y => Some((x, y, x, 3)),
+            _ => None,
+        };
+        if let Some((first, second, typed_last, length)) = swap {
+            errors.push(TypingError::Swapped { first: first.clone(), second: second.clone() });
+            last_typed = Some(typed_last);
+            k += length;
+            continue;
+        }
+        match &alignment[k] {
+            Match(typed) => last_typed = Some(typed),
+            Substitution { expected, typed } => {
+                errors.push(TypingError::Substituted { expected: expected.clone(), typed: typed.clone() });
+                last_typed = Some(typed);
+            },
+            Missed(expected) => errors.push(TypingError::Missed { expected: expected.clone() }),
+            Inserted(typed) => {
+                // the alignment may put the extra copy of a doubled chord either before or after the one that counts
+                let next_typed = alignment[k + 1..].iter().find_map(|pair| match pair {
+                    Match(t) | Inserted(t) | Substitution { typed: t, .. } => Some(t),
+                    Missed(_) => None,
+                });
+                if last_typed == Some(typed) || next_typed == Some(typed) {
+                    errors.push(TypingError::Doubled { chord: typed.clone() });
+                } else {
+                    errors.push(TypingError::Extra { typed: typed.clone() });
+                }
+                last_typed = Some(typed);
+            },
+        }
+        k += 1;
+    }
+    errors
+}
+
+pub fn compute_accuracy<K: Key, const N: usize, L: Layout<K, N>>(actual_input: &Vec<TypedChord<K, N, L>>, expected_input: &[Chord<K, N, L>]) -> f64 {
     // we find the optimal "alignment" between the two sequences: the way to insert "filler" chords
     // in both of them so that the greatest number of chords match each other. 
     // i.e., for sequence ABABAB and BABABA, a direct comparison would give an accuracy of 0 but the optimal alignment     ABABAB
@@ -244,20 +383,91 @@ pub fn compute_accuracy<K: Key, const N: usize, L: Layout<K, N>>(actual_input: &
     // (in other words, we assume that the user accidentally typed B before they attempted the sequence, and then missed the final element)
     // we don't give an ''partial credit'' if the user gets most of the keys in a chord right but messes up one or two; the result of this
     // will generally be illegible, so we want the reward model to learn to avoid chords which are difficult to type accurately.
-    let (correct, incorrect) = alignment_quality(expected_input, actual_input);
+    // text which couldn't be decoded is in actual_input as invalid input, which never matches an expected chord, so it counts as a mistake.
+    let (correct, incorrect) = alignment_quality(&as_typed(expected_input), actual_input);
     (correct as f64) / ((correct + incorrect) as f64)
 }
 
-pub fn accuracy_from_chord_pair<K: Key, const N: usize, L: Layout<K, N>>(actual_input: &Vec<Chord<K, N, L>>, chord_pair: &[Chord<K, N, L>; 2]) -> f64 {
+pub fn as_typed<K: Key, const N: usize, L: Layout<K, N>>(chords: &[Chord<K, N, L>]) -> Vec<TypedChord<K, N, L>> {
+    // the chords as they'd be read if they were typed correctly, for comparing with what was typed
+    chords.iter().cloned().map(TypedChord::from).collect()
+}
+
+pub fn accuracy_from_chord_pair<K: Key, const N: usize, L: Layout<K, N>>(actual_input: &Vec<TypedChord<K, N, L>>, chord_pair: &[Chord<K, N, L>; 2]) -> f64 {
     let expected_input: [Chord<K, N, L>; 2 * N_REPETITIONS_PER_TRIAL] = array::from_fn(|i| chord_pair[i % 2].clone());
-    compute_accuracy::<K, N, L>(&actual_input, &expected_input.to_vec())
+    compute_accuracy::<K, N, L>(&actual_input, &expected_input)
+}
+
+pub fn errors_from_chord_pair<K: Key, const N: usize, L: Layout<K, N>>(actual_input: &Vec<TypedChord<K, N, L>>, chord_pair: &[Chord<K, N, L>; 2]) -> Vec<TypingError<TypedChord<K, N, L>>> {
+    let expected_input: Vec<TypedChord<K, N, L>> = (0..2 * N_REPETITIONS_PER_TRIAL).map(|i| TypedChord::Chord(chord_pair[i % 2].clone())).collect();
+    classify_errors(&traceback(&expected_input, actual_input))
+}
+
+// the number of chords typed to check that the decoder is the one for the config on the keyboard
+pub const N_CALIBRATION_CHORDS: usize = 4;
+
+pub fn check_calibration<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, I, S: ChordSampler<K, N, L, R, I>, C: ChordTrialUtils<K, N, L, R, I, S>>(chord_trial_utils: &C, expected: &[Chord<K, N, L>], typed: &str) -> Result<(), String> {
+    // whether typing the expected chords produced what the decoder expects, and if not, a description of what went wrong
+    let parsed = chord_trial_utils.parse_trial_string_tolerant(typed);
+    let expected = as_typed(expected);
+    if parsed == expected {
+        return Ok(());
+    }
+    let describe = |chords: &[TypedChord<K, N, L>]| chords.iter().map(|chord| chord.to_string()).collect::<Vec<String>>().join(" ");
+    Err(format!("expected the chords {}, but the decoder read {:?} as {}", describe(&expected), typed, describe(&parsed)))
+}
+
+fn calibrate<K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, StdRng, I>, C: ChordTrialUtils<K, N, L, StdRng, I, S>>(chord_trial_utils: &C, rng: &mut StdRng) -> Result<bool, std::io::Error> {
+    // have the user type a few chords before the trials, to catch a decoder which doesn't belong to the config on the keyboard.
+    // returns whether to go on with the trials
+    let vocab = chord_trial_utils.get_vocab();
+    let expected: Vec<Chord<K, N, L>> = vocab.choose_multiple(rng, N_CALIBRATION_CHORDS).map(|(chord, _)| chord.clone()).collect();
+    loop {
+        println!("first, to check that the keyboard has the config for this decoder, type these chords in order and hit Enter (or type SKIP to skip this check):");
+        for chord in &expected {
+            println!("{}", chord);
+            println!("{}", GraphicalChord { chord });
+        }
+        let mut input = String::new();
+        std::io::stdin().read_line(&mut input)?;
+        let input = input.trim_end_matches(['\n', '\r']);
+        if input == "SKIP" {
+            return Ok(true);
+        }
+        match check_calibration(chord_trial_utils, &expected, input) {
+            Ok(()) => {
+                println!("the decoder matches the keyboard.");
+                return Ok(true);
+            },
+            Err(e) => {
+                println!("{}. if you typed them right, the keyboard probably has a different config from the one this decoder was generated with.", e);
+                println!("type R to try again, GO to go on anyway, or QUIT to quit.");
+                let mut choice = String::new();
+                std::io::stdin().read_line(&mut choice)?;
+                match choice.trim_end_matches(['\n', '\r']) {
+                    "GO" => return Ok(true),
+                    "QUIT" => return Ok(false),
+                    _ => continue,
+                }
+            },
+        }
+    }
 }
 
-fn gather_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, ThreadRng, I>, C: ChordTrialUtils<K, N, L, ThreadRng, I, S>>(chord_trial_utils: C) -> Result<TrialResults<K, N, L>, std::io::Error> {
-    let rng = &mut rand::thread_rng();
+fn gather_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, StdRng, I>, C: ChordTrialUtils<K, N, L, StdRng, I, S>>(chord_trial_utils: C, seed: u64) -> Result<TrialResults<K, N, L>, std::io::Error> {
+    // the seed determines the sequence of chord pairs shown
+    let rng = &mut StdRng::seed_from_u64(seed);
     println!("you will be shown two chords. after some time to practice, you will need to type this pair of chords {} times, as quickly as possible.", N_REPETITIONS_PER_TRIAL);
     
     let mut results: TrialResults<K, N, L> = TrialResults::new();
+    results.seed = Some(seed);
+    results.decoder_seed = chord_trial_utils.get_seed();
+
+    // the calibration chords are drawn separately, so that the trials are the same whether or not it's done
+    if !calibrate(&chord_trial_utils, &mut StdRng::seed_from_u64(seed.wrapping_add(1)))? {
+        println!("quitting...");
+        return Ok(results);
+    }
 
     let chord_list: Vec<&Chord<K, N, L>> = chord_trial_utils.get_vocab()
                                                            .into_iter()
@@ -281,18 +491,19 @@ fn gather_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K
                 let mut trial_input = String::new();
                 let start_time = std::time::Instant::now();
                 std::io::stdin().read_line(&mut trial_input)?;
-                let parsed_chords = match chord_trial_utils.parse_trial_string(&trial_input[0..trial_input.len() - 1]) {  // remove the final newline
-                    Ok(parsed) => parsed,
-                    Err(e) => {
-                        println!("error parsing input: {}. perhaps you entered text from the wrong device?", e);
-                        continue 'trial;
-                    }
-                };
                 let trial_time = start_time.elapsed().as_secs_f64();
+                // text which isn't the output of any chord (e.g. from a chord which isn't in the vocabulary) is kept as an
+                // invalid input, which counts as a mistake, rather than spoiling the whole trial
+                let parsed_chords = chord_trial_utils.parse_trial_string_tolerant(trial_input.trim_end_matches(['\n', '\r']));
+                let n_invalid = parsed_chords.iter().filter(|chord| chord.is_invalid_input()).count();
+                if n_invalid > 0 {
+                    println!("{} part(s) of the input weren't the output of any chord. if you entered text from the wrong device, try again (N).", n_invalid);
+                }
 
                 // print accuracy and speed to the user
                 let expected_chords: [Chord<K, N, L>; 2 * N_REPETITIONS_PER_TRIAL] = array::from_fn(|i| chords[i % 2].clone());
-                let trial_accuracy = compute_accuracy::<K, N, L>(&parsed_chords, &expected_chords.to_vec());
+                let trial_accuracy = compute_accuracy::<K, N, L>(&parsed_chords, &expected_chords);
+                let trial_errors = classify_errors(&traceback(&as_typed(&expected_chords), &parsed_chords));
                 let expected_input: Vec<String> = expected_chords.into_iter().map(|c| chord_trial_utils.lookup_chord(&c).unwrap()).collect();  // this unwrap is safe if the code is correct, because this chord belongs to the vocab
                 println!("expected input: {}; accuracy: {}; average switching time: {}", expected_input.join(" "), trial_accuracy, trial_time / ((2 * N_REPETITIONS_PER_TRIAL - 1) as f64));
                 println!("accept this trial (Y), or try again (N)?");
@@ -304,7 +515,7 @@ fn gather_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K
                         let trial_data = TrialData {
                             chord_pair: chords,
                             n_repetitions: N_REPETITIONS_PER_TRIAL,
-                            performance: Ok(Performance { input: parsed_chords, time: trial_time }),
+                            performance: Ok(Performance { input: parsed_chords, time: trial_time, errors: Some(trial_errors.clone()) }),
                         };
                         results.push(trial_data);
                         break 'trial;
@@ -334,22 +545,23 @@ fn gather_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K
     }
 }
 
-pub fn gather_and_save_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, ThreadRng, I>, C: ChordTrialUtils<K, N, L, ThreadRng, I, S>>(chord_trial_utils_file: &str) -> Result<TrialResults<K, N, L>, std::io::Error> {
-    let results_path = format!("{}/chord_preferences_results_{}.json",
-                                       DATA_PATH,
-                                       std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs());
-    let chord_trial_utils: C = serde_json::from_reader(std::fs::File::open(std::path::Path::new(chord_trial_utils_file))?)?;
-    let results = gather_data::<K, N, L, I, S, C>(chord_trial_utils)?;
+pub fn gather_and_save_data<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, StdRng, I>, C: ChordTrialUtils<K, N, L, StdRng, I, S>>(decoder_path: &str, seed: u64, participant: Option<&str>, store: &DataStore, results_path: Option<&str>) -> Result<TrialResults<K, N, L>, Box<dyn Error>> {
+    // decoder_path is a session package or a decoder file.
+    // the results are written to results_path if it is given, and otherwise to a timestamped file in the store
+    let results_path = match results_path {
+        Some(path) => path.to_string(),
+        None => store.new_path(Artifact::Results, "chord_preferences_results", "json")?,
+    };
+    let (chord_trial_utils, manifest): (C, _) = session::load_decoder(decoder_path)?;
+    let mut results = gather_data::<K, N, L, I, S, C>(chord_trial_utils, seed)?;
+    results.participant = participant.map(str::to_string);
+    results.session = manifest.map(|manifest| manifest.id);
     results.save(&results_path)?;
     Ok(results)
 }
 
-pub fn run<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, ThreadRng, I>, C: ChordTrialUtils<K, N, L, ThreadRng, I, S>>(chord_trial_utils_file: &str) {
-    match gather_and_save_data::<K, N, L, I, S, C>(chord_trial_utils_file) {
-        Ok(gather_results) => gather_results,
-        Err(e) => {
-            eprintln!("Error gathering or saving data: {}", e);
-            return;
-        }
-    };
+pub fn run<'a, K: Key, const N: usize, L: Layout<K, N>, I, S: ChordSampler<K, N, L, StdRng, I>, C: ChordTrialUtils<K, N, L, StdRng, I, S>>(decoder_path: &str, seed: u64, participant: Option<&str>, store: &DataStore, results_path: Option<&str>) -> Result<(), Box<dyn Error>> {
+    gather_and_save_data::<K, N, L, I, S, C>(decoder_path, seed, participant, store, results_path)
+        .map_err(|e| format!("Error gathering or saving data: {}", e))?;
+    Ok(())
 }
@@ -1,4 +1,8 @@
 pub mod gather_chords;
 pub mod data_collection_keymap_gen;
+pub mod data_cleaning;
+pub mod confusion;
+pub mod practice;
+pub mod session;
 
 pub use gather_chords::*;
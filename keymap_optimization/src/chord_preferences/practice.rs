@@ -0,0 +1,489 @@
+use std::io::Write;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::prelude::SliceRandom;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::keyboard_config::{Key, Chord, Layout, GraphicalChord, DecodedToken, LineEvent, StreamDecoder, TypedChord};
+use crate::layout_optimization::corpus::Segmenter;
+use crate::data_store::{Artifact, DataStore};
+use super::gather_chords::{as_typed, classify_errors, traceback, AlignedPair, TypingError};
+
+// a typing tutor for a layout (a vocabulary of chords and their outputs). it shows lines of a corpus with the chords for
+// them and times the learner typing them on the keyboard, which has to be configured with the layout. the typed text is
+// split back into outputs as it's typed, so that a wrong chord can be signalled right away, and then aligned with the
+// prompt to find the chords which were typed wrongly.
+// the chords are scheduled with spaced repetition (a leitner system): a prompt in which all of a chord's occurrences are
+// right moves it up a level, and it's next due after twice as many prompts as the time before; a mistake puts it back to
+// level 0. each prompt is the one of some random lines which contains the most chords that are due, counting the ones
+// with more mistakes for more. chords at hint_level or above aren't drawn before the prompt any more, so they have to be recalled.
+// every line typed is also logged, as a source of naturalistic data on the transitions between chords (see PracticeLog).
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PracticeConfig {
+    // lines of the corpus with more outputs than this are split into sentences, and then into runs of words
+    pub max_prompt_outputs: usize,
+    // the number of random prompts each prompt is chosen from
+    pub n_prompt_candidates: usize,
+    pub hint_level: u32,
+}
+
+impl Default for PracticeConfig {
+    fn default() -> Self {
+        Self { max_prompt_outputs: 40, n_prompt_candidates: 50, hint_level: 3 }
+    }
+}
+
+impl PracticeConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config: Self = serde_json::from_reader(std::fs::File::open(path)?)?;
+        config.check()?;
+        Ok(config)
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // the prompts are aligned with what's typed, which only works for so many outputs
+        if self.max_prompt_outputs > MAX_TYPED_OUTPUTS {
+            return Err(format!("max_prompt_outputs is {}, but prompts can have at most {} outputs", self.max_prompt_outputs, MAX_TYPED_OUTPUTS).into());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct ChordProgress<K: Key, const N: usize, L: Layout<K, N>> {
+    pub chord: Chord<K, N, L>,
+    pub output: String,
+    // the number of times the chord was in a prompt, and the number of those times it was typed wrongly
+    pub attempts: usize,
+    pub errors: usize,
+    pub level: u32,
+    // the number of prompts after which the chord is next due
+    pub due: usize,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> ChordProgress<K, N, L> {
+    pub fn error_rate(&self) -> f64 {
+        self.errors as f64 / self.attempts.max(1) as f64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub n_prompts: usize,
+    // the number of outputs in the prompts, and how many of them were typed wrongly
+    pub n_outputs: usize,
+    pub n_errors: usize,
+    pub n_characters: usize,
+    pub seconds: f64,
+}
+
+impl SessionSummary {
+    pub fn wpm(&self) -> f64 {
+        words_per_minute(self.n_characters, self.seconds)
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        1.0 - self.n_errors as f64 / self.n_outputs.max(1) as f64
+    }
+}
+
+pub fn words_per_minute(n_characters: usize, seconds: f64) -> f64 {
+    // a word is 5 characters, by the usual convention
+    if seconds <= 0.0 { 0.0 } else { n_characters as f64 / 5.0 / (seconds / 60.0) }
+}
+
+// the learner's progress with a layout, saved between sessions
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct PracticeProgress<K: Key, const N: usize, L: Layout<K, N>> {
+    // in the order of the vocabulary
+    pub chords: Vec<ChordProgress<K, N, L>>,
+    pub n_prompts: usize,
+    pub sessions: Vec<SessionSummary>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> PracticeProgress<K, N, L> {
+    pub fn new(vocab: &[(Chord<K, N, L>, String)]) -> Self {
+        Self::for_vocab(vocab, None)
+    }
+
+    pub fn for_vocab(vocab: &[(Chord<K, N, L>, String)], previous: Option<Self>) -> Self {
+        // the progress for vocab, keeping the previous progress for the chords which still have the same outputs
+        let previous = previous.unwrap_or(Self { chords: Vec::new(), n_prompts: 0, sessions: Vec::new() });
+        let chords = vocab.iter().map(|(chord, output)| {
+            previous.chords.iter().find(|p| p.chord == *chord && p.output == *output).cloned().unwrap_or_else(|| ChordProgress {
+                chord: chord.clone(), output: output.clone(), attempts: 0, errors: 0, level: 0, due: 0,
+            })
+        }).collect();
+        Self { chords, n_prompts: previous.n_prompts, sessions: previous.sessions }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(serde_json::to_writer_pretty(std::fs::File::create(path)?, self)?)
+    }
+
+    fn priority(&self, idx: usize) -> f64 {
+        // how much it's worth having the chord in the next prompt: nothing if it isn't due, and more for more mistakes
+        let progress = &self.chords[idx];
+        if progress.due <= self.n_prompts { 1.0 + 4.0 * progress.error_rate() } else { 0.0 }
+    }
+
+    pub fn prompt_priority(&self, prompt: &[usize]) -> f64 {
+        let mut distinct = prompt.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        distinct.into_iter().map(|idx| self.priority(idx)).sum()
+    }
+
+    pub fn record(&mut self, expected: &[usize], correct: &[bool]) {
+        // update the chords of a prompt (given as the index of each output in the vocabulary) with whether each was typed right
+        self.n_prompts += 1;
+        let mut distinct = expected.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        for idx in distinct {
+            let occurrences: Vec<bool> = expected.iter().zip(correct).filter(|(e, _)| **e == idx).map(|(_, c)| *c).collect();
+            let progress = &mut self.chords[idx];
+            progress.attempts += occurrences.len();
+            progress.errors += occurrences.iter().filter(|c| !**c).count();
+            if occurrences.iter().all(|c| *c) {
+                progress.level += 1;
+            } else {
+                progress.level = 0;
+            }
+            progress.due = self.n_prompts + (1 << progress.level.min(16)) - 1;
+        }
+    }
+
+    pub fn weakest(&self, n: usize) -> Vec<&ChordProgress<K, N, L>> {
+        // the chords with the highest error rates, among those which have been attempted
+        let mut attempted: Vec<&ChordProgress<K, N, L>> = self.chords.iter().filter(|p| p.attempts > 0).collect();
+        attempted.sort_by(|a, b| b.error_rate().total_cmp(&a.error_rate()).then(b.attempts.cmp(&a.attempts)));
+        attempted.into_iter().take(n).collect()
+    }
+}
+
+// a line typed in practice
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct PracticeLine<K: Key, const N: usize, L: Layout<K, N>> {
+    pub expected: Vec<Chord<K, N, L>>,
+    // what was typed, including text which isn't the output of any chord (stored as bare chords otherwise, as before
+    // invalid input was kept)
+    pub typed: Vec<TypedChord<K, N, L>>,
+    // from when the prompt was shown to when Enter was pressed
+    pub time: f64,
+    // from when the prompt was shown to when each typed chord was read.
+    // logs from before the chords were timed don't have them, so only the time for the whole line is known
+    #[serde(default)]
+    pub typed_times: Option<Vec<f64>>,
+    pub errors: Vec<TypingError<TypedChord<K, N, L>>>,
+}
+
+// one switch from a chord to the next within a line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transition<K: Key, const N: usize, L: Layout<K, N>> {
+    pub chord_pair: [Chord<K, N, L>; 2],
+    // the time between typing the chords, or None if one of them wasn't typed at all.
+    // for lines without typed_times it's the mean time between the chords typed in the line
+    pub time: Option<f64>,
+    // whether both chords were typed correctly
+    pub correct: bool,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> PracticeLine<K, N, L> {
+    pub fn new(expected: Vec<Chord<K, N, L>>, typed: Vec<TypedChord<K, N, L>>, typed_times: Option<Vec<f64>>, time: f64) -> Self {
+        let errors = classify_errors(&traceback(&as_typed(&expected), &typed));
+        Self { expected, typed, time, typed_times, errors }
+    }
+
+    pub fn transitions(&self) -> Vec<Transition<K, N, L>> {
+        // the transitions between consecutive expected chords, timed from the chords typed for them (rightly or wrongly), so
+        // that the time spent reading the prompt before the first chord isn't counted. without typed_times, the line's time
+        // is spread over the switches between the typed chords instead
+        let even_time = self.time / self.typed.len().saturating_sub(1).max(1) as f64;
+        // for each expected chord, whether it was typed correctly and the index of the chord typed for it
+        let mut aligned: Vec<(bool, Option<usize>)> = Vec::with_capacity(self.expected.len());
+        let mut j = 0;
+        for pair in traceback(&as_typed(&self.expected), &self.typed) {
+            match pair {
+                AlignedPair::Match(_) => {
+                    aligned.push((true, Some(j)));
+                    j += 1;
+                },
+                AlignedPair::Substitution { .. } => {
+                    aligned.push((false, Some(j)));
+                    j += 1;
+                },
+                AlignedPair::Missed(_) => aligned.push((false, None)),
+                AlignedPair::Inserted(_) => j += 1,
+            }
+        }
+        self.expected.windows(2).zip(aligned.windows(2)).map(|(chords, aligned)| {
+            let time = match (&self.typed_times, aligned[0].1, aligned[1].1) {
+                (None, _, _) => Some(even_time),
+                (Some(times), Some(first), Some(second)) => times.get(second).zip(times.get(first)).map(|(second, first)| second - first),
+                (Some(_), _, _) => None,
+            };
+            Transition { chord_pair: [chords[0].clone(), chords[1].clone()], time, correct: aligned[0].0 && aligned[1].0 }
+        }).collect()
+    }
+}
+
+// the lines typed in a practice session, saved alongside the results of the pair trials
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct PracticeLog<K: Key, const N: usize, L: Layout<K, N>> {
+    pub lines: Vec<PracticeLine<K, N, L>>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default)]
+    pub participant: Option<String>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> PracticeLog<K, N, L> {
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(serde_json::to_writer(std::fs::File::create(path)?, self)?)
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+}
+
+// the transitions between an ordered pair of chords, over all the practice logs
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransitionSummary<K: Key, const N: usize, L: Layout<K, N>> {
+    pub chord_pair: [Chord<K, N, L>; 2],
+    pub n_transitions: usize,
+    // the number of transitions with a time, which mean_time is over (it's 0 if there are none)
+    pub n_timed: usize,
+    pub mean_time: f64,
+    // the fraction of the transitions in which both chords were typed correctly
+    pub accuracy: f64,
+}
+
+pub fn summarize_transitions<K: Key, const N: usize, L: Layout<K, N>>(logs: &[PracticeLog<K, N, L>]) -> Vec<TransitionSummary<K, N, L>> {
+    // in the order the pairs were first seen
+    let mut summaries: Vec<TransitionSummary<K, N, L>> = Vec::new();
+    for transition in logs.iter().flat_map(|log| &log.lines).flat_map(|line| line.transitions()) {
+        let summary = match summaries.iter_mut().find(|s| s.chord_pair == transition.chord_pair) {
+            Some(summary) => summary,
+            None => {
+                summaries.push(TransitionSummary { chord_pair: transition.chord_pair.clone(), n_transitions: 0, n_timed: 0, mean_time: 0.0, accuracy: 0.0 });
+                // this unwrap is safe because we just pushed an element
+                summaries.last_mut().unwrap()
+            },
+        };
+        // running means
+        summary.n_transitions += 1;
+        summary.accuracy += (if transition.correct { 1.0 } else { 0.0 } - summary.accuracy) / summary.n_transitions as f64;
+        if let Some(time) = transition.time {
+            summary.n_timed += 1;
+            summary.mean_time += (time - summary.mean_time) / summary.n_timed as f64;
+        }
+    }
+    summaries
+}
+
+pub fn prompts_from_corpus(text: &str, segmenter: &Segmenter, max_outputs: usize) -> Vec<Vec<usize>> {
+    // the prompts, as the indices of their outputs. lines which are too long are split at the ends of sentences, and then
+    // between words. prompts with characters which aren't outputs are left out, since they can't be typed
+    let segment = |s: &str| -> Option<Vec<usize>> { segmenter.segment(&s.chars().collect::<Vec<char>>()).into_iter().collect() };
+    let mut prompts = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let mut pieces = vec![line.to_string()];
+        if segment(line).is_some_and(|outputs| outputs.len() > max_outputs) {
+            pieces = line.split_inclusive(['.', '!', '?']).map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+        }
+        for piece in pieces {
+            let Some(outputs) = segment(&piece) else { continue };
+            if outputs.len() <= max_outputs {
+                prompts.push(outputs);
+                continue;
+            }
+            // runs of words which fit
+            let mut run = String::new();
+            for word in piece.split(' ') {
+                let candidate = if run.is_empty() { word.to_string() } else { format!("{} {}", run, word) };
+                match segment(&candidate) {
+                    Some(outputs) if outputs.len() > max_outputs && !run.is_empty() => {
+                        prompts.extend(segment(&run));
+                        run = word.to_string();
+                    },
+                    _ => run = candidate,
+                }
+            }
+            prompts.extend(segment(&run).filter(|outputs| outputs.len() <= max_outputs));
+        }
+    }
+    prompts.retain(|prompt| !prompt.is_empty());
+    prompts
+}
+
+pub fn score_typing(expected: &[usize], typed: &[DecodedToken]) -> Vec<bool> {
+    // whether each expected output was typed correctly, from the alignment of what was typed with the expected outputs.
+    // invalid text never matches, so in place of an output it's a mistake
+    let expected_tokens: Vec<DecodedToken> = expected.iter().map(|&idx| DecodedToken::Output(idx)).collect();
+    let mut correct = Vec::with_capacity(expected.len());
+    for pair in traceback(&expected_tokens, typed) {
+        match pair {
+            AlignedPair::Match(_) => correct.push(true),
+            AlignedPair::Substitution { .. } | AlignedPair::Missed(_) => correct.push(false),
+            AlignedPair::Inserted(_) => {},
+        }
+    }
+    correct
+}
+
+// while this is alive, the terminal hands over each character as it's typed rather than a whole line when Enter is pressed,
+// so that the chords can be decoded and timed as they're typed. this uses stty, so it only works on unix-like systems;
+// elsewhere, or when the input isn't a terminal, the characters arrive a line at a time as usual, and the chords can't be timed
+struct CharacterMode {
+    enabled: bool,
+}
+
+impl CharacterMode {
+    fn enable() -> Self {
+        Self { enabled: stty(&["-icanon", "min", "1", "time", "0"]) }
+    }
+}
+
+impl Drop for CharacterMode {
+    fn drop(&mut self) {
+        if self.enabled {
+            stty(&["icanon"]);
+        }
+    }
+}
+
+fn stty(args: &[&str]) -> bool {
+    // whether the terminal settings were changed
+    std::process::Command::new("stty").args(args).stdin(std::process::Stdio::inherit()).stderr(std::process::Stdio::null())
+        .status().is_ok_and(|status| status.success())
+}
+
+// the alignment counts chords in a u8, so very long inputs are cut short (and prompts can't be longer)
+pub const MAX_TYPED_OUTPUTS: usize = 200;
+
+fn choose_prompt<'a, K: Key, const N: usize, L: Layout<K, N>>(prompts: &'a [Vec<usize>], progress: &PracticeProgress<K, N, L>, config: &PracticeConfig, rng: &mut StdRng) -> &'a [usize] {
+    // the most valuable of some random prompts (ties are broken by the order they were drawn in)
+    let candidates: Vec<&Vec<usize>> = prompts.choose_multiple(rng, config.n_prompt_candidates.max(1)).collect();
+    // this unwrap is safe because there is at least one prompt
+    candidates.into_iter().rev().max_by(|a, b| progress.prompt_priority(a).total_cmp(&progress.prompt_priority(b))).unwrap()
+}
+
+pub fn practice<K: Key, const N: usize, L: Layout<K, N>>(vocab: &[(Chord<K, N, L>, String)], text: &str, progress: &mut PracticeProgress<K, N, L>, log: &mut PracticeLog<K, N, L>, config: &PracticeConfig, seed: u64) -> Result<SessionSummary, Box<dyn std::error::Error>> {
+    // an interactive session, which lasts until the learner quits. progress should be for vocab (see PracticeProgress::for_vocab).
+    // the lines typed are added to log
+    config.check()?;
+    let rng = &mut StdRng::seed_from_u64(seed);
+    let outputs: Vec<String> = vocab.iter().map(|(_, output)| output.clone()).collect();
+    let segmenter = Segmenter::new(&outputs);
+    let prompts = prompts_from_corpus(text, &segmenter, config.max_prompt_outputs);
+    let mut decoder = StreamDecoder::new(outputs.iter().map(String::as_str));
+    if prompts.is_empty() {
+        return Err("no line of the corpus can be typed with this layout".into());
+    }
+    let character_mode = CharacterMode::enable();
+    let mut summary = SessionSummary { n_prompts: 0, n_outputs: 0, n_errors: 0, n_characters: 0, seconds: 0.0 };
+    println!("type each line as it's shown, using the chords drawn under it, and hit Enter when you're done. the bell rings when a chord isn't the next one in the line. type QUIT to stop.");
+
+    loop {
+        let prompt = choose_prompt(&prompts, progress, config, rng);
+        let prompt_text: String = prompt.iter().map(|&idx| outputs[idx].as_str()).collect();
+        println!();
+        println!("{}", prompt_text);
+        let mut shown = Vec::new();
+        for &idx in prompt {
+            if !shown.contains(&idx) && progress.chords[idx].level < config.hint_level {
+                shown.push(idx);
+                println!("{:?}: {}", outputs[idx], vocab[idx].0);
+                println!("{}", GraphicalChord { chord: &vocab[idx].0 });
+            }
+        }
+
+        // the chords are decoded as they're typed, and the bell rings for one which isn't the next in the prompt (counting
+        // from the chords typed so far, so after a missed or extra chord it keeps ringing until the line is typed again).
+        // text which isn't made of outputs of the layout is kept in its place, as a mistake. a chord which is erased is taken
+        // back, along with its time
+        let (mut typed, mut typed_times): (Vec<DecodedToken>, Vec<f64>) = (Vec::new(), Vec::new());
+        let start_time = std::time::Instant::now();
+        let line = decoder.decode_line(&mut std::io::stdin().lock(), |event| match event {
+            LineEvent::Token(token) => {
+                if !matches!(token, DecodedToken::Output(idx) if prompt.get(typed.len()) == Some(&idx)) {
+                    print!("\x07");
+                    let _ = std::io::stdout().flush();
+                }
+                typed.push(token);
+                typed_times.push(start_time.elapsed().as_secs_f64());
+            },
+            LineEvent::Erased(_) => {
+                typed.pop();
+                typed_times.pop();
+            },
+        })?;
+        let seconds = start_time.elapsed().as_secs_f64();
+        // the end of the input (e.g. when it's piped in) ends the session like QUIT
+        let Some(input) = line.filter(|line| line != "QUIT") else { break };
+        for token in &typed {
+            if let DecodedToken::Invalid(text) = token {
+                println!("{:?} isn't typed by any chord of the layout", text);
+            }
+        }
+        typed.truncate(MAX_TYPED_OUTPUTS);
+        typed_times.truncate(MAX_TYPED_OUTPUTS);
+        let correct = score_typing(prompt, &typed);
+        progress.record(prompt, &correct);
+        let typed_chords = typed.into_iter().map(|token| match token {
+            DecodedToken::Output(idx) => TypedChord::Chord(vocab[idx].0.clone()),
+            DecodedToken::Invalid(invalid) => TypedChord::Invalid { invalid },
+        }).collect();
+        let expected_chords = prompt.iter().map(|&idx| vocab[idx].0.clone()).collect();
+        log.lines.push(PracticeLine::new(expected_chords, typed_chords, Some(typed_times).filter(|_| character_mode.enabled), seconds));
+
+        let n_errors = correct.iter().filter(|c| !**c).count();
+        summary.n_prompts += 1;
+        summary.n_outputs += prompt.len();
+        summary.n_errors += n_errors;
+        summary.n_characters += input.chars().count();
+        summary.seconds += seconds;
+        println!("accuracy: {:.2}; speed: {:.1} wpm (this session: {:.2}, {:.1} wpm)",
+                 1.0 - n_errors as f64 / prompt.len() as f64, words_per_minute(input.chars().count(), seconds), summary.accuracy(), summary.wpm());
+        for (&idx, _) in prompt.iter().zip(&correct).filter(|(_, c)| !**c) {
+            println!("missed {:?}: {}", outputs[idx], vocab[idx].0);
+        }
+    }
+
+    println!("{} prompts, accuracy {:.2}, {:.1} wpm", summary.n_prompts, summary.accuracy(), summary.wpm());
+    for weak in progress.weakest(5) {
+        println!("{:?} ({}): {} mistakes in {} attempts", weak.output, weak.chord, weak.errors, weak.attempts);
+    }
+    progress.sessions.push(summary.clone());
+    Ok(summary)
+}
+
+pub fn run<K: Key, const N: usize, L: Layout<K, N>>(vocab: &[(Chord<K, N, L>, String)], corpus_path: &str, progress_path: &str, participant: Option<&str>, store: &DataStore, config: &PracticeConfig, seed: u64) -> Result<SessionSummary, Box<dyn std::error::Error>> {
+    // the progress is read from progress_path (if it exists) and written back to it after the session. the lines typed are
+    // written to a timestamped practice log in the store's results, where training finds them
+    let text = std::fs::read_to_string(corpus_path)?;
+    let previous = if std::path::Path::new(progress_path).exists() { Some(PracticeProgress::load(progress_path)?) } else { None };
+    let mut progress = PracticeProgress::for_vocab(vocab, previous);
+    let mut log = PracticeLog { lines: Vec::new(), seed: Some(seed), participant: participant.map(str::to_string) };
+    let summary = practice(vocab, &text, &mut progress, &mut log, config, seed)?;
+    // the log is the training data, so it's written first, and a failure to write the progress doesn't lose it
+    if !log.lines.is_empty() {
+        let log_path = store.new_path(Artifact::Results, "practice_log", "json")?;
+        log.save(&log_path)?;
+        println!("saved the lines typed to {}", log_path);
+    }
+    progress.save(progress_path)?;
+    Ok(summary)
+}
@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::path::Path;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+// a session package is a directory holding everything needed to run the data collection game with one keyboard config:
+// the binary config to load on the keyboard, the decoder for its output, and a manifest recording where they came from
+// and the checksums of the files, so that a package which was changed or mixed up with another can be caught.
+// gather_chords accepts a package in place of a decoder file, and records the package's id with the results
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const CONFIG_FILE: &str = "config.cfg";
+pub const DECODER_FILE: &str = "decoder.json";
+
+// how the chords of a session were chosen: the sampler, and the model and training settings it used (if any)
+#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(Serialize, Deserialize)]
+pub struct Provenance {
+    pub sampler: String,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub train_config: Option<serde_json::Value>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+pub struct PackagedFile {
+    pub name: String,
+    // hex encoded sha256 of the file's contents
+    pub sha256: String,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+pub struct SessionManifest {
+    pub id: String,
+    // seconds since the unix epoch
+    pub created: u64,
+    // the seed the config and decoder were generated from
+    pub seed: u64,
+    pub provenance: Provenance,
+    pub files: Vec<PackagedFile>,
+}
+
+pub fn sha256_hex(contents: &[u8]) -> String {
+    Sha256::digest(contents).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn is_package(path: &str) -> bool {
+    Path::new(path).join(MANIFEST_FILE).is_file()
+}
+
+pub fn write_package<C: Serialize>(path: &str, config: &[u8], decoder: &C, seed: u64, provenance: Provenance) -> Result<SessionManifest, Box<dyn Error>> {
+    // writes a package to the directory at path, which is created if needed (but shouldn't already hold a package)
+    if is_package(path) {
+        return Err(format!("{} already holds a session package", path).into());
+    }
+    std::fs::create_dir_all(path)?;
+    let decoder = serde_json::to_vec(decoder)?;
+    let mut files = Vec::new();
+    for (name, contents) in [(CONFIG_FILE, config), (DECODER_FILE, decoder.as_slice())] {
+        std::fs::write(Path::new(path).join(name), contents)?;
+        files.push(PackagedFile { name: name.to_string(), sha256: sha256_hex(contents) });
+    }
+    let created = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    // the checksum of the contents tells apart packages made at the same time with the same seed (e.g. by different samplers)
+    let id = format!("{}_{}", created, &sha256_hex(&[config, decoder.as_slice()].concat())[..12]);
+    let manifest = SessionManifest { id, created, seed, provenance, files };
+    std::fs::write(Path::new(path).join(MANIFEST_FILE), serde_json::to_string_pretty(&manifest)?)?;
+    Ok(manifest)
+}
+
+pub fn open_package(path: &str) -> Result<SessionManifest, Box<dyn Error>> {
+    // reads the manifest of the package at path, and checks that its files are all there and unchanged
+    let manifest: SessionManifest = serde_json::from_reader(std::fs::File::open(Path::new(path).join(MANIFEST_FILE))?)?;
+    for file in &manifest.files {
+        let contents = std::fs::read(Path::new(path).join(&file.name)).map_err(|e| format!("can't read {} from the package {}: {}", file.name, path, e))?;
+        if sha256_hex(&contents) != file.sha256 {
+            return Err(format!("{} in the package {} doesn't match its checksum; it was changed after the package was made", file.name, path).into());
+        }
+    }
+    Ok(manifest)
+}
+
+pub fn load_decoder<C: DeserializeOwned>(path: &str) -> Result<(C, Option<SessionManifest>), Box<dyn Error>> {
+    // path is either a package, whose decoder is loaded once the package is checked, or a decoder file on its own
+    if is_package(path) {
+        let manifest = open_package(path)?;
+        let decoder = serde_json::from_reader(std::fs::File::open(Path::new(path).join(DECODER_FILE))?)?;
+        Ok((decoder, Some(manifest)))
+    } else {
+        Ok((serde_json::from_reader(std::fs::File::open(path)?)?, None))
+    }
+}
@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+
+// where the tools keep the files they read and write. the root directory has a subdirectory for each kind of artifact, and
+// new files are given timestamped names in them. the root is chosen at runtime (see DataStore::locate), so the tools find
+// the same data whichever directory they're run from.
+// before the store had subdirectories, everything was kept in the root; files there are still found by their names
+
+// the environment variable which can set the root
+pub const DATA_DIR_ENV: &str = "KEYMAP_DATA_DIR";
+// a json file which can set the root, found in the working directory or one of its parents: {"data_dir": "<path>"}, where a
+// relative path is relative to the file's directory
+pub const STORE_CONFIG_FILE: &str = "keymap.json";
+// the root used if nothing else sets it, relative to the working directory
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display, strum_macros::EnumString, strum_macros::IntoStaticStr, strum_macros::VariantArray)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Artifact {
+    // keyboard configs for designed layouts, and the layouts they're made from
+    Config,
+    // session packages, and the decoder files written before packages
+    Decoder,
+    // the results of the data collection game and the logs of practice sessions, which training reads, and the learner's
+    // progress in practice
+    Results,
+    // checkpoints of trained models
+    Model,
+    // catalogs, leaderboards and other output meant for reading
+    Report,
+}
+
+impl Artifact {
+    pub fn names() -> Vec<&'static str> {
+        <Self as strum::VariantArray>::VARIANTS.iter().map(|kind| kind.into()).collect()
+    }
+
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Artifact::Config => "configs",
+            Artifact::Decoder => "decoders",
+            Artifact::Results => "results",
+            Artifact::Model => "models",
+            Artifact::Report => "reports",
+        }
+    }
+
+    pub fn prefixes(self) -> &'static [&'static str] {
+        // the names the files of this kind start with, which are used to find them in the root
+        match self {
+            Artifact::Config => &["config_", "layout_"],
+            Artifact::Decoder => &["session_", "decoder_"],
+            Artifact::Results => &["chord_preferences_results", "practice_log", "practice_progress"],
+            Artifact::Model => &["model_"],
+            Artifact::Report => &["catalog_", "hyperparameter_leaderboard_"],
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct StoreConfig {
+    data_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataStore {
+    root: PathBuf,
+}
+
+pub fn resolve_root(data_dir: Option<&str>, env_data_dir: Option<&str>, working_dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    // the root given directly (e.g. on the command line), or else by the environment, or else by the nearest store config
+    // file, or else the default
+    if let Some(data_dir) = data_dir.or(env_data_dir) {
+        return Ok(PathBuf::from(data_dir));
+    }
+    for dir in working_dir.ancestors() {
+        let config_path = dir.join(STORE_CONFIG_FILE);
+        if config_path.is_file() {
+            let config: StoreConfig = serde_json::from_reader(std::fs::File::open(&config_path)?)
+                .map_err(|e| format!("can't read {}: {}", config_path.display(), e))?;
+            if let Some(data_dir) = config.data_dir {
+                return Ok(dir.join(data_dir));
+            }
+        }
+    }
+    Ok(working_dir.join(DEFAULT_DATA_DIR))
+}
+
+impl DataStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DataStore { root: root.into() }
+    }
+
+    pub fn locate(data_dir: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        // see resolve_root
+        let env_data_dir = std::env::var(DATA_DIR_ENV).ok();
+        Ok(DataStore::new(resolve_root(data_dir, env_data_dir.as_deref(), &std::env::current_dir()?)?))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn dir(&self, kind: Artifact) -> PathBuf {
+        self.root.join(kind.dir_name())
+    }
+
+    pub fn new_path(&self, kind: Artifact, prefix: &str, extension: &str) -> std::io::Result<String> {
+        // a path for a new file (or directory, if extension is empty) of this kind, named with the prefix and the time.
+        // the subdirectory is created if needed
+        let dir = self.dir(kind);
+        std::fs::create_dir_all(&dir)?;
+        let current_time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let name = if extension.is_empty() { format!("{}_{}", prefix, current_time) } else { format!("{}_{}.{}", prefix, current_time, extension) };
+        Ok(dir.join(name).to_string_lossy().to_string())
+    }
+
+    pub fn named_path(&self, kind: Artifact, name: &str) -> std::io::Result<String> {
+        // the path of a file of this kind with a fixed name, such as one carried over from session to session: in the
+        // subdirectory, unless it's only in the root from before there were subdirectories. the subdirectory is created if needed
+        let dir = self.dir(kind);
+        let in_root = self.root.join(name);
+        if !dir.join(name).exists() && in_root.exists() {
+            return Ok(in_root.to_string_lossy().to_string());
+        }
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir.join(name).to_string_lossy().to_string())
+    }
+
+    pub fn list(&self, kind: Artifact) -> std::io::Result<Vec<PathBuf>> {
+        // everything in the subdirectory, and the files in the root named like this kind, sorted by name
+        let mut paths = Vec::new();
+        for (dir, in_subdir) in [(self.dir(kind), true), (self.root.clone(), false)] {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if in_subdir || kind.prefixes().iter().any(|prefix| name.starts_with(prefix)) {
+                    paths.push(entry.path());
+                }
+            }
+        }
+        paths.sort_by_key(|path| path.file_name().map(|name| name.to_os_string()));
+        Ok(paths)
+    }
+
+    pub fn list_matching(&self, kind: Artifact, prefix: &str, extension: &str) -> std::io::Result<Vec<PathBuf>> {
+        // the artifacts of this kind whose names start with prefix and end with extension (which may be empty)
+        Ok(self.list(kind)?.into_iter().filter(|path| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            name.starts_with(prefix) && name.ends_with(extension)
+        }).collect())
+    }
+
+    pub fn latest(&self, kind: Artifact, prefix: &str) -> std::io::Result<Option<PathBuf>> {
+        // the most recently modified artifact of this kind whose name starts with prefix
+        let mut latest: Option<(std::time::SystemTime, PathBuf)> = None;
+        for path in self.list_matching(kind, prefix, "")? {
+            let modified = std::fs::metadata(&path)?.modified()?;
+            if latest.as_ref().is_none_or(|(time, _)| modified >= *time) {
+                latest = Some((modified, path));
+            }
+        }
+        Ok(latest.map(|(_, path)| path))
+    }
+
+    pub fn find(&self, kind: Artifact, name: &str) -> Option<PathBuf> {
+        // looks up an artifact given by a path, or by its name in the subdirectory or the root
+        [PathBuf::from(name), self.dir(kind).join(name), self.root.join(name)].into_iter().find(|path| path.exists())
+    }
+}
@@ -1,4 +1,6 @@
 use core::fmt;
+use core::hash::{Hash, Hasher};
+use std::collections::{HashMap, HashSet};
 use strum::{EnumCount, VariantArray};
 use std::marker::PhantomData;
 use std::error::Error;
@@ -9,61 +11,215 @@ use serde::{Serialize, Deserialize, de::DeserializeOwned};
 pub trait Key: Sized + fmt::Display + PartialEq + Copy + EnumCount + VariantArray + fmt::Debug + Serialize + DeserializeOwned
 {
     fn gen_random<R: rand::Rng>(rng: &mut R) -> Self;
+    // the position of the key in VARIANTS, which is also its bit in a chord. this searches VARIANTS; keyboards whose keys are
+    // a plain enum listed in the same order can return the discriminant instead
+    fn index(self) -> usize {
+        Self::VARIANTS.iter().position(|x| *x == self).unwrap()
+    }
+}
+
+// the finger which presses a key. this is only used by hand-tuned models which need to know about the physical layout
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(strum_macros::EnumCount)]
+pub enum Finger {
+    Thumb,
+    Index,
+    Middle,
+    Ring,
+    Pinky,
 }
 
 pub trait Layout<K: Key, const N: usize>: Sized + Serialize + DeserializeOwned + fmt::Debug + Clone + PartialEq {
     fn fmt_chord_graphical(chord: &Chord<K, N, Self>, f: &mut fmt::Formatter) -> fmt::Result;
     fn fmt_chord_text(chord: &Chord<K, N, Self>, f: &mut fmt::Formatter) -> fmt::Result;
     fn is_valid(chord: &Chord<K, N, Self>) -> bool;
+    fn finger(key: K) -> Finger;
 }
 
 // a combination of keys pressed simultaneously
-#[derive(PartialEq, Clone)]
+#[derive(Clone)]
 #[derive(Serialize, Deserialize)]
-#[derive(Debug)]
-// N is the number of distinct keys that there are, i.e. Key::COUNT (which can't be used here since it's a generic)
+#[serde(from = "StoredChord<N>", into = "StoredChord<N>", bound = "")]
+// N is the number of distinct keys that there are, i.e. Key::COUNT (which can't be used here since it's a generic).
+// the keys are stored as a bitmask, bit i being set if the chord contains K::VARIANTS[i], so there can be at most 32 of them
 pub struct Chord<K: Key, const N: usize, L: Layout<K, N>> {
-    #[serde(with = "serde_arrays")]
-    keys: [bool; N],
-    #[serde(skip)]
+    keys: u32,
     _marker0: PhantomData<K>,
-    #[serde(skip)]
     _marker1: PhantomData<L>,
 }
 
+// the serialized form of a chord, which is an array of whether it contains each key (as it was before chords were bitmasks)
+#[derive(Serialize, Deserialize)]
+struct StoredChord<const N: usize> {
+    #[serde(with = "serde_arrays")]
+    keys: [bool; N],
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> From<StoredChord<N>> for Chord<K, N, L> {
+    fn from(stored: StoredChord<N>) -> Self {
+        Self::from_bits(stored.keys.iter().enumerate().filter(|(_, &pressed)| pressed).map(|(i, _)| 1u32 << i).sum())
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> From<Chord<K, N, L>> for StoredChord<N> {
+    fn from(chord: Chord<K, N, L>) -> Self {
+        Self { keys: core::array::from_fn(|i| chord.keys & (1 << i) != 0) }
+    }
+}
+
+// these are implemented by hand because deriving them would require K and L to implement them too
+impl<K: Key, const N: usize, L: Layout<K, N>> PartialEq for Chord<K, N, L> {
+    fn eq(&self, other: &Self) -> bool {
+        self.keys == other.keys
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> Eq for Chord<K, N, L> {}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> Hash for Chord<K, N, L> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.keys.hash(state);
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> FromIterator<K> for Chord<K, N, L> {
+    fn from_iter<I: IntoIterator<Item = K>>(keys: I) -> Self {
+        let mut chord = Self::new();
+        for key in keys {
+            chord.add_key(key);
+        }
+        chord
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> fmt::Debug for Chord<K, N, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Chord").field("keys", &self.to_vector()).finish()
+    }
+}
+
 impl<K: Key, const N: usize, L: Layout<K, N>> Chord<K, N, L> {
     pub fn new() -> Self {
+        Self::from_bits(0)
+    }
+
+    pub fn from_bits(keys: u32) -> Self {
+        // keys is a bitmask as returned by bits. bits for keys past the Nth are ignored
+        const { assert!(N <= 32, "chords can have at most 32 distinct keys") };
+        let mask = if N == 32 { u32::MAX } else { (1 << N) - 1 };
         Self {
-            keys: [false; N],
+            keys: keys & mask,
             _marker0: PhantomData,
             _marker1: PhantomData,
         }
     }
 
-    fn index(&self, key: K) -> usize {
-        K::VARIANTS.iter().position(|x| *x == key).unwrap()
+    pub fn bits(&self) -> u32 {
+        self.keys
+    }
+
+    fn bit(key: K) -> u32 {
+        1 << key.index()
     }
 
     pub fn contains(&self, key: K) -> bool {
-        self.keys[self.index(key)]
+        self.keys & Self::bit(key) != 0
     }
 
     pub fn add_key(&mut self, key: K) {
-        self.keys[self.index(key)] = true;
+        self.keys |= Self::bit(key);
+    }
+
+    pub fn remove_key(&mut self, key: K) {
+        self.keys &= !Self::bit(key);
     }
 
     pub fn n_keys(&self) -> usize {
-        self.keys.iter().filter(|&&x| x).count()
+        self.keys.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys == 0
+    }
+
+    // the keys in the chord, in the order of K::VARIANTS
+    pub fn keys(&self) -> impl Iterator<Item = K> + '_ {
+        (0..N).filter(|i| self.keys & (1 << i) != 0).map(|i| K::VARIANTS[i])
+    }
+
+    // the set operations, treating a chord as the set of its keys
+    pub fn union(&self, other: &Self) -> Self {
+        Self::from_bits(self.keys | other.keys)
+    }
+
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self::from_bits(self.keys & other.keys)
+    }
+
+    // the keys in self but not in other
+    pub fn difference(&self, other: &Self) -> Self {
+        Self::from_bits(self.keys & !other.keys)
+    }
+
+    // the keys in exactly one of the chords
+    pub fn symmetric_difference(&self, other: &Self) -> Self {
+        Self::from_bits(self.keys ^ other.keys)
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.keys & !other.keys == 0
+    }
+
+    pub fn is_superset(&self, other: &Self) -> bool {
+        other.is_subset(self)
     }
 
     pub fn to_vector(&self) -> Vec<bool> {
-        self.keys.to_vec()
+        (0..N).map(|i| self.keys & (1 << i) != 0).collect()
     }
 
     // allow direct editing of the private field .keys in the unit tests
     #[cfg(test)]
-    pub(crate) fn get_raw_keys(&mut self) -> &mut [bool] {
-        &mut self.keys
+    pub(crate) fn toggle_raw_key(&mut self, idx: usize) {
+        self.keys ^= 1 << idx;
+    }
+}
+
+// what was read from typed text: a chord, or text which isn't the output of any chord, kept as it was typed. a chord is
+// stored just like a bare chord, so results from before invalid input was recorded read as chords
+#[derive(Clone, PartialEq, Debug)]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged, bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub enum TypedChord<K: Key, const N: usize, L: Layout<K, N>> {
+    Chord(Chord<K, N, L>),
+    Invalid { invalid: String },
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> TypedChord<K, N, L> {
+    pub fn chord(&self) -> Option<&Chord<K, N, L>> {
+        match self {
+            TypedChord::Chord(chord) => Some(chord),
+            TypedChord::Invalid { .. } => None,
+        }
+    }
+
+    pub fn is_invalid_input(&self) -> bool {
+        self.chord().is_none()
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> From<Chord<K, N, L>> for TypedChord<K, N, L> {
+    fn from(chord: Chord<K, N, L>) -> Self {
+        TypedChord::Chord(chord)
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> fmt::Display for TypedChord<K, N, L> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TypedChord::Chord(chord) => write!(f, "{}", chord),
+            TypedChord::Invalid { invalid } => write!(f, "?{:?}", invalid),
+        }
     }
 }
 
@@ -95,18 +251,298 @@ fn _display_chord_sequence<K: Key, const N: usize, L: Layout<K, N>>(chords: &Vec
 pub trait ChordSampler<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, I> where Self: Sized {
     fn new(rng: R, info: &I) -> Result<Self, Box<dyn Error>>;  // I is the initialization info
     fn sample_chord(&mut self) -> Chord<K, N, L>;  // this need not be uniform. there may be multiple samplers for the same type of chord
+    // sample n distinct chords. by default this rejection samples, which can be very slow if n is large compared to the
+    // number of chords the sampler is likely to produce; samplers which choose from a finite set of candidates should
+    // override it to sample without replacement (e.g. using sample_without_replacement)
+    fn sample_distinct_chords(&mut self, n: usize) -> Result<Vec<Chord<K, N, L>>, Box<dyn Error>> {
+        let mut chords = Vec::new();
+        while chords.len() < n {
+            let chord = self.sample_chord();
+            if !chords.contains(&chord) {
+                chords.push(chord);
+            }
+        }
+        Ok(chords)
+    }
+}
+
+pub fn sample_without_replacement<T: Clone, R: rand::Rng>(rng: &mut R, weighted_items: &[(T, f64)], n: usize) -> Result<Vec<T>, Box<dyn Error>> {
+    // sample n distinct items, each with probability proportional to its (nonnegative) weight among the items not yet chosen.
+    // rather than drawing the items one at a time, we use the equivalent method of efraimidis and spirakis:
+    // give each item the key ln(u)/weight for u uniform in [0, 1), and take the n items with the largest keys.
+    // items with weight 0 get a key of -inf, so they are only chosen if there aren't enough items with positive weight.
+    if weighted_items.len() < n {
+        return Err(format!("cannot sample {} distinct items from only {} candidates", n, weighted_items.len()).into());
+    }
+    // a negative or nan weight would give a key above all the others, so that item would always be chosen first
+    if let Some((_, weight)) = weighted_items.iter().find(|(_, weight)| !weight.is_finite() || *weight < 0.0) {
+        return Err(format!("cannot sample with weight {}: weights must be finite and nonnegative", weight).into());
+    }
+    let mut keyed_items: Vec<(f64, &T)> = weighted_items.iter()
+                                                        .map(|(item, weight)| (rng.gen::<f64>().ln() / weight, item))
+                                                        .collect();
+    keyed_items.sort_by(|(key1, _), (key2, _)| key2.total_cmp(key1));
+    Ok(keyed_items.into_iter().take(n).map(|(_, item)| item.clone()).collect())
+}
+
+// the vocabulary hashed both ways, for looking up chords and outputs without scanning it. if a chord or an output appears
+// more than once, the first occurrence is the one found
+#[derive(Debug, Clone)]
+pub struct VocabIndex<K: Key, const N: usize, L: Layout<K, N>> {
+    by_chord: HashMap<Chord<K, N, L>, usize>,
+    by_string: HashMap<String, usize>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> VocabIndex<K, N, L> {
+    pub fn new(vocab: &[(Chord<K, N, L>, String)]) -> Self {
+        let mut by_chord = HashMap::with_capacity(vocab.len());
+        let mut by_string = HashMap::with_capacity(vocab.len());
+        for (idx, (chord, string)) in vocab.iter().enumerate() {
+            by_chord.entry(chord.clone()).or_insert(idx);
+            by_string.entry(string.clone()).or_insert(idx);
+        }
+        Self { by_chord, by_string }
+    }
+
+    // the position in the vocabulary
+    pub fn chord_index(&self, chord: &Chord<K, N, L>) -> Option<usize> {
+        self.by_chord.get(chord).copied()
+    }
+
+    pub fn string_index(&self, string: &str) -> Option<usize> {
+        self.by_string.get(string).copied()
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> Default for VocabIndex<K, N, L> {
+    fn default() -> Self {
+        Self { by_chord: HashMap::new(), by_string: HashMap::new() }
+    }
+}
+
+// what a stream decoder has read: the output at some index of the vocabulary, or text which isn't any output
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodedToken {
+    Output(usize),
+    Invalid(String),
+}
+
+// what decode_line reports as a line is typed: a token as soon as it's decoded, or a token reported earlier in the line which
+// was then erased with a backspace
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineEvent {
+    Token(DecodedToken),
+    Erased(DecodedToken),
+}
+
+// decodes the text produced by typing chords a character at a time, so that each chord can be reported as soon as it's
+// complete. the outputs are matched greedily, longest first; for a prefix-free vocabulary (as the trial decoders use) that
+// means each output is read as soon as its last character arrives, and text which can't start any output is reported as
+// soon as it's typed. otherwise an output which is the start of a longer one is only read once the next character rules
+// the longer one out (or at finish).
+// after invalid text the decoder resynchronizes: it drops the first character of what it couldn't read and decodes the rest
+// again, so a stray character only spoils itself. consecutive invalid characters found together make one Invalid token
+#[derive(Debug, Clone)]
+pub struct StreamDecoder {
+    // the first index of each output
+    outputs: HashMap<String, usize>,
+    // the nonempty proper prefixes of the outputs
+    prefixes: HashSet<String>,
+    // the length in bytes of each output
+    lengths: Vec<usize>,
+    pending: String,
+    // the longest output which pending starts with, as (its length in bytes, its index)
+    matched: Option<(usize, usize)>,
+}
+
+impl StreamDecoder {
+    pub fn new<'a, I: IntoIterator<Item = &'a str>>(outputs: I) -> Self {
+        let mut by_output = HashMap::new();
+        let mut prefixes = HashSet::new();
+        let mut lengths = Vec::new();
+        for (idx, output) in outputs.into_iter().enumerate() {
+            by_output.entry(output.to_string()).or_insert(idx);
+            prefixes.extend(output.char_indices().skip(1).map(|(end, _)| output[..end].to_string()));
+            lengths.push(output.len());
+        }
+        Self { outputs: by_output, prefixes, lengths, pending: String::new(), matched: None }
+    }
+
+    // the characters read since the last token
+    pub fn pending(&self) -> &str {
+        &self.pending
+    }
+
+    // erase the last character read since the last token, as a backspace would. returns whether there was one to erase
+    pub fn backspace(&mut self) -> bool {
+        if self.pending.pop().is_none() {
+            return false;
+        }
+        // what's left is still the start of an output, so only the longest output it starts with needs finding again
+        self.matched = self.pending.char_indices().skip(1).map(|(end, _)| end).chain([self.pending.len()])
+                           .filter_map(|end| self.outputs.get(&self.pending[..end]).map(|&idx| (end, idx)))
+                           .last();
+        true
+    }
+
+    pub fn push(&mut self, c: char) -> Vec<DecodedToken> {
+        let mut tokens = Vec::new();
+        self.feed(c, &mut tokens);
+        tokens
+    }
+
+    fn feed(&mut self, c: char, tokens: &mut Vec<DecodedToken>) {
+        self.pending.push(c);
+        let is_prefix = self.prefixes.contains(&self.pending);
+        if let Some(&idx) = self.outputs.get(&self.pending) {
+            if !is_prefix {
+                tokens.push(DecodedToken::Output(idx));
+                self.pending.clear();
+                self.matched = None;
+            } else {
+                self.matched = Some((self.pending.len(), idx));
+            }
+        } else if !is_prefix {
+            self.resolve(tokens);
+        }
+    }
+
+    fn resolve(&mut self, tokens: &mut Vec<DecodedToken>) {
+        // pending can't be continued into an output: read the longest output it starts with and decode the rest again,
+        // or if there isn't one, it's invalid
+        match self.matched.take() {
+            Some((len, idx)) => {
+                tokens.push(DecodedToken::Output(idx));
+                let rest = self.pending.split_off(len);
+                self.pending.clear();
+                for c in rest.chars() {
+                    self.feed(c, tokens);
+                }
+            },
+            None => {
+                let mut rest = self.pending.chars();
+                // this unwrap is safe because pending is only resolved when it isn't empty
+                let first = rest.next().unwrap();
+                let rest = rest.as_str().to_string();
+                match tokens.last_mut() {
+                    Some(DecodedToken::Invalid(text)) => text.push(first),
+                    _ => tokens.push(DecodedToken::Invalid(first.to_string())),
+                }
+                self.pending.clear();
+                for c in rest.chars() {
+                    self.feed(c, tokens);
+                }
+            },
+        }
+    }
+
+    // the end of the input: whatever is pending is read, and the start of an output which wasn't completed is invalid
+    pub fn finish(&mut self) -> Vec<DecodedToken> {
+        let mut tokens = Vec::new();
+        while !self.pending.is_empty() {
+            self.resolve(&mut tokens);
+        }
+        tokens
+    }
+
+    // read a line from input a character at a time, decoding it as it's read: each token is given to on_event as soon as it's
+    // decoded, so with a terminal which hands over each character as it's typed, each chord is reported as soon as it's
+    // complete. returns the line (without the line ending or what was erased), or None if the input had already ended.
+    // such a terminal leaves erasing to us: a backspace erases the last character still pending, or if there isn't one, takes
+    // back the whole of the last token reported in the line (a chord is typed at once, so it's erased at once)
+    pub fn decode_line<R: std::io::Read>(&mut self, input: &mut R, mut on_event: impl FnMut(LineEvent)) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        // the tokens reported in the line, so that they can be taken back
+        let mut reported: Vec<DecodedToken> = Vec::new();
+        let mut bytes = Vec::new();
+        let mut byte = [0u8];
+        let mut ended = true;
+        loop {
+            match input.read(&mut byte) {
+                Ok(0) => break,
+                Ok(_) => ended = false,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            bytes.push(byte[0]);
+            let c = match std::str::from_utf8(&bytes) {
+                // this unwrap is safe because bytes is one whole character
+                Ok(s) => s.chars().next().unwrap(),
+                // the rest of the character is still to come
+                Err(e) if e.error_len().is_none() => continue,
+                Err(_) => char::REPLACEMENT_CHARACTER,
+            };
+            bytes.clear();
+            match c {
+                '\n' => break,
+                '\r' => continue,
+                '\u{8}' | '\u{7f}' => {
+                    if self.backspace() {
+                        line.pop();
+                    } else if let Some(token) = reported.pop() {
+                        let len = match &token {
+                            DecodedToken::Output(idx) => self.lengths[*idx],
+                            DecodedToken::Invalid(text) => text.len(),
+                        };
+                        line.truncate(line.len() - len);
+                        on_event(LineEvent::Erased(token));
+                    }
+                },
+                _ => {
+                    line.push(c);
+                    for token in self.push(c) {
+                        reported.push(token.clone());
+                        on_event(LineEvent::Token(token));
+                    }
+                },
+            }
+        }
+        for token in self.finish() {
+            on_event(LineEvent::Token(token));
+        }
+        Ok(if ended { None } else { Some(line) })
+    }
+
+    // decode a whole string, starting from nothing pending
+    pub fn decode(&mut self, text: &str) -> Vec<DecodedToken> {
+        self.pending.clear();
+        self.matched = None;
+        let mut tokens = Vec::new();
+        for c in text.chars() {
+            self.feed(c, &mut tokens);
+        }
+        tokens.extend(self.finish());
+        tokens
+    }
 }
 
 pub trait ChordTrialUtils<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, I, S: ChordSampler<K, N, L, R, I>>: Sized + Serialize + DeserializeOwned {
-    fn new(chord_sampler: S) -> Self;
+    // how the outputs the chords are given are chosen
+    type CodeConfig: Default + DeserializeOwned;
+    // seed is the seed that the sampler's rng was created from, if any. it is only recorded so that the vocabulary can be regenerated
+    fn new(chord_sampler: S, seed: Option<u64>, code_config: &Self::CodeConfig) -> Result<Self, Box<dyn Error>>;
+    fn get_seed(&self) -> Option<u64>;
     fn get_config(&self) -> Result<Vec<u8>, Box<dyn Error>>;
     fn get_vocab(&self) -> &Vec<(Chord<K, N, L>, String)>;
+    // the index of get_vocab. implementations should build it when they're created or deserialized
+    fn get_index(&self) -> &VocabIndex<K, N, L>;
     fn parse_trial_string(&self, test_string: &str) -> Result<Vec<Chord<K, N, L>>, Box<dyn Error>>;
-    // TODO: these are inefficient. if we ever need ok performance on this, should create and store a hashmap inside the struct
     fn lookup_chord(&self, chord: &Chord<K, N, L>) -> Option<String> {
-        self.get_vocab().iter().find(|(c, _)| c == chord).map(|(_, s)| s.clone())
+        self.get_index().chord_index(chord).map(|idx| self.get_vocab()[idx].1.clone())
     }
     fn lookup_string(&self, string: &str) -> Option<Chord<K, N, L>> {
-        self.get_vocab().iter().find(|(_, s)| s == string).map(|(c, _)| c.clone())
+        self.get_index().string_index(string).map(|idx| self.get_vocab()[idx].0.clone())
+    }
+    // a decoder for the text typed with the vocabulary, which reads the indices of its entries
+    fn stream_decoder(&self) -> StreamDecoder {
+        StreamDecoder::new(self.get_vocab().iter().map(|(_, string)| string.as_str()))
+    }
+    // like parse_trial_string, but text which isn't the output of any chord is kept as invalid input instead of failing,
+    // so that the mistakes in a trial count against its accuracy
+    fn parse_trial_string_tolerant(&self, trial_string: &str) -> Vec<TypedChord<K, N, L>> {
+        self.stream_decoder().decode(trial_string).into_iter().map(|token| match token {
+            DecodedToken::Output(idx) => TypedChord::Chord(self.get_vocab()[idx].0.clone()),
+            DecodedToken::Invalid(invalid) => TypedChord::Invalid { invalid },
+        }).collect()
     }
 }
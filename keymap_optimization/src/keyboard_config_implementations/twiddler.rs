@@ -1,19 +1,18 @@
-use crate::keyboard_config::{Chord, ChordTrialUtils, Key, Layout, ChordSampler};
+use crate::keyboard_config::{Chord, ChordTrialUtils, DecodedToken, Key, Layout, ChordSampler, Finger, VocabIndex};
 use rand::distributions::{Distribution, Standard};
-use rand::rngs::ThreadRng;
 use strum::{EnumCount, VariantArray};
 use std::fmt;
 use std::fmt::Display;
 use std::error::Error;
+use std::collections::HashSet;
 use serde::{Serialize, Deserialize};
 use serde_big_array::BigArray;
-use queues::{queue, Queue, IsQueue};
 
-use twidlk_rust::{twiddler_config::{generate_bin_config, text_to_usb, usb_hid_to_text, sort_chords, ChordWithOutput, TwiddlerConfig}, unmap_char};
+use twidlk_rust::{twiddler_config::{generate_bin_config, text_to_usb, usb_hid_to_text, sort_chords, ChordWithOutput, TwiddlerConfig}, unmap_char, generate_text_config, read_config};
 
 // requirements for twiddler config files
-const MAX_CHORDS: u16 = 1020;
-const MAX_MULTICHAR_CHORDS: u16 = 256;
+pub const MAX_CHORDS: u16 = 1020;
+pub const MAX_MULTICHAR_CHORDS: u16 = 256;
 
 // we use usb hid codes to represent characters in the output since they're what the twiddler actually sends;
 // we aren't working with the codes directly (we're basically just using the number of them) but it's nice
@@ -116,6 +115,11 @@ impl Key for TwiddlerKey {
     fn gen_random<R: rand::Rng>(rng: &mut R) -> Self {
         rng.gen::<TwiddlerKey>()
     }
+
+    fn index(self) -> usize {
+        // the variants are declared in the order of VARIANTS
+        self as usize
+    }
 }
 
 impl Distribution<TwiddlerKey> for Standard {
@@ -208,6 +212,18 @@ impl Layout<TwiddlerKey, { TwiddlerKey::COUNT }> for TwiddlerLayout {
             true
         }
     }
+
+    fn finger(key: TwiddlerKey) -> Finger {
+        // all the thumb keys are pressed by the thumb; each row of the main keys is pressed by a single finger,
+        // from the index finger in the top row to the pinky in the bottom row
+        if TwiddlerLayout::THUMB.contains(&key) {
+            return Finger::Thumb;
+        }
+        const ROW_FINGERS: [Finger; 4] = [Finger::Index, Finger::Middle, Finger::Ring, Finger::Pinky];
+        // this unwrap is safe because every key is either a thumb key or in one of the main rows
+        let row = TwiddlerLayout::MAIN.iter().position(|row| row.contains(&key)).unwrap();
+        ROW_FINGERS[row]
+    }
 }
 
 // Z0, R0 is also reserved but isn't a valid chord anyway
@@ -333,144 +349,182 @@ impl Node {
         // convert a list of indices to a single string by concatenating the results for each index
         idxs.into_iter().map(|i| Node::idx_to_string(i)).collect()
     }
+}
 
-    // value is reversed. it should be cloned before calling this function.
-    fn get_child<'a>(&mut self, mut value: Vec<Idx>) -> Option<&mut Node> {
-        // just get_child_ but with value reversed
-        value.reverse();
-        self.get_child_(value)
+#[derive(Serialize, Deserialize)]
+#[serde(from = "StoredTrialUtils")]
+pub struct TwiddlerChordTrialUtils {
+    vocab: Vec<(TwiddlerChord, String)>,
+    code_tree: Node,
+    seed: Option<u64>,
+    // how the strings were chosen. decoders from before this was configurable don't have it; their strings are every
+    // character, and then pairs starting with the first characters, in the order of the usb hid codes
+    code: Option<CodeConfig>,
+    // built from vocab whenever this is created or loaded
+    #[serde(skip)]
+    index: VocabIndex<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout>,
+}
+
+// the fields of TwiddlerChordTrialUtils which are saved
+#[derive(Deserialize)]
+struct StoredTrialUtils {
+    vocab: Vec<(TwiddlerChord, String)>,
+    code_tree: Node,
+    #[serde(default)]  // decoders generated before seeds were recorded don't have one
+    seed: Option<u64>,
+    #[serde(default)]
+    code: Option<CodeConfig>,
+}
+
+impl From<StoredTrialUtils> for TwiddlerChordTrialUtils {
+    fn from(stored: StoredTrialUtils) -> Self {
+        let index = VocabIndex::new(&stored.vocab);
+        Self { vocab: stored.vocab, code_tree: stored.code_tree, seed: stored.seed, code: stored.code, index }
     }
+}
 
-    fn get_child_(&mut self, mut value: Vec<Idx>) -> Option<&mut Node> {
-        match value.pop() {
-            None => return Some(self),
-            Some(last) => {
-                match &mut self.children {
-                    Some(children) => children.contents[last as usize].get_child_(value),
-                    None => None,
-                }
-            }
-        }
+// the design of the code which the trial decoder uses: the strings the chords of a data collection config output, which have
+// to be prefix-free so that the trial input can be split back into chords. the code maximizes the number of strings (within
+// the limits below), and among codes of that size it minimizes the total cost of typing them all, since the chords are
+// drawn uniformly
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CodeConfig {
+    // the characters the strings are made of; None for every character the twiddler can output (see USB_HID_RANGES)
+    pub alphabet: Option<String>,
+    // characters which are left out of the alphabet. by default these are the dead keys of common international layouts,
+    // which the os combines with the next character
+    pub avoid: String,
+    // typing a character costs 1, plus this if it needs shift
+    pub shift_cost: f64,
+    // at most MAX_CHORDS and MAX_MULTICHAR_CHORDS
+    pub max_outputs: usize,
+    pub max_multichar: usize,
+}
+
+impl Default for CodeConfig {
+    fn default() -> Self {
+        Self { alphabet: None, avoid: "`~^'\"".to_string(), shift_cost: 1.0, max_outputs: MAX_CHORDS as usize, max_multichar: MAX_MULTICHAR_CHORDS as usize }
     }
+}
 
-    fn read_last_word_<'a>(&self, out: &'a mut Vec<Idx>, value: &'a mut Vec<Idx>) -> Result<(), Box<dyn Error>> {
-        // removes the last word of value and reads it into out
-        match value.pop() {
-            None => match &self.children {
-                None => Ok(()),  // the value string ended at a leaf node
-                Some(_) => Err("value string ended in the middle of a word".into()),
-            }
-            Some(last) => {
-                match &self.children {
-                    None => {
-                        value.push(last);  // put last back, since it wasn't part of this word
-                        Ok(())
-                    },
-                    Some(children) => {
-                        out.push(last);
-                        children.contents[last as usize].read_last_word_(out, value)
-                    }
-                }
+impl CodeConfig {
+    fn alphabet(&self) -> Result<Vec<(Idx, f64)>, Box<dyn Error>> {
+        // the characters as indices, with their costs, cheapest first (and otherwise in the order they were given)
+        let mut idxs: Vec<Idx> = match &self.alphabet {
+            None => (0..USB_HID_COUNT).collect(),
+            Some(alphabet) => alphabet.chars().map(|c| {
+                let (shifted, usb) = unmap_char(&c.to_string()).map_err(|_| format!("the twiddler can't type {:?}", c))?;
+                Node::usb_to_idx(shifted.unwrap_or(false), usb).map_err(|_| format!("{:?} can't be used in the code", c).into())
+            }).collect::<Result<Vec<Idx>, Box<dyn Error>>>()?,
+        };
+        let mut seen = Vec::new();
+        idxs.retain(|idx| !seen.contains(idx) && { seen.push(*idx); true });
+        let mut alphabet = Vec::new();
+        for idx in idxs {
+            if !Node::idx_to_string(idx)?.chars().any(|c| self.avoid.contains(c)) {
+                let shifted = idx >= HALF_USB_HID_COUNT;
+                alphabet.push((idx, 1.0 + if shifted { self.shift_cost } else { 0.0 }));
             }
         }
-    }
-
-    fn read_last_word<'a>(&self, value: &'a mut Vec<Idx>) -> Result<Vec<Idx>, Box<dyn Error>> {
-        // removes the last word of value and returns it as a string
-        let mut out = Vec::new();
-        self.read_last_word_(&mut out, value)?;
-        Ok(out)
+        alphabet.sort_by(|(_, c1), (_, c2)| c1.total_cmp(c2));
+        Ok(alphabet)
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct TwiddlerChordTrialUtils {
-    vocab: Vec<(TwiddlerChord, String)>,
-    code_tree: Node,
+pub fn check_code(outputs: &[String], max_outputs: usize, max_multichar: usize) -> Result<(), Box<dyn Error>> {
+    // that the outputs can be split apart again, and fit on the twiddler
+    let n_multichar = outputs.iter().filter(|output| output.chars().count() > 1).count();
+    if outputs.len() > max_outputs.min(MAX_CHORDS as usize) || n_multichar > max_multichar.min(MAX_MULTICHAR_CHORDS as usize) {
+        return Err(format!("the code has {} strings, {} with more than one character, which is too many", outputs.len(), n_multichar).into());
+    }
+    // in sorted order, a string which is a prefix of others comes right before one of them
+    let mut sorted: Vec<&String> = outputs.iter().collect();
+    sorted.sort();
+    for pair in sorted.windows(2) {
+        if pair[1].starts_with(pair[0].as_str()) {
+            return Err(format!("the code isn't prefix-free: {:?} starts with {:?}", pair[1], pair[0]).into());
+        }
+    }
+    Ok(())
 }
 
 impl TwiddlerChordTrialUtils {
     // this should only be called once: during initialization. after that, the fields vocab and code_tree should be referenced.
-    fn get_code<R: rand::Rng, I, S: ChordSampler<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, R, I>>(chord_sampler: &mut S) -> (Node, Vec<(TwiddlerChord, String)>) {
-        // make a binary tree so we can uniquely decode sequences of chord strings into chords
-        // there can be at most MAX_MULTICHAR_CHORDS strings with multiple characters,
-        // and at most MAX_CHORDS strings overall.
-
-        let mut multichar_count: u16 = 0;
-
-        // the string represents the path from the root (empty string) to the leaf
-        let root_value = Vec::new();
-        let mut root = Node {
-            children: None,
+    fn get_code<R: rand::Rng, I, S: ChordSampler<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, R, I>>(chord_sampler: &mut S, config: &CodeConfig) -> Result<(Node, Vec<(TwiddlerChord, String)>), Box<dyn Error>> {
+        // the code has strings of one or two characters: the characters which aren't the first of a longer string, and the
+        // pairs which start with the others (the prefixes), so it's prefix-free. it's also a tree, with the prefixes as the
+        // nodes with children, so that it can be saved in the same form as before.
+        let alphabet = &config.alphabet()?;
+        let n_chars = alphabet.len();
+        if n_chars < 2 {
+            return Err("the code needs at least two characters".into());
+        }
+        let max_outputs = config.max_outputs.min(MAX_CHORDS as usize);
+        let max_multichar = config.max_multichar.min(MAX_MULTICHAR_CHORDS as usize);
+        // the number of strings with n_prefixes prefixes, and the fewest prefixes which give the most strings
+        let n_strings = |n_prefixes: usize| {
+            let n_single = n_chars - n_prefixes;
+            n_single.min(max_outputs) + (n_prefixes * n_chars).min(max_multichar).min(max_outputs.saturating_sub(n_single))
         };
-        // the queue just stores the in-progress strings, rather than mutable references to them, to avoid multiple mutable borrows.
-        // when we want to modify the children of a node, we look it up via the tree.
-        let mut node_queue: Queue<Vec<Idx>> = queue![];
-        node_queue.add(root_value).unwrap();  // for whatever reason this always returns Ok(None). idk why. i checked the source code. so the unwrap is ok
-        'create_strings: loop {
-            // take a node from the queue.
-            // create and enqueue all its children.
-            // continue this until we reach one of the stopping conditions.
-            // this unwrap is safe because we always enqueue at least one child for each node
-            // (in particular, we always enqueue as many children as there are HID codes we want to use)
-            let current_node_str = node_queue.remove().unwrap();
-            // this unwrap is safe because whenever we add a string to the queue we also add it to the tree, and we never remove things from the tree
-            let current_node = if current_node_str.len() == 0 {  // this only happens when we just popped the root
-                &mut root
-            } else {
-                root.get_child(current_node_str.clone()).unwrap()
-            };
-            if current_node_str.len() > 1 {  // we're removing this string from the queue, so if it has multiple characters we need to adjust the count
-                multichar_count -= 1;
-            }
-
-            // use core::array::from_fn to create an array of nodes
-            current_node.children = Some(Box::new(Children { contents: core::array::from_fn(|_| Node { children: None }) }));
-
-            for idx in 0..USB_HID_COUNT {
-                let mut new_node_str = current_node_str.clone();
-                new_node_str.push(idx);
-                node_queue.add(new_node_str).unwrap();  // for whatever reason this always returns Ok(None). idk why. i checked the source code. so the unwrap is ok
-                if current_node_str.len() > 0 {  // if the current node contained at least one character, the new value we're adding is a multichar
-                    multichar_count += 1;
-                }
-                // stopping conditions
-                if node_queue.size() >= MAX_CHORDS as usize || multichar_count >= MAX_MULTICHAR_CHORDS {
-                    break 'create_strings;
+        // this unwrap is safe because the range isn't empty
+        let n_prefixes = (0..=n_chars).rev().max_by_key(|&n| n_strings(n)).unwrap();
+        let n_pairs = n_strings(n_prefixes) - (n_chars - n_prefixes).min(max_outputs);
+
+        // the cost of a choice of prefixes (as positions in alphabet): the single characters left, and the cheapest pairs
+        let pairs = |prefixes: &[usize]| -> Vec<(f64, usize, usize)> {
+            let mut pairs: Vec<(f64, usize, usize)> = prefixes.iter()
+                .flat_map(|&first| (0..n_chars).map(move |second| (alphabet[first].1 + alphabet[second].1, first, second)))
+                .collect();
+            pairs.sort_by(|(c1, f1, s1), (c2, f2, s2)| c1.total_cmp(c2).then(f1.cmp(f2)).then(s1.cmp(s2)));
+            pairs.truncate(n_pairs);
+            pairs
+        };
+        let cost = |prefixes: &[usize]| -> f64 {
+            let singles: f64 = (0..n_chars).filter(|i| !prefixes.contains(i)).map(|i| alphabet[i].1).take(max_outputs).sum();
+            singles + pairs(prefixes).iter().map(|(c, _, _)| c).sum::<f64>()
+        };
+        // the cheapest characters make the cheapest pairs, so they're the prefixes to start with; then characters are swapped
+        // in and out while that makes the code cheaper
+        let mut prefixes: Vec<usize> = (0..n_prefixes).collect();
+        let mut best = cost(&prefixes);
+        'improve: loop {
+            for slot in 0..prefixes.len() {
+                for candidate in (0..n_chars).filter(|i| !prefixes.contains(i)) {
+                    let mut swapped = prefixes.clone();
+                    swapped[slot] = candidate;
+                    let swapped_cost = cost(&swapped);
+                    if swapped_cost < best - 1e-9 {
+                        (prefixes, best) = (swapped, swapped_cost);
+                        continue 'improve;
+                    }
                 }
             }
-        }
-        fn queue_to_vec<T: Clone>(mut queue: Queue<T>) -> Vec<T> {
-            let mut vec = Vec::new();
-            while let Ok(item) = queue.remove() {
-                vec.push(item);
-            }
-            vec
+            break;
         }
 
-        // now the queue contains a valid set of strings
-        let ok_strings = queue_to_vec(node_queue)
-        .into_iter()
-        // this unwrap is safe if the code is correct, because the values of i that are converted to usb do not depend on any input
-        .map(|s| Node::idxs_to_string(s).unwrap())
-        .collect::<Vec<String>>();
-
-        // we match each string with a chord
-        let mut chords = Vec::new();
-        while chords.len() < ok_strings.len() {
-            let chord = chord_sampler.sample_chord();
-            // TODO: this can be very slow if the number of chords we need is large compared to the standard deviation of the sampler.
-            // if this becomes a problem, it should be possible to make samplers which can exclude chords they've already sampled
-            // (e.g. if the sampler is choosing a random chord from the list of valid chords, we could remove the chosen chord from the list)
-            if !chords.contains(&chord) {
-                chords.push(chord);
-            }
+        let mut root = Node { children: Some(Box::new(Children { contents: core::array::from_fn(|_| Node { children: None }) })) };
+        let mut strings: Vec<Vec<Idx>> = (0..n_chars).filter(|i| !prefixes.contains(i)).map(|i| vec![alphabet[i].0]).take(max_outputs).collect();
+        for &prefix in &prefixes {
+            // this unwrap is safe because the root has children
+            root.children.as_mut().unwrap().contents[alphabet[prefix].0 as usize].children = Some(Box::new(Children { contents: core::array::from_fn(|_| Node { children: None }) }));
         }
+        strings.extend(pairs(&prefixes).into_iter().map(|(_, first, second)| vec![alphabet[first].0, alphabet[second].0]));
+        let ok_strings = strings.into_iter().map(Node::idxs_to_string).collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+        check_code(&ok_strings, max_outputs, max_multichar)?;
+
+        // we match each string with a chord.
+        // samplers which choose from a finite set of candidates sample these without replacement, so this is fast;
+        // the others fall back to rejection sampling
+        let chords = chord_sampler.sample_distinct_chords(ok_strings.len())?;
         let vocab = chords.into_iter().zip(ok_strings).collect();
 
-        (root, vocab)
+        Ok((root, vocab))
     }
 
+    pub fn code_config(&self) -> Option<&CodeConfig> {
+        self.code.as_ref()
+    }
 }
 
 pub fn chord_list_to_config_object(chords: Vec<(TwiddlerChord, String)>) -> Result<TwiddlerConfig, Box<dyn Error>> {
@@ -485,6 +539,27 @@ pub fn chord_list_to_config_object(chords: Vec<(TwiddlerChord, String)>) -> Resu
     Ok(twidlk_config)
 }
 
+pub fn vocab_to_config(vocab: Vec<(TwiddlerChord, String)>) -> Result<Vec<u8>, Box<dyn Error>> {
+    // the binary config file for a vocabulary (e.g. one made by the layout optimizer)
+    generate_bin_config(&chord_list_to_config_object(vocab)?)
+}
+
+pub fn config_differences(config: &[u8], expected: &[u8]) -> Result<(Vec<String>, Vec<String>), Box<dyn Error>> {
+    // compares two binary config files through their text format. returns the lines only in config, and those only in expected
+    let config_text = generate_text_config(&read_config(config)?)?;
+    let expected_text = generate_text_config(&read_config(expected)?)?;
+    let config_lines: HashSet<&str> = config_text.lines().collect();
+    let expected_lines: HashSet<&str> = expected_text.lines().collect();
+    let only_in_config = config_text.lines().filter(|line| !expected_lines.contains(line)).map(String::from).collect();
+    let only_in_expected = expected_text.lines().filter(|line| !config_lines.contains(line)).map(String::from).collect();
+    Ok((only_in_config, only_in_expected))
+}
+
+pub fn is_typeable(output: &str) -> bool {
+    // whether the twiddler can be configured to produce this string
+    text_to_usb(output.to_string()).is_ok()
+}
+
 public_for_test! {
 fn random_chord_<R: rand::Rng, K: Key, const N: usize, L: Layout<K, N>>(rng: &mut R, threshold: f64) -> Chord<K, N, L> {
     // sample a random chord with a number of keys distributed almost exponentially with base 1/threshold
@@ -507,74 +582,63 @@ pub struct TwiddlerExponentialSampler<R: rand::Rng> {
     rng: R
 }
 
-impl ChordSampler<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, ThreadRng, ()> for TwiddlerExponentialSampler<ThreadRng> {
-    fn new(rng: ThreadRng, _: &()) -> Result<Self, Box<dyn Error>> {
+// nothing here depends on the twiddler, so this sampler works for any layout
+impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng> ChordSampler<K, N, L, R, ()> for TwiddlerExponentialSampler<R> {
+    fn new(rng: R, _: &()) -> Result<Self, Box<dyn Error>> {
         Ok(TwiddlerExponentialSampler { rng })
     }
 
-    fn sample_chord(&mut self) -> TwiddlerChord {
+    fn sample_chord(&mut self) -> Chord<K, N, L> {
         // sample a chord with an exponentially distributed number of keys
         const CHORD_KEY_SAMPLE_THRESHOLD: f64 = 0.6;
         // rejection sample until we get a valid chord (this is quite fast; most chords are valid)
         loop {
             let attempted_chord = random_chord_(&mut self.rng, CHORD_KEY_SAMPLE_THRESHOLD);
-            if TwiddlerLayout::is_valid(&attempted_chord) {
+            if L::is_valid(&attempted_chord) {
                 return attempted_chord;
             }
         }
     }
 }
 
-impl<I, S: ChordSampler<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, ThreadRng, I>> ChordTrialUtils<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, ThreadRng, I, S> for TwiddlerChordTrialUtils {
-    fn new(mut chord_sampler: S) -> Self {
-        let (code_tree, vocab) = Self::get_code(&mut chord_sampler);
-        TwiddlerChordTrialUtils {
+impl<R: rand::Rng, I, S: ChordSampler<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, R, I>> ChordTrialUtils<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout, R, I, S> for TwiddlerChordTrialUtils {
+    type CodeConfig = CodeConfig;
+
+    fn new(mut chord_sampler: S, seed: Option<u64>, code_config: &CodeConfig) -> Result<Self, Box<dyn Error>> {
+        let (code_tree, vocab) = Self::get_code(&mut chord_sampler, code_config)?;
+        let index = VocabIndex::new(&vocab);
+        Ok(TwiddlerChordTrialUtils {
             vocab,
             code_tree,
-        }
+            seed,
+            code: Some(code_config.clone()),
+            index,
+        })
+    }
+
+    fn get_seed(&self) -> Option<u64> {
+        self.seed
     }
 
     fn get_vocab(&self) -> &Vec<(TwiddlerChord, String)> {
         &self.vocab
     }
 
+    fn get_index(&self) -> &VocabIndex<TwiddlerKey, { TwiddlerKey::COUNT }, TwiddlerLayout> {
+        &self.index
+    }
+
     fn get_config(&self) -> Result<Vec<u8>, Box<dyn Error>> {
-        let twidlk_config = chord_list_to_config_object(self.vocab.clone())?;
-        generate_bin_config(&twidlk_config)
+        vocab_to_config(self.vocab.clone())
     }
 
     fn parse_trial_string(&self, trial_string: &str) -> Result<Vec<TwiddlerChord>, Box<dyn Error>> {
-        // convert the test string to usb hid codes, and from there to indices
-        let mut trial_idxs = trial_string.chars().map(|c| {
-            let (shifted, usb) = unmap_char(&c.to_string())?;
-            Node::usb_to_idx(match shifted {
-                Some(v) => v,
-                _ => false,
-            }, usb)
-        }).collect::<Result<Vec<Idx>, Box<dyn Error>>>()?;
-        let root = &self.code_tree;
-
-        // the reader function takes a reversed list
-        trial_idxs.reverse();
-
-        // read in words until end of trial input
-        let mut words: Vec<String> = Vec::new();
-        while trial_idxs.len() > 0 {
-            let word: String = root.read_last_word(&mut trial_idxs)?
-            .into_iter()
-            .map(|i| Node::idx_to_usb(i).and_then(|(s, c)| Ok(usb_hid_to_text(s, c).1)))
-            .collect::<Result<Vec<String>, Box<dyn Error>>>()?
-            .join("");
-            words.push(word);
-        }
-
-        // now convert the words to chords
-        let result: Vec<TwiddlerChord> = match words.into_iter().map(|w| <TwiddlerChordTrialUtils as ChordTrialUtils<TwiddlerKey, 16, TwiddlerLayout, ThreadRng, I, S>>::lookup_string(self, &w)).collect() {
-            None => return Err("could not find chord for word".into()),
-            Some(c) => c,
-        };
-
-        Ok(result)
+        // the outputs are the code words of a prefix-free code, so each chord is read as soon as its output is complete
+        let decoder = &mut <TwiddlerChordTrialUtils as ChordTrialUtils<TwiddlerKey, 16, TwiddlerLayout, R, I, S>>::stream_decoder(self);
+        decoder.decode(trial_string).into_iter().map(|token| match token {
+            DecodedToken::Output(idx) => Ok(self.vocab[idx].0.clone()),
+            DecodedToken::Invalid(text) => Err(format!("{:?} is not the output of any chord", text).into()),
+        }).collect()
     }
 }
 
@@ -0,0 +1,104 @@
+use std::error::Error;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::keyboard_config::{Chord, Key, Layout};
+
+// restrictions on an optimization run, e.g. so that extending an existing layout with punctuation doesn't move its letters:
+// outputs pinned to chords, chords which may not be used, a maximum number of keys per chord, and a cap on how many of the
+// outputs of an existing (base) layout may be given different chords. outputs of the base layout stay on their chords
+// unless moving them is worth it, and the base layout's outputs are kept even if the corpus doesn't contain them.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutConstraints<K: Key, const N: usize, L: Layout<K, N>> {
+    pub pinned: Vec<(Chord<K, N, L>, String)>,
+    pub forbidden: Vec<Chord<K, N, L>>,
+    // pinned chords may have more keys than this
+    pub max_keys: Option<usize>,
+    pub base_layout: Vec<(Chord<K, N, L>, String)>,
+    // the number of outputs of the base layout which may end up on a different chord. outputs whose base chord is forbidden
+    // (or too big, or not valid on the device) count towards this too, since they have to move
+    pub max_changes: Option<usize>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> LayoutConstraints<K, N, L> {
+    pub fn none() -> Self {
+        Self { pinned: Vec::new(), forbidden: Vec::new(), max_keys: None, base_layout: Vec::new(), max_changes: None }
+    }
+
+    pub fn allows(&self, chord: &Chord<K, N, L>) -> bool {
+        // whether chord may be assigned to an output which isn't pinned
+        L::is_valid(chord) && !self.forbidden.contains(chord) && self.max_keys.is_none_or(|max_keys| chord.n_keys() <= max_keys)
+    }
+
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        for (i, (chord, output)) in self.pinned.iter().enumerate() {
+            if !L::is_valid(chord) {
+                return Err(format!("the chord pinned to {:?} isn't valid", output).into());
+            }
+            if self.forbidden.contains(chord) {
+                return Err(format!("the chord pinned to {:?} is forbidden", output).into());
+            }
+            if self.pinned[..i].iter().any(|(c, o)| c == chord || o == output) {
+                return Err(format!("{:?} is pinned more than once, or shares its chord with another pinned output", output).into());
+            }
+        }
+        Ok(())
+    }
+}
+
+// the chord of a pinned output in a constraint file, as a list of keys
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned")]
+pub struct PinnedChord<K: Key> {
+    pub keys: Vec<K>,
+    pub output: String,
+}
+
+// the form of LayoutConstraints that is written by hand, with chords as lists of keys.
+// pin_from_base lists outputs which are pinned to their chords in the base layout (e.g. all the letters)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, bound = "K: DeserializeOwned")]
+pub struct ConstraintSpec<K: Key> {
+    pub pinned: Vec<PinnedChord<K>>,
+    pub pin_from_base: Vec<String>,
+    pub forbidden: Vec<Vec<K>>,
+    pub max_keys: Option<usize>,
+    pub max_changes: Option<usize>,
+}
+
+impl<K: Key> Default for ConstraintSpec<K> {
+    fn default() -> Self {
+        Self { pinned: Vec::new(), pin_from_base: Vec::new(), forbidden: Vec::new(), max_keys: None, max_changes: None }
+    }
+}
+
+pub fn chord_from_keys<K: Key, const N: usize, L: Layout<K, N>>(keys: &[K]) -> Chord<K, N, L> {
+    let mut chord = Chord::new();
+    for key in keys {
+        chord.add_key(*key);
+    }
+    chord
+}
+
+impl<K: Key> ConstraintSpec<K> {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    pub fn resolve<const N: usize, L: Layout<K, N>>(&self, base_layout: Vec<(Chord<K, N, L>, String)>) -> Result<LayoutConstraints<K, N, L>, Box<dyn Error>> {
+        let mut pinned: Vec<(Chord<K, N, L>, String)> = self.pinned.iter().map(|p| (chord_from_keys(&p.keys), p.output.clone())).collect();
+        for output in &self.pin_from_base {
+            let (chord, _) = base_layout.iter().find(|(_, o)| o == output).ok_or_else(|| format!("{:?} isn't in the base layout", output))?;
+            pinned.push((chord.clone(), output.clone()));
+        }
+        let constraints = LayoutConstraints {
+            pinned,
+            forbidden: self.forbidden.iter().map(|keys| chord_from_keys(keys)).collect(),
+            max_keys: self.max_keys,
+            base_layout,
+            max_changes: self.max_changes,
+        };
+        constraints.check()?;
+        Ok(constraints)
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+
+// how often each output (a string a chord produces) appears in some text, and how often each output follows each other one.
+// the text is split into outputs greedily, always taking the longest output that matches; characters which don't start any
+// output are skipped, and break the sequence (so they don't create bigrams between the outputs on either side of them)
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextStatistics {
+    pub outputs: Vec<String>,
+    pub counts: Vec<f64>,  // indexed like outputs
+    pub bigrams: Vec<(usize, usize, f64)>,  // (index of the first output, index of the second output, count)
+}
+
+// splits text into outputs, greedily taking the longest output that matches at each position
+pub struct Segmenter<'a> {
+    index: HashMap<&'a str, usize>,
+    max_len: usize,
+}
+
+impl<'a> Segmenter<'a> {
+    pub fn new(outputs: &'a [String]) -> Self {
+        let index = outputs.iter().enumerate().map(|(i, o)| (o.as_str(), i)).collect();
+        let max_len = outputs.iter().map(|o| o.chars().count()).max().unwrap_or(0);
+        Self { index, max_len }
+    }
+
+    pub fn segment(&self, chars: &[char]) -> Vec<Option<usize>> {
+        // the index of each output in turn, and None for each character which doesn't start any output
+        let mut segments = Vec::new();
+        let mut pos = 0;
+        while pos < chars.len() {
+            let matched = (1..=self.max_len.min(chars.len() - pos)).rev().find_map(|len| {
+                let candidate: String = chars[pos..pos + len].iter().collect();
+                self.index.get(candidate.as_str()).map(|&i| (i, len))
+            });
+            match matched {
+                Some((i, len)) => {
+                    segments.push(Some(i));
+                    pos += len;
+                },
+                None => {
+                    segments.push(None);
+                    pos += 1;
+                },
+            }
+        }
+        segments
+    }
+}
+
+impl TextStatistics {
+    pub fn from_text(text: &str, outputs: &[String]) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let mut counts = vec![0.0; outputs.len()];
+        let mut bigram_counts: HashMap<(usize, usize), f64> = HashMap::new();
+        let mut previous: Option<usize> = None;
+        for segment in Segmenter::new(outputs).segment(&chars) {
+            if let (Some(p), Some(i)) = (previous, segment) {
+                *bigram_counts.entry((p, i)).or_insert(0.0) += 1.0;
+            }
+            if let Some(i) = segment {
+                counts[i] += 1.0;
+            }
+            previous = segment;
+        }
+        let mut bigrams: Vec<(usize, usize, f64)> = bigram_counts.into_iter().map(|((a, b), count)| (a, b, count)).collect();
+        // sorted so that the statistics (and so the optimization) don't depend on the hashmap's iteration order
+        bigrams.sort_by_key(|x| (x.0, x.1));
+        Self { outputs: outputs.to_vec(), counts, bigrams }
+    }
+
+    pub fn characters(text: &str) -> Vec<String> {
+        // the distinct characters of the text other than line breaks, most frequent first
+        let mut counts: HashMap<char, usize> = HashMap::new();
+        for c in text.chars().filter(|c| *c != '\n' && *c != '\r') {
+            *counts.entry(c).or_insert(0) += 1;
+        }
+        let mut chars: Vec<(char, usize)> = counts.into_iter().collect();
+        chars.sort_by(|(c1, n1), (c2, n2)| n2.cmp(n1).then(c1.cmp(c2)));
+        chars.into_iter().map(|(c, _)| c.to_string()).collect()
+    }
+
+    pub fn add_output(&mut self, output: &str) -> usize {
+        // the index of output, adding it (as never seen in the text) if it isn't one of the outputs already
+        match self.outputs.iter().position(|o| o == output) {
+            Some(idx) => idx,
+            None => {
+                self.outputs.push(output.to_string());
+                self.counts.push(0.0);
+                self.outputs.len() - 1
+            },
+        }
+    }
+
+    pub fn count_of(&self, output: &str) -> f64 {
+        // how many times output is typed in the text (0 if it isn't one of the outputs)
+        self.outputs.iter().position(|o| o == output).map_or(0.0, |idx| self.counts[idx])
+    }
+
+    pub fn total_count(&self) -> f64 {
+        self.counts.iter().sum()
+    }
+}
@@ -0,0 +1,4 @@
+pub mod corpus;
+pub mod optimizer;
+pub mod constraints;
+pub mod multichar;
@@ -0,0 +1,145 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+use crate::twiddler::{MAX_CHORDS, MAX_MULTICHAR_CHORDS};
+use super::corpus::{Segmenter, TextStatistics};
+
+// choosing which multi-character strings of a corpus (words and other frequent n-grams) get chords of their own.
+// a chord for a string saves typing its pieces one at a time, but it takes up a chord, and the outputs which are less
+// frequent than it end up on worse chords. the chords' costs are estimated without transitions: the outputs get the chords
+// in chord_costs (the costs of the candidate chords by themselves, cheapest first) in order, most frequent first.
+// strings are added in rounds, most valuable first, and the text is segmented again after each round, since e.g. a chord
+// for "the" makes "th" much rarer.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MulticharConfig {
+    // the limits of the device: the number of outputs with more than one character, and the number of outputs overall.
+    // there can't be more outputs than candidate chords either
+    pub max_multichar: usize,
+    pub max_outputs: usize,
+    // the longest string considered, in characters
+    pub max_len: usize,
+    // strings which would be typed fewer times than this in the corpus don't get chords
+    pub min_count: f64,
+    pub n_per_round: usize,
+}
+
+impl Default for MulticharConfig {
+    fn default() -> Self {
+        Self { max_multichar: MAX_MULTICHAR_CHORDS as usize, max_outputs: MAX_CHORDS as usize, max_len: 8, min_count: 5.0, n_per_round: 16 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MulticharOutput {
+    pub output: String,
+    // the number of times it's typed in the corpus, with all the chosen outputs
+    pub count: f64,
+    // the estimated cost it saves over the whole corpus, when it was chosen
+    pub saving: f64,
+}
+
+pub fn ngram_counts(text: &str, max_len: usize) -> HashMap<String, f64> {
+    // the number of occurrences (overlapping ones included) of each string of 2 to max_len characters without line breaks
+    let chars: Vec<char> = text.chars().collect();
+    let is_break = |c: char| c == '\n' || c == '\r';
+    let mut counts = HashMap::new();
+    for (start, &first) in chars.iter().enumerate().filter(|(_, c)| !is_break(**c)) {
+        let mut ngram = first.to_string();
+        for &c in chars[start + 1..].iter().take(max_len.saturating_sub(1)).take_while(|c| !is_break(**c)) {
+            ngram.push(c);
+            *counts.entry(ngram.clone()).or_insert(0.0) += 1.0;
+        }
+    }
+    counts
+}
+
+fn is_multichar(output: &str) -> bool {
+    output.chars().count() > 1
+}
+
+pub fn choose_multichar(text: &str, mut outputs: Vec<String>, chord_costs: &[f64], config: &MulticharConfig) -> (Vec<String>, Vec<MulticharOutput>) {
+    // outputs are the ones which the layout has to have anyway (e.g. the characters of the text), and their multi-character
+    // outputs count towards the limit. a string is only considered if it can already be typed with these outputs.
+    // returns the outputs with the chosen strings added, and the chosen strings, most frequent first
+    let max_outputs = config.max_outputs.min(chord_costs.len());
+    let mut ngrams: Vec<(String, f64)> = ngram_counts(text, config.max_len).into_iter()
+        .filter(|(ngram, count)| *count >= config.min_count && !outputs.contains(ngram))
+        .collect();
+    // sorted so that the choice doesn't depend on the hashmap's iteration order
+    ngrams.sort_by(|(s1, n1), (s2, n2)| n2.total_cmp(n1).then(s1.cmp(s2)));
+    let mut rejected: HashSet<String> = HashSet::new();
+    let mut chosen: Vec<(String, f64)> = Vec::new();
+
+    loop {
+        let n_multichar = outputs.iter().filter(|o| is_multichar(o)).count();
+        let room = config.max_multichar.saturating_sub(n_multichar).min(max_outputs.saturating_sub(outputs.len())).min(config.n_per_round);
+        if room == 0 {
+            break;
+        }
+        let stats = TextStatistics::from_text(text, &outputs);
+        let mut sorted_counts = stats.counts.clone();
+        sorted_counts.sort_by(|a, b| b.total_cmp(a));
+        let mut by_frequency: Vec<usize> = (0..outputs.len()).collect();
+        by_frequency.sort_by(|&a, &b| stats.counts[b].total_cmp(&stats.counts[a]));
+        let mut chord_cost = vec![0.0; outputs.len()];
+        for (rank, &output) in by_frequency.iter().enumerate() {
+            chord_cost[output] = chord_costs[rank];
+        }
+        // shift[rank] is the extra cost of moving the outputs from that rank onwards one chord down. there is a chord to move
+        // the last one to, since there's room for another output
+        let mut shift = vec![0.0; outputs.len() + 1];
+        for rank in (0..outputs.len()).rev() {
+            shift[rank] = shift[rank + 1] + sorted_counts[rank] * (chord_costs[rank + 1] - chord_costs[rank]);
+        }
+
+        let segmenter = Segmenter::new(&outputs);
+        let mut scored: Vec<(f64, usize)> = ngrams.iter().enumerate()
+            .filter(|(_, (ngram, _))| !rejected.contains(ngram) && !chosen.iter().any(|(c, _)| c == ngram))
+            .filter_map(|(i, (ngram, count))| {
+                let pieces = segmenter.segment(&ngram.chars().collect::<Vec<char>>());
+                let pieces_cost: f64 = pieces.iter().map(|piece| piece.map(|p| chord_cost[p])).sum::<Option<f64>>()?;
+                let rank = sorted_counts.partition_point(|&c| c >= *count);
+                let saving = count * (pieces_cost - chord_costs[rank]) - shift[rank];
+                (saving > 0.0).then_some((saving, i))
+            })
+            .collect();
+        scored.sort_by(|(s1, i1), (s2, i2)| s2.total_cmp(s1).then(i1.cmp(i2)));
+        // strings which overlap compete for the same occurrences, so only one of them is added per round
+        let mut added: Vec<(String, f64)> = Vec::new();
+        for (saving, i) in scored {
+            let ngram = &ngrams[i].0;
+            if added.len() == room {
+                break;
+            }
+            if !added.iter().any(|(a, _)| a.contains(ngram.as_str()) || ngram.contains(a.as_str())) {
+                added.push((ngram.clone(), saving));
+            }
+        }
+        if added.is_empty() {
+            break;
+        }
+        outputs.extend(added.iter().map(|(ngram, _)| ngram.clone()));
+        chosen.extend(added);
+
+        // the counts were of all the occurrences, but the segmentation can use a string much less often (e.g. if a longer
+        // one covers most of them). the strings which end up too rare are dropped, and aren't considered again
+        let stats = TextStatistics::from_text(text, &outputs);
+        let too_rare: Vec<String> = chosen.iter()
+            .filter(|(ngram, _)| stats.count_of(ngram) < config.min_count)
+            .map(|(ngram, _)| ngram.clone())
+            .collect();
+        outputs.retain(|o| !too_rare.contains(o));
+        chosen.retain(|(ngram, _)| !too_rare.contains(ngram));
+        rejected.extend(too_rare);
+    }
+
+    let stats = TextStatistics::from_text(text, &outputs);
+    let mut chosen: Vec<MulticharOutput> = chosen.into_iter().map(|(output, saving)| {
+        let count = stats.count_of(&output);
+        MulticharOutput { output, count, saving }
+    }).collect();
+    chosen.sort_by(|a, b| b.count.total_cmp(&a.count).then(a.output.cmp(&b.output)));
+    (outputs, chosen)
+}
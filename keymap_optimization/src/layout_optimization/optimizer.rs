@@ -0,0 +1,347 @@
+use std::error::Error;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::keyboard_config::{Chord, Key, Layout};
+use crate::chord_preferences::confusion::ConfusionModel;
+use super::corpus::TextStatistics;
+use super::constraints::LayoutConstraints;
+
+// choosing which chord produces each output, in the vocabulary format of ChordTrialUtils::get_vocab.
+// the cost of a layout is the average cost of the transitions between chords needed to type the corpus, plus a penalty for
+// the confusions it makes likely to produce plausible-but-wrong text: if chord A is often typed when B was intended and
+// both are assigned outputs, the typo produces real-looking text, which (unlike typing a chord with no output) is easy to
+// miss. the penalty for B's output being replaced by A's is the probability of the confusion times how plausible A's
+// output is in the places where B's appears (the fraction of B's occurrences whose preceding output is also seen before A's).
+// the layout is optimized by simulated annealing over the assignments of the outputs to a fixed set of candidate chords,
+// subject to the LayoutConstraints.
+
+pub trait ChordCostModel<K: Key, const N: usize, L: Layout<K, N>> {
+    // the cost (e.g. the expected time, plus something for the chance of errors) of typing `to` right after `from`
+    fn transition_cost(&self, from: &Chord<K, N, L>, to: &Chord<K, N, L>) -> f64;
+
+    // the costs between all pairs of chords, indexed [from][to]. models which can evaluate many pairs at once faster
+    // than one at a time (e.g. neural networks) should override this
+    fn transition_costs(&self, chords: &[Chord<K, N, L>]) -> Vec<Vec<f64>> {
+        chords.iter().map(|from| chords.iter().map(|to| self.transition_cost(from, to)).collect()).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OptimizerConfig {
+    pub n_iterations: usize,
+    // the temperature decreases geometrically from initial_temperature to final_temperature. costs are per output typed,
+    // so the temperatures are on the scale of the transition costs
+    pub initial_temperature: f64,
+    pub final_temperature: f64,
+    // how much the confusion penalty counts relative to the transition costs
+    pub confusion_weight: f64,
+}
+
+impl Default for OptimizerConfig {
+    fn default() -> Self {
+        Self { n_iterations: 100_000, initial_temperature: 1e-2, final_temperature: 1e-5, confusion_weight: 1.0 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LayoutCost {
+    // both are per output typed
+    pub transition: f64,
+    pub confusion: f64,
+    pub total: f64,  // transition + confusion_weight * confusion
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct OptimizedLayout<K: Key, const N: usize, L: Layout<K, N>> {
+    pub vocab: Vec<(Chord<K, N, L>, String)>,
+    pub cost: LayoutCost,
+    // the number of outputs of the base layout which were given different chords
+    #[serde(default)]
+    pub n_changed: usize,
+}
+
+pub struct LayoutObjective<K: Key, const N: usize, L: Layout<K, N>> {
+    stats: TextStatistics,
+    candidates: Vec<Chord<K, N, L>>,
+    transition_costs: Vec<Vec<f64>>,  // [from candidate][to candidate]
+    confusions: Vec<Vec<(usize, f64)>>,  // for each candidate, the candidates which may be typed instead and their probabilities
+    confused_with: Vec<Vec<usize>>,  // for each candidate, the candidates which it may be typed instead of
+    plausibility: Vec<Vec<f64>>,  // [intended output][typed output]
+    bigrams_of: Vec<Vec<usize>>,  // for each output, the indices of the bigrams it's part of
+    total_count: f64,
+    confusion_weight: f64,
+    pinned: Vec<Option<usize>>,  // for each output, the candidate it's pinned to, if any
+    base_chord: Vec<Option<usize>>,  // for each output, its candidate in the base layout, if any (None if it isn't a candidate)
+    in_base: Vec<bool>,  // for each output, whether it's in the base layout
+    max_changes: Option<usize>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> LayoutObjective<K, N, L> {
+    pub fn new<C: ChordCostModel<K, N, L>, M: ConfusionModel<K, N, L>>(mut stats: TextStatistics, candidates: Vec<Chord<K, N, L>>, costs: &C, confusion_model: Option<&M>, confusion_weight: f64, constraints: &LayoutConstraints<K, N, L>) -> Result<Self, Box<dyn Error>> {
+        constraints.check()?;
+        // the candidates are the allowed ones, plus the pinned chords and the allowed chords of the base layout
+        let mut candidates: Vec<Chord<K, N, L>> = candidates.into_iter().filter(|c| constraints.allows(c)).collect();
+        for (chord, _) in constraints.pinned.iter().chain(constraints.base_layout.iter().filter(|(c, _)| constraints.allows(c))) {
+            if !candidates.contains(chord) {
+                candidates.push(chord.clone());
+            }
+        }
+        // and the outputs include the pinned outputs and those of the base layout, even if they aren't in the text
+        for (_, output) in constraints.pinned.iter().chain(&constraints.base_layout) {
+            stats.add_output(output);
+        }
+        let position = |chord: &Chord<K, N, L>| candidates.iter().position(|c| c == chord);
+        let output_chord = |layout: &[(Chord<K, N, L>, String)], output: &String| layout.iter().find(|(_, o)| o == output).and_then(|(c, _)| position(c));
+        let pinned = stats.outputs.iter().map(|o| output_chord(&constraints.pinned, o)).collect();
+        let base_chord = stats.outputs.iter().map(|o| output_chord(&constraints.base_layout, o)).collect();
+        let in_base = stats.outputs.iter().map(|o| constraints.base_layout.iter().any(|(_, b)| b == o)).collect();
+
+        if candidates.len() < stats.outputs.len() {
+            return Err(format!("{} candidate chords aren't enough for {} outputs", candidates.len(), stats.outputs.len()).into());
+        }
+        let transition_costs = costs.transition_costs(&candidates);
+        // confusions with chords which aren't candidates can't produce any output, so they're never penalized
+        let confusions: Vec<Vec<(usize, f64)>> = candidates.iter().map(|intended| match confusion_model {
+            Some(model) => model.confusions(intended).into_iter()
+                .filter_map(|(typed, p)| candidates.iter().position(|c| *c == typed).map(|idx| (idx, p)))
+                .collect(),
+            None => Vec::new(),
+        }).collect();
+        let mut confused_with = vec![Vec::new(); candidates.len()];
+        for (intended, typed) in confusions.iter().enumerate() {
+            for &(t, _) in typed {
+                confused_with[t].push(intended);
+            }
+        }
+        let plausibility = Self::plausibility(&stats);
+        let mut bigrams_of = vec![Vec::new(); stats.outputs.len()];
+        for (idx, &(a, b, _)) in stats.bigrams.iter().enumerate() {
+            bigrams_of[a].push(idx);
+            if b != a {
+                bigrams_of[b].push(idx);
+            }
+        }
+        let total_count = stats.total_count().max(1.0);
+        let objective = Self { stats, candidates, transition_costs, confusions, confused_with, plausibility, bigrams_of, total_count, confusion_weight,
+                               pinned, base_chord, in_base, max_changes: constraints.max_changes };
+        let n_changed = objective.n_changed(&objective.initial_assignment());
+        if let Some(max_changes) = objective.max_changes.filter(|&max_changes| n_changed > max_changes) {
+            return Err(format!("{} outputs of the base layout have to move (e.g. because their chords are forbidden), but at most {} may change", n_changed, max_changes).into());
+        }
+        Ok(objective)
+    }
+
+    fn changed(&self, output: usize, chord: usize) -> bool {
+        // whether the output is in the base layout and would be on a different chord
+        self.in_base[output] && self.base_chord[output] != Some(chord)
+    }
+
+    fn n_changed(&self, chord_of: &[usize]) -> usize {
+        chord_of.iter().enumerate().filter(|&(output, &chord)| self.changed(output, chord)).count()
+    }
+
+    fn initial_assignment(&self) -> Vec<usize> {
+        // the pinned outputs go on their chords and the outputs of the base layout stay on theirs (where they're allowed to).
+        // the rest go on the free chords which are cheapest to repeat, most frequent first
+        let n_outputs = self.stats.outputs.len();
+        let mut chord_of: Vec<Option<usize>> = self.pinned.clone();
+        let mut taken = vec![false; self.candidates.len()];
+        for chord in chord_of.iter().flatten() {
+            taken[*chord] = true;
+        }
+        for (assigned, base_chord) in chord_of.iter_mut().zip(&self.base_chord) {
+            if let (None, Some(chord)) = (*assigned, *base_chord) {
+                if !taken[chord] {
+                    *assigned = Some(chord);
+                    taken[chord] = true;
+                }
+            }
+        }
+        let mut by_frequency: Vec<usize> = (0..n_outputs).filter(|&output| chord_of[output].is_none()).collect();
+        by_frequency.sort_by(|&a, &b| self.stats.counts[b].total_cmp(&self.stats.counts[a]));
+        let mut by_cost: Vec<usize> = (0..self.candidates.len()).filter(|&chord| !taken[chord]).collect();
+        by_cost.sort_by(|&a, &b| self.transition_costs[a][a].total_cmp(&self.transition_costs[b][b]));
+        for (output, chord) in by_frequency.into_iter().zip(by_cost) {
+            chord_of[output] = Some(chord);
+        }
+        // this unwrap is safe because there are at least as many candidates as outputs, so every output got a chord
+        chord_of.into_iter().map(|chord| chord.unwrap()).collect()
+    }
+
+    fn plausibility(stats: &TextStatistics) -> Vec<Vec<f64>> {
+        // plausibility[a][b] is the fraction of the occurrences of a after another output where b has also been seen after
+        // that output. outputs which are never preceded by anything give no evidence either way, so everything is plausible.
+        // the bigrams are sparse, so we go through each output's predecessors and their other successors rather than all triples
+        let n = stats.outputs.len();
+        let mut successors: Vec<Vec<(usize, f64)>> = vec![Vec::new(); n];
+        let mut predecessors: Vec<Vec<usize>> = vec![Vec::new(); n];
+        let mut preceded = vec![0.0; n];
+        for &(a, b, count) in &stats.bigrams {
+            successors[a].push((b, count));
+            predecessors[b].push(a);
+            preceded[b] += count;
+        }
+        let mut plausibility = vec![vec![0.0; n]; n];
+        for b in 0..n {
+            for &p in &predecessors[b] {
+                for &(a, count) in &successors[p] {
+                    plausibility[a][b] += count;
+                }
+            }
+        }
+        for (row, preceded) in plausibility.iter_mut().zip(preceded) {
+            for p in row.iter_mut() {
+                *p = if preceded == 0.0 { 1.0 } else { *p / preceded };
+            }
+        }
+        plausibility
+    }
+
+    pub fn candidates(&self) -> &[Chord<K, N, L>] {
+        &self.candidates
+    }
+
+    pub fn n_outputs(&self) -> usize {
+        self.stats.outputs.len()
+    }
+
+    fn bigram_cost(&self, chord_of: &[usize], bigram: usize) -> f64 {
+        let (a, b, count) = self.stats.bigrams[bigram];
+        count * self.transition_costs[chord_of[a]][chord_of[b]]
+    }
+
+    fn confusion_cost(&self, chord_of: &[usize], output_of: &[Option<usize>], output: usize) -> f64 {
+        self.stats.counts[output] * self.confusions[chord_of[output]].iter()
+            .filter_map(|&(typed, p)| output_of[typed].map(|typed_output| p * self.plausibility[output][typed_output]))
+            .sum::<f64>()
+    }
+
+    fn normalize(&self, transition: f64, confusion: f64) -> LayoutCost {
+        let (transition, confusion) = (transition / self.total_count, confusion / self.total_count);
+        LayoutCost { transition, confusion, total: transition + self.confusion_weight * confusion }
+    }
+
+    fn cost(&self, chord_of: &[usize], output_of: &[Option<usize>]) -> (f64, f64) {
+        // the unnormalized transition and confusion costs. chord_of[output] is the candidate assigned to the output,
+        // and output_of is its inverse
+        let transition = (0..self.stats.bigrams.len()).map(|bigram| self.bigram_cost(chord_of, bigram)).sum();
+        let confusion = (0..chord_of.len()).map(|output| self.confusion_cost(chord_of, output_of, output)).sum();
+        (transition, confusion)
+    }
+
+    fn affected(&self, output_of: &[Option<usize>], chords: [usize; 2]) -> (Vec<usize>, Vec<usize>) {
+        // the bigrams and the confusion terms (indexed by intended output) which can change when the outputs of these chords
+        // change: the ones involving those outputs, and the confusions of other outputs into those chords
+        let moved: Vec<usize> = chords.iter().filter_map(|&c| output_of[c]).collect();
+        let mut bigrams: Vec<usize> = moved.iter().flat_map(|&o| self.bigrams_of[o].iter().copied()).collect();
+        bigrams.sort_unstable();
+        bigrams.dedup();
+        let mut outputs: Vec<usize> = chords.iter()
+            .flat_map(|&c| self.confused_with[c].iter().filter_map(|&intended| output_of[intended]))
+            .chain(moved)
+            .collect();
+        outputs.sort_unstable();
+        outputs.dedup();
+        (bigrams, outputs)
+    }
+
+    fn local_cost(&self, chord_of: &[usize], output_of: &[Option<usize>], (bigrams, outputs): &(Vec<usize>, Vec<usize>)) -> (f64, f64) {
+        (bigrams.iter().map(|&bigram| self.bigram_cost(chord_of, bigram)).sum(),
+         outputs.iter().map(|&output| self.confusion_cost(chord_of, output_of, output)).sum())
+    }
+
+    fn inverse(&self, chord_of: &[usize]) -> Vec<Option<usize>> {
+        let mut output_of = vec![None; self.candidates.len()];
+        for (output, &chord) in chord_of.iter().enumerate() {
+            output_of[chord] = Some(output);
+        }
+        output_of
+    }
+
+    fn assignment(&self, vocab: &[(Chord<K, N, L>, String)]) -> Result<Vec<usize>, Box<dyn Error>> {
+        // the candidate assigned to each output by vocab, which must assign all the outputs to distinct candidates
+        let chord_of = self.stats.outputs.iter().map(|output| {
+            let (chord, _) = vocab.iter().find(|(_, o)| o == output).ok_or_else(|| format!("no chord produces {:?}", output))?;
+            self.candidates.iter().position(|c| c == chord).ok_or_else(|| format!("the chord for {:?} isn't a candidate", output).into())
+        }).collect::<Result<Vec<usize>, Box<dyn Error>>>()?;
+        if self.inverse(&chord_of).iter().flatten().count() < chord_of.len() {
+            return Err("the same chord is assigned to several outputs".into());
+        }
+        Ok(chord_of)
+    }
+
+    fn vocab(&self, chord_of: &[usize]) -> Vec<(Chord<K, N, L>, String)> {
+        chord_of.iter().zip(&self.stats.outputs).map(|(&chord, output)| (self.candidates[chord].clone(), output.clone())).collect()
+    }
+
+    pub fn score(&self, vocab: &[(Chord<K, N, L>, String)]) -> Result<LayoutCost, Box<dyn Error>> {
+        let chord_of = self.assignment(vocab)?;
+        let (transition, confusion) = self.cost(&chord_of, &self.inverse(&chord_of));
+        Ok(self.normalize(transition, confusion))
+    }
+
+    pub fn optimize(&self, config: &OptimizerConfig, seed: u64) -> OptimizedLayout<K, N, L> {
+        // we start from initial_assignment and anneal. each step moves an output which isn't pinned to another candidate,
+        // swapping with the output already there (if any, and if it isn't pinned), as long as this doesn't change more
+        // outputs of the base layout than allowed
+        let mut rng = StdRng::seed_from_u64(seed);
+        let movable: Vec<usize> = (0..self.stats.outputs.len()).filter(|&output| self.pinned[output].is_none()).collect();
+        let mut chord_of = self.initial_assignment();
+        let mut n_changed = self.n_changed(&chord_of);
+        let mut output_of = self.inverse(&chord_of);
+        // the costs are updated by recomputing only the terms each move can change
+        let (mut transition, mut confusion) = self.cost(&chord_of, &output_of);
+        let mut cost = self.normalize(transition, confusion);
+        let (mut best_chord_of, mut best_cost) = (chord_of.clone(), cost);
+
+        if !movable.is_empty() && self.candidates.len() > 1 {
+            let cooling = (config.final_temperature / config.initial_temperature).powf(1.0 / config.n_iterations.max(1) as f64);
+            let mut temperature = config.initial_temperature;
+            for _ in 0..config.n_iterations {
+                let output = movable[rng.gen_range(0..movable.len())];
+                let (old_chord, new_chord) = (chord_of[output], rng.gen_range(0..self.candidates.len()));
+                let displaced = output_of[new_chord];
+                let new_n_changed = n_changed + displaced.map_or(0, |d| self.changed(d, old_chord) as usize) + self.changed(output, new_chord) as usize
+                                    - displaced.map_or(0, |d| self.changed(d, new_chord) as usize) - self.changed(output, old_chord) as usize;
+                let allowed = displaced.is_none_or(|d| self.pinned[d].is_none()) && self.max_changes.is_none_or(|max_changes| new_n_changed <= max_changes);
+                if new_chord != old_chord && allowed {
+                    let affected = self.affected(&output_of, [old_chord, new_chord]);
+                    let (transition_before, confusion_before) = self.local_cost(&chord_of, &output_of, &affected);
+                    Self::apply_move(&mut chord_of, &mut output_of, output, old_chord, new_chord, displaced);
+                    let (transition_after, confusion_after) = self.local_cost(&chord_of, &output_of, &affected);
+                    let new_transition = transition + transition_after - transition_before;
+                    let new_confusion = confusion + confusion_after - confusion_before;
+                    let new_cost = self.normalize(new_transition, new_confusion);
+                    let delta = new_cost.total - cost.total;
+                    if delta <= 0.0 || rng.gen::<f64>() < (-delta / temperature).exp() {
+                        (transition, confusion, cost, n_changed) = (new_transition, new_confusion, new_cost, new_n_changed);
+                        if cost.total < best_cost.total {
+                            (best_chord_of, best_cost) = (chord_of.clone(), cost);
+                        }
+                    } else {
+                        // undo the move
+                        Self::apply_move(&mut chord_of, &mut output_of, output, new_chord, old_chord, displaced);
+                    }
+                }
+                temperature *= cooling;
+            }
+        }
+        // recompute the cost from scratch, so that it doesn't include any rounding errors from the updates
+        let (transition, confusion) = self.cost(&best_chord_of, &self.inverse(&best_chord_of));
+        OptimizedLayout { vocab: self.vocab(&best_chord_of), cost: self.normalize(transition, confusion), n_changed: self.n_changed(&best_chord_of) }
+    }
+
+    fn apply_move(chord_of: &mut [usize], output_of: &mut [Option<usize>], output: usize, from: usize, to: usize, displaced: Option<usize>) {
+        // move output from one candidate to another, and the output displaced from `to` (if any) the other way
+        chord_of[output] = to;
+        output_of[to] = Some(output);
+        output_of[from] = displaced;
+        if let Some(d) = displaced {
+            chord_of[d] = from;
+        }
+    }
+}
@@ -1,8 +1,9 @@
 pub mod keyboard_config;
 pub mod keyboard_config_implementations;
 pub mod chord_preferences;
+pub mod layout_optimization;
 
-pub mod local_env;
+pub mod data_store;
 
 #[cfg(test)]
 mod tests;
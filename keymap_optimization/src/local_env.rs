@@ -1 +0,0 @@
-pub const DATA_PATH: &str = "../data";
@@ -1,11 +1,20 @@
 #![cfg(test)]
 
-use crate::keyboard_config::{Chord, ChordTrialUtils, GraphicalChord, Layout};
-use crate::twiddler::{chord_list_to_config_object, random_chord_, Node, TwiddlerKey as K, TwiddlerChord, TwiddlerLayout as L, TwiddlerChordTrialUtils as C, TwiddlerExponentialSampler, RESERVED, USB_HID_COUNT};
-use crate::chord_preferences::gather_chords::{TrialResults, TrialData, ErrCode, align, best_candidate, Direction, Performance};
+use crate::keyboard_config::{Chord, ChordTrialUtils, DecodedToken, GraphicalChord, Key, Layout, Finger, LineEvent, StreamDecoder, TypedChord, sample_without_replacement};
+use crate::twiddler::{chord_list_to_config_object, random_chord_, Node, TwiddlerKey as K, TwiddlerChord, TwiddlerLayout as L, TwiddlerChordTrialUtils as C, TwiddlerExponentialSampler, CodeConfig, check_code, config_differences, MAX_CHORDS, MAX_MULTICHAR_CHORDS, RESERVED, USB_HID_COUNT};
+use crate::chord_preferences::gather_chords::{TrialResults, TrialData, ErrCode, align, as_typed, compute_accuracy, best_candidate, Direction, Performance, traceback, classify_errors, AlignedPair, TypingError, check_calibration, N_CALIBRATION_CHORDS};
 use crate::chord_preferences::data_collection_keymap_gen::gen_random_config_with_trial_decoder;
+use crate::chord_preferences::data_cleaning::{find_outliers, OutlierConfig, OutlierReason};
+use crate::chord_preferences::session::{self, load_decoder, open_package, write_package, Provenance};
+use crate::data_store::{resolve_root, Artifact, DataStore, DEFAULT_DATA_DIR, STORE_CONFIG_FILE};
+use crate::chord_preferences::confusion::{ConfusionModel, ConfusionTable};
+use crate::chord_preferences::practice::{prompts_from_corpus, score_typing, summarize_transitions, words_per_minute, PracticeConfig, PracticeLine, PracticeLog, PracticeProgress, MAX_TYPED_OUTPUTS};
+use crate::layout_optimization::corpus::{Segmenter, TextStatistics};
+use crate::layout_optimization::optimizer::{ChordCostModel, LayoutObjective, OptimizerConfig};
+use crate::layout_optimization::constraints::{chord_from_keys, ConstraintSpec, LayoutConstraints};
+use crate::layout_optimization::multichar::{choose_multichar, ngram_counts, MulticharConfig};
 use twidlk_rust::{generate_text_config, read_config};
-use rand::{thread_rng, Rng, rngs::ThreadRng};
+use rand::{thread_rng, Rng, seq::SliceRandom, rngs::{ThreadRng, StdRng}};
 use strum::{EnumCount, VariantArray};
 
 struct TempFile {
@@ -43,6 +52,36 @@ impl Drop for TempFile {
     }
 }
 
+struct TempDir {
+    path: String,
+}
+
+impl TempDir {
+    fn new(unique_id: &str) -> TempDir {
+        // like TempFile, this only makes the name; the random part keeps runs of the same test apart
+        TempDir{
+            path: format!("/tmp/{}_{}", unique_id, thread_rng().gen::<u64>())
+        }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        const PERFORM_DROP: bool = true;
+        if !PERFORM_DROP {
+            return;
+        }
+
+        if !std::path::Path::new(&self.path).exists() {
+            return;
+        }
+        match std::fs::remove_dir_all(&self.path) {
+            Ok(_) => (),
+            Err(e) => eprintln!("Error deleting temporary directory: {}", e)
+        }
+    }
+}
+
 macro_rules! run_n_times {
     ($n:literal, $(#[$meta:meta])* $vis:vis fn $name:ident$(<$($($gen_arg:ident)*: $gen_trait:path),*>)?($($arg:ident: $typ:ty),*) $(-> $ret:ty)? $(where $($b:path: $d:path),*)? $body:block) => {
         paste::paste! {
@@ -56,6 +95,27 @@ macro_rules! run_n_times {
     };
 }
 
+type S = TwiddlerExponentialSampler<StdRng>;
+
+// calls a ChordTrialUtils method of a decoder made by random_decoder
+macro_rules! decoder_utils {
+    ($method:ident($($arg:expr),*)) => {
+        <C as ChordTrialUtils<K, { K::COUNT }, L, StdRng, (), S>>::$method($($arg),*)
+    };
+}
+
+fn random_decoder_with_code(code_config: &CodeConfig, seed: u64) -> (Vec<u8>, C) {
+    gen_random_config_with_trial_decoder::<K, { K::COUNT }, L, (), S, C>(&(), code_config, seed).unwrap()
+}
+
+fn random_decoder(seed: u64) -> (Vec<u8>, C) {
+    random_decoder_with_code(&CodeConfig::default(), seed)
+}
+
+fn vocab(decoder: &C) -> &Vec<(TwiddlerChord, String)> {
+    decoder_utils!(get_vocab(decoder))
+}
+
 fn make_demo_trial<R: Rng> (rng: &mut R, threshold: f64, impossible_threshold: f64) -> TrialData<K, { K::COUNT }, L> {
     let n_repetitions_per_trial = rng.gen_range(1..10);  // this will actually be fixed in practice, but doesn't hurt to vary it here
     // sometimes get a set of chord input randomly sampled to resemble the expected chords;
@@ -74,20 +134,21 @@ fn make_demo_trial<R: Rng> (rng: &mut R, threshold: f64, impossible_threshold: f
                 if rng.gen::<f64>() > del_prob {  // < del_prob is a deletion--don't add any input chord corresponding to this expected chord
                     loop {  // insert a geometric distribution number of random chords
                         if rng.gen::<f64>() < ins_prob {
-                            input.push(random_chord_(rng, threshold));
+                            input.push(random_chord_(rng, threshold).into());
                         } else {
                             break;
                         }
                     }
                     // insert the chord corresponding to the expected chord, perhaps with an error
+                    // (some mistakes are text which isn't the output of any chord)
                     if rng.gen::<f64>() < sub_prob {
-                        input.push(random_chord_(rng, threshold));
+                        input.push(if rng.gen::<f64>() < 0.2 { TypedChord::Invalid { invalid: "\t".to_string() } } else { random_chord_(rng, threshold).into() });
                     } else {
-                        input.push(chord_pair[i % 2].clone());
+                        input.push(chord_pair[i % 2].clone().into());
                     }
                 }
             }
-            Ok(Performance { input, time: TIME_RANGE * rng.gen::<f64>() })
+            Ok(Performance { input, time: TIME_RANGE * rng.gen::<f64>(), errors: None })
         }
     };
     
@@ -220,8 +281,7 @@ fn serialization_round_trip_flip_key() {
         }
         let chord_idx = rng.gen_range(0..2);
         let key_idx = rng.gen_range(0..K::COUNT);
-        let chord_keys = &mut demo_results.data[idx].chord_pair[chord_idx].get_raw_keys();
-        chord_keys[key_idx] = !chord_keys[key_idx];
+        demo_results.data[idx].chord_pair[chord_idx].toggle_raw_key(key_idx);
         Ok(())
     }
     let unique_id = &format!("test_file_{}", line!());
@@ -255,7 +315,7 @@ fn serialization_round_trip_toggle_input_error() {
         }
         demo_results.data[idx].performance = match demo_results.data[idx].performance {
             Ok(_) => Err(ErrCode::Impossible),
-            Err(ErrCode::Impossible) => Ok(Performance { input: Vec::new(), time: 0.0 }),
+            Err(ErrCode::Impossible) => Ok(Performance { input: Vec::new(), time: 0.0, errors: None }),
         };
         Ok(())
     }
@@ -273,8 +333,8 @@ fn serialization_round_trip_change_time() {
             return Err("no trials");
         }
         demo_results.data[idx].performance = match &demo_results.data[idx].performance {
-            Ok(v) => Ok(Performance { input: v.input.clone(), time: 100.0 * rng.gen::<f64>() }),
-            Err(ErrCode::Impossible) => Ok(Performance { input: Vec::new(), time: 100.0 * rng.gen::<f64>() }),
+            Ok(v) => Ok(Performance { input: v.input.clone(), time: 100.0 * rng.gen::<f64>(), errors: None }),
+            Err(ErrCode::Impossible) => Ok(Performance { input: Vec::new(), time: 100.0 * rng.gen::<f64>(), errors: None }),
         };
         Ok(())
     }
@@ -324,7 +384,7 @@ fn serialization_round_trip_change_input() {
                     } else {
                         println!("replacing range {}-{} with [{}]", range.start, range.end, slice.clone().into_iter().map(|c| format!("{}", c)).collect::<Vec<String>>().join(", "));
                     }
-                    v.input.splice(range, slice);
+                    v.input.splice(range, as_typed(&slice));
                     if rng.gen::<f64>() < 0.5 {
                         break;
                     }
@@ -335,7 +395,7 @@ fn serialization_round_trip_change_input() {
         
             Err(ErrCode::Impossible) => {  // toggling between error and result is specifically tested above
                 println!("input was error");
-                demo_results.data[idx].performance = Ok(Performance { input: vec![], time: 0.0 })
+                demo_results.data[idx].performance = Ok(Performance { input: vec![], time: 0.0, errors: None })
               },
         };
         Ok(())
@@ -411,18 +471,38 @@ fn index_usb_hid_conversion() {
 run_n_times! {10,
 #[test]
 fn make_config_and_decoder() {
-    match gen_random_config_with_trial_decoder::<K, { K::COUNT }, L, (), TwiddlerExponentialSampler<ThreadRng>, C>(&()) {
+    match gen_random_config_with_trial_decoder::<K, { K::COUNT }, L, (), TwiddlerExponentialSampler<StdRng>, C>(&(), &CodeConfig::default(), thread_rng().gen()) {
         Ok(_) => (),
         Err(e) => assert!(false, "Error generating config: {}", e)
     }
 }
 }
 
+run_n_times! {10,
+#[test]
+fn sampling_without_replacement() {
+    // the samples should be distinct, and items with weight 0 should only be chosen once all the others have been
+    let mut rng = thread_rng();
+    let weighted_items: Vec<(usize, f64)> = (0..20).map(|i| (i, if i < 10 { rng.gen::<f64>() + 0.01 } else { 0.0 })).collect();
+    let mut sampled = sample_without_replacement(&mut rng, &weighted_items, 10).unwrap();
+    sampled.sort();
+    assert_eq!(sampled, (0..10).collect::<Vec<usize>>());
+
+    let all_sampled = sample_without_replacement(&mut rng, &weighted_items, 20).unwrap();
+    assert_eq!(all_sampled.len(), 20);
+    assert!(sample_without_replacement(&mut rng, &weighted_items, 21).is_err());
+    // weights which would skew the sampling are refused
+    for bad_weight in [-1.0, f64::NAN, f64::INFINITY] {
+        assert!(sample_without_replacement(&mut rng, &[(0, 1.0), (1, bad_weight)], 1).is_err());
+    }
+}
+}
+
 run_n_times! {10,
 #[test]
 fn config_round_trip() {
-    let (config_bin, chord_trial_utils) = gen_random_config_with_trial_decoder::<K, { K::COUNT }, L, (), TwiddlerExponentialSampler<ThreadRng>, C>(&()).unwrap();
-    let twidlk_config = chord_list_to_config_object(<C as ChordTrialUtils<K, 16, L, ThreadRng, (), TwiddlerExponentialSampler<ThreadRng>>>::get_vocab(&chord_trial_utils).clone()).unwrap();
+    let (config_bin, chord_trial_utils) = random_decoder(thread_rng().gen());
+    let twidlk_config = chord_list_to_config_object(vocab(&chord_trial_utils).clone()).unwrap();
     let original_text_config = generate_text_config(&twidlk_config).unwrap();
     println!("original config:\n{}", original_text_config);
 
@@ -433,6 +513,309 @@ fn config_round_trip() {
 }
 }
 
+run_n_times! {5,
+#[test]
+fn config_reproducible_from_seed() {
+    // generating a config twice from the same seed should give the same config and vocabulary, and record the seed
+    let seed: u64 = thread_rng().gen();
+    let (config_1, decoder_1) = random_decoder(seed);
+    let (config_2, decoder_2) = random_decoder(seed);
+    assert_eq!(config_1, config_2);
+    assert_eq!(vocab(&decoder_1), vocab(&decoder_2));
+    assert_eq!(decoder_utils!(get_seed(&decoder_1)), Some(seed));
+}
+}
+
+#[test]
+fn trial_code_design() {
+    let outputs = |config: &CodeConfig| -> Vec<String> {
+        let (_, decoder) = random_decoder_with_code(config, thread_rng().gen());
+        vocab(&decoder).iter().map(|(_, output)| output.clone()).collect()
+    };
+
+    // the default code fills the twiddler's multi-character strings, and leaves out the dead keys
+    let default_outputs = outputs(&CodeConfig::default());
+    assert!(check_code(&default_outputs, MAX_CHORDS as usize, MAX_MULTICHAR_CHORDS as usize).is_ok());
+    assert_eq!(default_outputs.iter().filter(|output| output.len() > 1).count(), MAX_MULTICHAR_CHORDS as usize);
+    assert!(default_outputs.iter().all(|output| !output.contains(['`', '~', '^', '\'', '"'])));
+
+    // with three characters and room for four pairs, one character is a prefix, which is the first of the cheapest
+    let config = CodeConfig { alphabet: Some("ab1".to_string()), max_multichar: 4, ..CodeConfig::default() };
+    assert_eq!(outputs(&config), vec!["b", "1", "aa", "ab", "a1"]);
+    // shifted characters cost more, so the pairs start with the unshifted one
+    let config = CodeConfig { alphabet: Some("aB".to_string()), max_multichar: 2, shift_cost: 1.0, ..CodeConfig::default() };
+    assert_eq!(outputs(&config), vec!["B", "aa", "aB"]);
+
+    assert!(check_code(&["a".to_string(), "ab".to_string()], 10, 10).is_err());
+    assert!(check_code(&["a".to_string(), "ba".to_string(), "bb".to_string()], 10, 1).is_err());
+}
+
+run_n_times! {5,
+#[test]
+fn decoder_lookups_after_loading() {
+    // the index is rebuilt when a decoder is loaded, and finds every entry of the vocabulary both ways
+    let (_, decoder) = random_decoder(thread_rng().gen());
+    let loaded: C = serde_json::from_str(&serde_json::to_string(&decoder).unwrap()).unwrap();
+    let loaded_vocab = vocab(&loaded);
+    assert_eq!(loaded_vocab, vocab(&decoder));
+    for (chord, string) in loaded_vocab {
+        assert_eq!(decoder_utils!(lookup_chord(&loaded, chord)).as_ref(), Some(string));
+        assert_eq!(decoder_utils!(lookup_string(&loaded, string)).as_ref(), Some(chord));
+    }
+    assert_eq!(decoder_utils!(lookup_string(&loaded, "not in the vocabulary")), None);
+}
+}
+
+run_n_times! {5,
+#[test]
+fn trial_string_parses_to_its_chords() {
+    // the outputs of some random entries of a decoder's vocabulary, typed one after another, parse back to their chords
+    let rng = &mut thread_rng();
+    let (_, decoder) = random_decoder(rng.gen());
+    let decoder_vocab = vocab(&decoder);
+    let entries: Vec<&(TwiddlerChord, String)> = (0..20).map(|_| &decoder_vocab[rng.gen_range(0..decoder_vocab.len())]).collect();
+    let text: String = entries.iter().map(|(_, string)| string.as_str()).collect();
+    let parsed = decoder_utils!(parse_trial_string(&decoder, &text)).unwrap();
+    assert_eq!(parsed, entries.iter().map(|(chord, _)| chord.clone()).collect::<Vec<TwiddlerChord>>());
+
+    // a stray character (tabs aren't the output of any chord) fails the strict parse, but the tolerant one reads it as an
+    // invalid input, which counts as a mistake
+    let position = rng.gen_range(0..=entries.len());
+    let with_stray: String = entries[..position].iter().map(|(_, string)| string.as_str()).chain(["\t"]).chain(entries[position..].iter().map(|(_, string)| string.as_str())).collect();
+    assert!(decoder_utils!(parse_trial_string(&decoder, &with_stray)).is_err());
+    let tolerant = decoder_utils!(parse_trial_string_tolerant(&decoder, &with_stray));
+    let mut expected = as_typed(&parsed);
+    expected.insert(position, TypedChord::Invalid { invalid: "\t".to_string() });
+    assert_eq!(tolerant, expected);
+    assert!(tolerant[position].is_invalid_input());
+    assert!(compute_accuracy(&tolerant, &parsed) < 1.0);
+
+    // the chords are stored as bare chords, so results from before invalid input was recorded still read, and the invalid
+    // input keeps its text
+    let stored = serde_json::to_string(&tolerant).unwrap();
+    assert_eq!(serde_json::from_str::<Vec<TypedChord<K, { K::COUNT }, L>>>(&serde_json::to_string(&parsed).unwrap()).unwrap(), as_typed(&parsed));
+    assert_eq!(serde_json::from_str::<Vec<TypedChord<K, { K::COUNT }, L>>>(&stored).unwrap(), tolerant);
+}
+}
+
+run_n_times! {3,
+#[test]
+fn config_consistency_checks() {
+    // typing a decoder's calibration chords passes the check, and a config from another seed is caught both ways
+    let rng = &mut thread_rng();
+    let (config, decoder) = random_decoder(rng.gen());
+    let (other_config, other_decoder) = random_decoder(rng.gen());
+    let decoder_vocab = vocab(&decoder);
+    let expected: Vec<TwiddlerChord> = decoder_vocab.choose_multiple(rng, N_CALIBRATION_CHORDS).map(|(chord, _)| chord.clone()).collect();
+    let typed: String = expected.iter().map(|chord| decoder_vocab.iter().find(|(c, _)| c == chord).unwrap().1.as_str()).collect();
+    assert!(check_calibration::<K, 16, L, StdRng, (), S, C>(&decoder, &expected, &typed).is_ok());
+    // with the other config on the keyboard, the same chords output other strings
+    let other_vocab = vocab(&other_decoder);
+    let typed_on_other: String = expected.iter().map(|chord| other_vocab.iter().find(|(c, _)| c == chord).map(|(_, output)| output.as_str()).unwrap_or("\t")).collect();
+    if typed_on_other != typed {
+        assert!(check_calibration::<K, 16, L, StdRng, (), S, C>(&decoder, &expected, &typed_on_other).is_err());
+    }
+
+    assert_eq!(decoder_utils!(get_config(&decoder)).unwrap(), config);
+    assert_eq!(config_differences(&config, &config).unwrap(), (vec![], vec![]));
+    let (only_in_other, only_in_config) = config_differences(&other_config, &config).unwrap();
+    assert!(!only_in_other.is_empty() && !only_in_config.is_empty());
+}
+}
+
+#[test]
+fn session_package_roundtrip() {
+    // a package gives back its decoder and manifest, a decoder file on its own still loads, and a changed file is caught
+    let seed = thread_rng().gen();
+    let package_dir = TempDir::new("session_package");
+    let package_path = package_dir.path.clone();
+    let (config, decoder) = random_decoder(seed);
+    let provenance = Provenance { sampler: "exponential".to_string(), ..Provenance::default() };
+    let manifest = write_package(&package_path, &config, &decoder, seed, provenance.clone()).unwrap();
+    assert!(write_package(&package_path, &config, &decoder, seed, provenance).is_err());
+
+    let (loaded, loaded_manifest): (C, _) = load_decoder(&package_path).unwrap();
+    assert_eq!(loaded_manifest.as_ref(), Some(&manifest));
+    assert_eq!(manifest.seed, seed);
+    assert_eq!(decoder_utils!(get_config(&loaded)).unwrap(), config);
+    assert_eq!(std::fs::read(format!("{}/{}", package_path, session::CONFIG_FILE)).unwrap(), config);
+
+    let decoder_file = format!("{}/{}", package_path, session::DECODER_FILE);
+    let (_, no_manifest): (C, _) = load_decoder(&decoder_file).unwrap();
+    assert_eq!(no_manifest, None);
+
+    std::fs::write(format!("{}/{}", package_path, session::CONFIG_FILE), &config[..config.len() / 2]).unwrap();
+    assert!(open_package(&package_path).is_err());
+    assert!(load_decoder::<C>(&package_path).is_err());
+}
+
+#[test]
+fn data_store_layout_and_lookup() {
+    // new files go in the subdirectory for their kind, and files kept in the root before there were subdirectories are
+    // still listed and found
+    let root_dir = TempDir::new("data_store");
+    let root = root_dir.path.clone();
+    let store = DataStore::new(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    let legacy = format!("{}/chord_preferences_results_1.json", root);
+    std::fs::write(&legacy, "{}").unwrap();
+    std::fs::write(format!("{}/decoder_1.json", root), "{}").unwrap();
+    let new = store.new_path(Artifact::Results, "chord_preferences_results", "json").unwrap();
+    assert!(new.starts_with(&format!("{}/results/chord_preferences_results_", root)) && new.ends_with(".json"));
+    std::fs::write(&new, "{}").unwrap();
+    std::fs::write(store.new_path(Artifact::Results, "practice_log", "json").unwrap(), "{}").unwrap();
+
+    let results = store.list_matching(Artifact::Results, "chord_preferences_results", ".json").unwrap();
+    assert_eq!(results, vec![std::path::PathBuf::from(&legacy), std::path::PathBuf::from(&new)]);
+    assert_eq!(store.list(Artifact::Results).unwrap().len(), 3);
+    assert_eq!(store.list(Artifact::Decoder).unwrap().len(), 1);
+    assert!(store.list(Artifact::Model).unwrap().is_empty());
+    assert_eq!(store.latest(Artifact::Results, "chord_preferences_results").unwrap(), Some(std::path::PathBuf::from(&new)));
+
+    assert_eq!(store.find(Artifact::Decoder, "decoder_1.json"), Some(std::path::PathBuf::from(format!("{}/decoder_1.json", root))));
+    let new_name = std::path::Path::new(&new).file_name().unwrap().to_string_lossy().to_string();
+    assert_eq!(store.find(Artifact::Results, &new_name), Some(std::path::PathBuf::from(&new)));
+    assert_eq!(store.find(Artifact::Results, "missing.json"), None);
+
+    // a file with a fixed name is kept in the subdirectory, which is made even in a new store, unless it was kept in the
+    // root before
+    let fresh = DataStore::new(format!("{}/fresh", root));
+    assert_eq!(fresh.named_path(Artifact::Results, "practice_progress.json").unwrap(), format!("{}/fresh/results/practice_progress.json", root));
+    assert!(fresh.dir(Artifact::Results).is_dir());
+    std::fs::write(format!("{}/practice_progress.json", root), "{}").unwrap();
+    assert_eq!(store.named_path(Artifact::Results, "practice_progress.json").unwrap(), format!("{}/practice_progress.json", root));
+
+    // the root comes from the command line, then the environment, then the nearest store config file, then the default
+    let nested = format!("{}/a/b", root);
+    std::fs::create_dir_all(&nested).unwrap();
+    let nested = std::path::Path::new(&nested);
+    assert_eq!(resolve_root(None, None, nested).unwrap(), nested.join(DEFAULT_DATA_DIR));
+    std::fs::write(format!("{}/{}", root, STORE_CONFIG_FILE), r#"{"data_dir": "stored"}"#).unwrap();
+    assert_eq!(resolve_root(None, None, nested).unwrap(), std::path::Path::new(&root).join("stored"));
+    assert_eq!(resolve_root(None, Some("/env"), nested).unwrap(), std::path::PathBuf::from("/env"));
+    assert_eq!(resolve_root(Some("/cli"), Some("/env"), nested).unwrap(), std::path::PathBuf::from("/cli"));
+}
+
+#[test]
+fn stream_decoder_reads_outputs_as_they_complete() {
+    // a prefix-free vocabulary: each output is read on its last character, and a character which can't continue is invalid
+    let mut decoder = StreamDecoder::new(["a", "ba", "bb", "c"]);
+    assert_eq!(decoder.push('b'), vec![]);
+    assert_eq!(decoder.pending(), "b");
+    assert_eq!(decoder.push('b'), vec![DecodedToken::Output(2)]);
+    assert_eq!(decoder.push('a'), vec![DecodedToken::Output(0)]);
+    assert_eq!(decoder.push('b'), vec![]);
+    assert_eq!(decoder.push('x'), vec![DecodedToken::Invalid("bx".to_string())]);
+    assert_eq!(decoder.push('c'), vec![DecodedToken::Output(3)]);
+    assert_eq!(decoder.push('b'), vec![]);
+    // an incomplete output at the end is invalid
+    assert_eq!(decoder.finish(), vec![DecodedToken::Invalid("b".to_string())]);
+    assert_eq!(decoder.pending(), "");
+
+    // otherwise an output which starts a longer one waits for the next character
+    let mut decoder = StreamDecoder::new(["t", "h", "e", "the", " "]);
+    assert_eq!(decoder.push('t'), vec![]);
+    assert_eq!(decoder.push('h'), vec![]);
+    assert_eq!(decoder.push(' '), vec![DecodedToken::Output(0), DecodedToken::Output(1), DecodedToken::Output(4)]);
+    assert_eq!(decoder.decode("the the"), [3, 4, 3].map(DecodedToken::Output).to_vec());
+    assert_eq!(decoder.decode("tx"), vec![DecodedToken::Output(0), DecodedToken::Invalid("x".to_string())]);
+    // erasing a pending character goes back to the longest output what's left starts with
+    let mut decoder = StreamDecoder::new(["a", "ab", "abc"]);
+    assert!(!decoder.backspace());
+    assert_eq!(decoder.push('a'), vec![]);
+    assert_eq!(decoder.push('b'), vec![]);
+    assert!(decoder.backspace());
+    assert_eq!(decoder.pending(), "a");
+    assert_eq!(decoder.finish(), vec![DecodedToken::Output(0)]);
+
+    // after invalid text, decoding picks up again at the next character which can start an output
+    let mut decoder = StreamDecoder::new(["a", "ba", "bb", "c"]);
+    assert_eq!(decoder.decode("bxa"), vec![DecodedToken::Invalid("bx".to_string()), DecodedToken::Output(0)]);
+    assert_eq!(decoder.decode("xyba!c"), vec![DecodedToken::Invalid("xy".to_string()), DecodedToken::Output(1), DecodedToken::Invalid("!".to_string()), DecodedToken::Output(3)]);
+}
+
+#[test]
+fn stream_decoder_reads_lines_as_they_are_typed() {
+    // input which hands over a byte at a time and counts them, to see when each token comes out
+    struct Typing<'a> {
+        text: &'a [u8],
+        n_read: &'a std::cell::Cell<usize>,
+    }
+    impl std::io::Read for Typing<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n_read = self.n_read.get();
+            if n_read == self.text.len() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.text[n_read];
+            self.n_read.set(n_read + 1);
+            Ok(1)
+        }
+    }
+    let n_read = std::cell::Cell::new(0);
+    let mut input = Typing { text: "bba\u{7f}b\u{7f}bax\u{7f}\u{e9}\r\nQUIT\n\u{7f}c".as_bytes(), n_read: &n_read };
+    let mut decoder = StreamDecoder::new(["a", "ba", "bb", "c", "\u{e9}"]);
+
+    // each token comes out on the character which completes it (the last being two bytes). a backspace (\u{7f}) erases a
+    // pending character quietly, and otherwise takes back the last token, chord or invalid text
+    let mut events = Vec::new();
+    assert_eq!(decoder.decode_line(&mut input, |event| events.push((event, n_read.get()))).unwrap().as_deref(), Some("bbba\u{e9}"));
+    assert_eq!(events, vec![(LineEvent::Token(DecodedToken::Output(2)), 2), (LineEvent::Token(DecodedToken::Output(0)), 3),
+                            (LineEvent::Erased(DecodedToken::Output(0)), 4), (LineEvent::Token(DecodedToken::Output(1)), 8),
+                            (LineEvent::Token(DecodedToken::Invalid("x".to_string())), 9), (LineEvent::Erased(DecodedToken::Invalid("x".to_string())), 10),
+                            (LineEvent::Token(DecodedToken::Output(4)), 12)]);
+    assert_eq!(decoder.decode_line(&mut input, |_| {}).unwrap().as_deref(), Some("QUIT"));
+
+    // a backspace at the start of a line has nothing to erase, and a last line without a line ending is still read, after
+    // which the input has ended
+    let mut events = Vec::new();
+    assert_eq!(decoder.decode_line(&mut input, |event| events.push(event)).unwrap().as_deref(), Some("c"));
+    assert_eq!(events, vec![LineEvent::Token(DecodedToken::Output(3))]);
+    assert_eq!(decoder.decode_line(&mut input, |_| {}).unwrap(), None);
+}
+
+run_n_times! {10,
+#[test]
+fn chord_serializes_as_key_array() {
+    // chords are stored as bitmasks, but written as an array of whether each key is pressed, as in older results files
+    let chord: TwiddlerChord = random_chord_(&mut thread_rng(), 0.5);
+    let json = serde_json::to_value(&chord).unwrap();
+    assert_eq!(json, serde_json::json!({ "keys": chord.to_vector() }));
+    assert_eq!(serde_json::from_value::<TwiddlerChord>(json).unwrap(), chord);
+    assert_eq!(chord.n_keys(), K::VARIANTS.iter().filter(|key| chord.contains(**key)).count());
+}
+}
+
+run_n_times! {100,
+#[test]
+fn chord_set_operations() {
+    // the set operations agree with checking each key
+    let rng = &mut thread_rng();
+    let (a, b): (TwiddlerChord, TwiddlerChord) = (random_chord_(rng, 0.7), random_chord_(rng, 0.7));
+    for key in K::VARIANTS.iter().copied() {
+        assert_eq!(a.union(&b).contains(key), a.contains(key) || b.contains(key));
+        assert_eq!(a.intersection(&b).contains(key), a.contains(key) && b.contains(key));
+        assert_eq!(a.difference(&b).contains(key), a.contains(key) && !b.contains(key));
+        assert_eq!(a.symmetric_difference(&b).contains(key), a.contains(key) != b.contains(key));
+        assert_eq!(K::VARIANTS[key.index()], key);
+    }
+    assert_eq!(a.is_subset(&b), K::VARIANTS.iter().all(|key| !a.contains(*key) || b.contains(*key)));
+    assert!(a.intersection(&b).is_subset(&a) && a.union(&b).is_superset(&b));
+    assert!(a.difference(&a).is_empty());
+
+    // keys lists the keys in order, and collecting them gives the chord back
+    let keys: Vec<K> = a.keys().collect();
+    assert_eq!(keys, K::VARIANTS.iter().filter(|key| a.contains(**key)).copied().collect::<Vec<K>>());
+    assert_eq!(keys.iter().copied().collect::<TwiddlerChord>(), a);
+    let mut removed = a.clone();
+    removed.remove_key(keys[0]);
+    assert!(!removed.contains(keys[0]));
+    assert_eq!(removed.n_keys(), a.n_keys() - 1);
+    removed.remove_key(keys[0]);
+    assert_eq!(removed.n_keys(), a.n_keys() - 1);
+}
+}
+
 #[test]
 fn empty_chord_is_invalid() {
     let chord: TwiddlerChord = Chord::new();
@@ -453,6 +836,20 @@ fn thumb_chord_is_invalid() {
 }
 }
 
+#[test]
+fn fingers_match_rows() {
+    // the thumb keys all belong to the thumb, and each row of main keys belongs to a different finger
+    for key in L::THUMB {
+        assert_eq!(L::finger(key), Finger::Thumb);
+    }
+    let row_fingers: Vec<Finger> = L::MAIN.iter().map(|row| L::finger(row[0])).collect();
+    for (row, finger) in L::MAIN.iter().zip(row_fingers.iter()) {
+        assert_ne!(*finger, Finger::Thumb);
+        assert!(row.iter().all(|key| L::finger(*key) == *finger));
+        assert_eq!(row_fingers.iter().filter(|f| *f == finger).count(), 1);
+    }
+}
+
 fn reserved_to_tw() -> Vec<TwiddlerChord> {
     let mut reserved_as_tw_chords = Vec::new();
     for reserved_chord in RESERVED {
@@ -656,3 +1053,409 @@ fn alignment_multiple_insertions() {
     assert!(new_incorrect == original_incorrect);
 }
 }
+
+run_n_times! {100,
+#[test]
+fn traceback_reconstructs_sequences() {
+    let mut rng = thread_rng();
+    let seq: Vec<usize> = (0..10).map(|_| rng.gen_range(0..4)).collect();
+    let mut corrupted_seq = seq.clone();
+    for _ in 0..rng.gen_range(0..4) {
+        let idx = rng.gen_range(0..corrupted_seq.len());
+        match rng.gen_range(0..3) {
+            0 => corrupted_seq[idx] = rng.gen_range(0..4),
+            1 => { corrupted_seq.remove(idx); },
+            _ => corrupted_seq.insert(idx, rng.gen_range(0..4)),
+        }
+    }
+    let (correct, _, _) = align(&seq, &corrupted_seq);
+    let alignment = traceback(&seq, &corrupted_seq);
+
+    // reading off either side of the alignment should give back the sequence, and the matches are the correct elements
+    let expected: Vec<usize> = alignment.iter().filter_map(|pair| match pair {
+        AlignedPair::Match(x) | AlignedPair::Missed(x) | AlignedPair::Substitution { expected: x, .. } => Some(*x),
+        AlignedPair::Inserted(_) => None,
+    }).collect();
+    let typed: Vec<usize> = alignment.iter().filter_map(|pair| match pair {
+        AlignedPair::Match(x) | AlignedPair::Inserted(x) | AlignedPair::Substitution { typed: x, .. } => Some(*x),
+        AlignedPair::Missed(_) => None,
+    }).collect();
+    assert_eq!(expected, seq);
+    assert_eq!(typed, corrupted_seq);
+    assert_eq!(alignment.iter().filter(|pair| matches!(pair, AlignedPair::Match(_))).count(), correct as usize);
+}
+}
+
+#[test]
+fn typing_errors_are_classified() {
+    let expected = vec![0, 1, 0, 1, 0, 1];
+    let errors = |typed: Vec<usize>| classify_errors(&traceback(&expected, &typed));
+
+    assert_eq!(errors(vec![0, 1, 0, 1, 0, 1]), vec![]);
+    assert_eq!(errors(vec![0, 1, 0, 2, 0, 1]), vec![TypingError::Substituted { expected: 1, typed: 2 }]);
+    assert_eq!(errors(vec![0, 1, 0, 0, 1]), vec![TypingError::Missed { expected: 1 }]);
+    assert_eq!(errors(vec![0, 1, 1, 0, 1, 0, 1]), vec![TypingError::Doubled { chord: 1 }]);
+    assert_eq!(errors(vec![0, 1, 2, 0, 1, 0, 1]), vec![TypingError::Extra { typed: 2 }]);
+
+    let expected = vec![0, 1, 2, 3];
+    assert_eq!(classify_errors(&traceback(&expected, &[0, 2, 1, 3])), vec![TypingError::Swapped { first: 1, second: 2 }]);
+}
+
+run_n_times! {10,
+#[test]
+fn outliers_are_flagged() {
+    let rng = &mut thread_rng();
+    let mut results: TrialResults<K, { K::COUNT }, L> = TrialResults::new();
+    let mut push_trial = |chord_pair: [TwiddlerChord; 2], input: Vec<TwiddlerChord>, time: f64| {
+        results.push(TrialData { chord_pair, n_repetitions: 5, performance: Ok(Performance { input: as_typed(&input), time, errors: None }) });
+    };
+    let random_pair = |rng: &mut ThreadRng| -> [TwiddlerChord; 2] {
+        loop {
+            let pair = [random_chord_(rng, 0.8), random_chord_(rng, 0.8)];
+            if pair[0] != pair[1] {
+                return pair;
+            }
+        }
+    };
+    let perfect_input = |pair: &[TwiddlerChord; 2]| (0..10).map(|i| pair[i % 2].clone()).collect::<Vec<TwiddlerChord>>();
+
+    // typical trials take between 2 and 4 seconds. they're evenly spread, since random times can by chance be bunched up
+    // enough for the extremes to look like outliers
+    for i in 0..30 {
+        let pair = random_pair(rng);
+        let input = perfect_input(&pair);
+        push_trial(pair, input, 2.0 + 2.0 * i as f64 / 29.0);
+    }
+    // a distracted participant
+    let pair = random_pair(rng);
+    let input = perfect_input(&pair);
+    push_trial(pair, input, 40.0);
+    // most of the sequence missed
+    let pair = random_pair(rng);
+    let input = pair.to_vec();
+    push_trial(pair, input, 3.0);
+    // the same trial recorded twice
+    let duplicate = results.data[3].clone();
+    results.push(duplicate);
+    // impossible trials have no time, so they aren't outliers
+    results.push(TrialData { chord_pair: random_pair(rng), n_repetitions: 5, performance: Err(ErrCode::Impossible) });
+
+    let report = find_outliers(&results, &OutlierConfig::default());
+    println!("{}", report);
+    assert_eq!(report.n_trials, 34);
+    assert_eq!(report.flagged.iter().map(|trial| trial.index).collect::<Vec<usize>>(), vec![30, 31, 32]);
+    assert!(matches!(report.flagged[0].reasons[..], [OutlierReason::SlowTime { .. }]));
+    assert!(matches!(report.flagged[1].reasons[..], [OutlierReason::LowAccuracy { .. }]));
+    assert_eq!(report.flagged[2].reasons, vec![OutlierReason::Duplicate { of: 3 }]);
+    assert!(report.is_flagged(30) && !report.is_flagged(0));
+}
+}
+
+run_n_times! {10,
+#[test]
+fn confusion_table_counts_substitutions() {
+    let rng = &mut thread_rng();
+    let a = random_chord_(rng, 0.5);
+    // a with one key flipped, so it differs from a by exactly that key
+    let mut a_typo = a.clone();
+    let key_idx = rng.gen_range(0..K::COUNT);
+    a_typo.toggle_raw_key(key_idx);
+    let b = loop {
+        let b = random_chord_(rng, 0.5);
+        if b != a && b != a_typo {
+            break b;
+        }
+    };
+
+    let mut results: TrialResults<K, { K::COUNT }, L> = TrialResults::new();
+    let perfect_input: Vec<TwiddlerChord> = (0..10).map(|i| [&a, &b][i % 2].clone()).collect();
+    let mut typo_input = as_typed(&perfect_input);
+    typo_input[2] = a_typo.clone().into();
+    typo_input[6] = a_typo.clone().into();
+    // text which isn't the output of any chord is a mistake, but it isn't a chord that b could be confused with
+    typo_input[5] = TypedChord::Invalid { invalid: "\t".to_string() };
+    for input in [as_typed(&perfect_input), typo_input] {
+        results.push(TrialData { chord_pair: [a.clone(), b.clone()], n_repetitions: 5, performance: Ok(Performance { input, time: 3.0, errors: None }) });
+    }
+    results.push(TrialData { chord_pair: [a.clone(), b.clone()], n_repetitions: 5, performance: Err(ErrCode::Impossible) });
+
+    let table = ConfusionTable::from_results(&[results]);
+    assert_eq!(table.n_trials, 2);
+    assert_eq!(table.entries.len(), 1);
+    let entry = &table.entries[0];
+    assert_eq!((&entry.expected, &entry.typed, entry.count, entry.attempts), (&a, &a_typo, 2, 10));
+    let flipped = K::VARIANTS[key_idx];
+    if a.contains(flipped) {
+        assert_eq!((entry.extra_keys.clone(), entry.missing_keys.clone()), (vec![], vec![flipped]));
+    } else {
+        assert_eq!((entry.extra_keys.clone(), entry.missing_keys.clone()), (vec![flipped], vec![]));
+    }
+    assert_eq!(table.confusion_probability(&a, &a_typo), 0.2);
+    assert_eq!(table.confusion_probability(&b, &a_typo), 0.0);
+
+    let mut csv = Vec::new();
+    table.write_csv(&mut csv).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), 2);
+    let json_file = TempFile::new("confusion_table");
+    table.save_json(&json_file.path).unwrap();
+    assert_eq!(ConfusionTable::load_json(&json_file.path).unwrap(), table);
+}
+}
+
+#[test]
+fn corpus_statistics() {
+    let outputs: Vec<String> = ["a", "b", "th"].iter().map(|s| s.to_string()).collect();
+    let stats = TextStatistics::from_text("thab\nxa th", &outputs);
+    assert_eq!(stats.counts, vec![2.0, 1.0, 2.0]);
+    // the line break, x and space aren't outputs, so they separate "thab" from "a" and "a" from "th"
+    assert_eq!(stats.bigrams, vec![(0, 1, 1.0), (2, 0, 1.0)]);
+    assert_eq!(TextStatistics::characters("abb\nb c"), vec!["b", " ", "a", "c"]);
+}
+
+// every transition costs the number of keys in the chord being typed
+struct KeyCountCost;
+
+impl ChordCostModel<K, { K::COUNT }, L> for KeyCountCost {
+    fn transition_cost(&self, _from: &TwiddlerChord, to: &TwiddlerChord) -> f64 {
+        to.n_keys() as f64
+    }
+}
+
+// the first chord is typed instead of the second half the time
+struct OneConfusion(TwiddlerChord, TwiddlerChord);
+
+impl ConfusionModel<K, { K::COUNT }, L> for OneConfusion {
+    fn confusion_probability(&self, intended: &TwiddlerChord, typed: &TwiddlerChord) -> f64 {
+        if *intended == self.0 && *typed == self.1 { 0.5 } else { 0.0 }
+    }
+
+    fn confusions(&self, intended: &TwiddlerChord) -> Vec<(TwiddlerChord, f64)> {
+        if *intended == self.0 { vec![(self.1.clone(), 0.5)] } else { vec![] }
+    }
+}
+
+fn distinct_chords<R: Rng>(rng: &mut R, n: usize) -> Vec<TwiddlerChord> {
+    // valid chords, since the optimizer only assigns those
+    let mut chords: Vec<TwiddlerChord> = Vec::new();
+    while chords.len() < n {
+        let chord = random_chord_(rng, 0.6);
+        if L::is_valid(&chord) && !chords.contains(&chord) {
+            chords.push(chord);
+        }
+    }
+    chords
+}
+
+run_n_times! {5,
+#[test]
+fn optimizer_prefers_cheap_chords() {
+    let rng = &mut thread_rng();
+    let text: String = (0..500).map(|_| ["e", "e", "e", "t", "t", "a", "o", " "][rng.gen_range(0..8)]).collect();
+    let outputs = TextStatistics::characters(&text);
+    let candidates = distinct_chords(rng, 20);
+    let objective = LayoutObjective::new(TextStatistics::from_text(&text, &outputs), candidates.clone(), &KeyCountCost, None::<&ConfusionTable<K, { K::COUNT }, L>>, 1.0, &LayoutConstraints::none()).unwrap();
+    let layout = objective.optimize(&OptimizerConfig { n_iterations: 5000, ..OptimizerConfig::default() }, rng.gen());
+
+    // the cost which is updated during the optimization matches the cost computed from scratch
+    let score = objective.score(&layout.vocab).unwrap();
+    assert!((score.total - layout.cost.total).abs() < 1e-9);
+    // with these costs, the best layout gives the most frequent outputs (which characters lists first) the chords with the fewest keys
+    let mut by_key_count = candidates.clone();
+    by_key_count.sort_by_key(|c| c.n_keys());
+    let best: Vec<(TwiddlerChord, String)> = by_key_count.into_iter().zip(outputs).collect();
+    assert!(layout.cost.total <= objective.score(&best).unwrap().total + 1e-9);
+}
+}
+
+run_n_times! {5,
+#[test]
+fn optimizer_separates_confusable_chords() {
+    let rng = &mut thread_rng();
+    // every output is seen after every other one, so any of them would be a plausible typo for any other
+    let text = "aabbab";
+    let outputs: Vec<String> = vec!["a".to_string(), "b".to_string()];
+    let chords = distinct_chords(rng, 3);
+    let confusion = OneConfusion(chords[0].clone(), chords[1].clone());
+    // all the transitions cost the same, so only the confusions matter
+    struct ConstantCost;
+    impl ChordCostModel<K, { K::COUNT }, L> for ConstantCost {
+        fn transition_cost(&self, _from: &TwiddlerChord, _to: &TwiddlerChord) -> f64 {
+            1.0
+        }
+    }
+    let objective = LayoutObjective::new(TextStatistics::from_text(text, &outputs), chords.clone(), &ConstantCost, Some(&confusion), 1.0, &LayoutConstraints::none()).unwrap();
+    assert!(objective.score(&[(chords[0].clone(), "a".to_string()), (chords[1].clone(), "b".to_string())]).unwrap().confusion > 0.0);
+    let layout = objective.optimize(&OptimizerConfig { n_iterations: 1000, ..OptimizerConfig::default() }, rng.gen());
+    assert_eq!(layout.cost.confusion, 0.0);
+    assert!(!(layout.vocab.iter().any(|(c, _)| *c == chords[0]) && layout.vocab.iter().any(|(c, _)| *c == chords[1])));
+}
+}
+
+run_n_times! {5,
+#[test]
+fn optimizer_respects_constraints() {
+    let rng = &mut thread_rng();
+    let text: String = (0..500).map(|_| ["e", "e", "e", "t", "t", "a", "o", " "][rng.gen_range(0..8)]).collect();
+    let outputs = TextStatistics::characters(&text);
+    let candidates = distinct_chords(rng, 60);
+    // an existing layout for the outputs and one more which isn't in the text, on chords with at most 3 keys
+    let base_outputs = ["e", "t", "a", "o", " ", "q"];
+    let base_layout: Vec<(TwiddlerChord, String)> = candidates.iter().filter(|c| c.n_keys() <= 3).zip(base_outputs).map(|(c, o)| (c.clone(), o.to_string())).collect();
+    let keys = |chord: &TwiddlerChord| K::VARIANTS.iter().filter(|k| chord.contains(**k)).copied().collect::<Vec<K>>();
+    // keep e where it is, and take t's chord away, so that t has to move
+    let spec: ConstraintSpec<K> = serde_json::from_value(serde_json::json!({
+        "pin_from_base": ["e"],
+        "forbidden": [keys(&base_layout[1].0)],
+        "max_keys": 3,
+        "max_changes": 2,
+    })).unwrap();
+    let constraints = spec.resolve(base_layout.clone()).unwrap();
+    let objective = LayoutObjective::new(TextStatistics::from_text(&text, &outputs), candidates.clone(), &KeyCountCost, None::<&ConfusionTable<K, { K::COUNT }, L>>, 1.0, &constraints).unwrap();
+    let layout = objective.optimize(&OptimizerConfig { n_iterations: 5000, ..OptimizerConfig::default() }, rng.gen());
+
+    let chord_of = |output: &str| layout.vocab.iter().find(|(_, o)| o == output).map(|(c, _)| c.clone());
+    assert_eq!(chord_of("e"), Some(base_layout[0].0.clone()));
+    assert_eq!(chord_of("q"), Some(base_layout[5].0.clone()));
+    assert_ne!(chord_of("t"), Some(base_layout[1].0.clone()));
+    assert!(layout.vocab.iter().all(|(c, _)| c.n_keys() <= 3));
+    let n_changed = base_layout.iter().filter(|(c, o)| chord_of(o).as_ref() != Some(c)).count();
+    assert_eq!(n_changed, layout.n_changed);
+    assert!((1..=2).contains(&n_changed));
+
+    // no outputs may move, but t has to
+    let constraints = LayoutConstraints { max_changes: Some(0), ..constraints };
+    assert!(LayoutObjective::new(TextStatistics::from_text(&text, &outputs), candidates.clone(), &KeyCountCost, None::<&ConfusionTable<K, { K::COUNT }, L>>, 1.0, &constraints).is_err());
+
+    // a base layout (e.g. an imported one) with a chord the device can't use: its output moves to a valid chord, and it
+    // can't be pinned there
+    let thumb_only: TwiddlerChord = chord_from_keys(&[K::L0]);
+    assert!(!L::is_valid(&thumb_only));
+    let mut invalid_base = base_layout.clone();
+    invalid_base[2].0 = thumb_only.clone();
+    let spec: ConstraintSpec<K> = serde_json::from_value(serde_json::json!({ "max_changes": 1 })).unwrap();
+    let constraints = spec.resolve(invalid_base.clone()).unwrap();
+    let objective = LayoutObjective::new(TextStatistics::from_text(&text, &outputs), candidates.clone(), &KeyCountCost, None::<&ConfusionTable<K, { K::COUNT }, L>>, 1.0, &constraints).unwrap();
+    let layout = objective.optimize(&OptimizerConfig { n_iterations: 2000, ..OptimizerConfig::default() }, rng.gen());
+    assert!(layout.vocab.iter().all(|(c, _)| L::is_valid(c)));
+    assert!(layout.vocab.iter().any(|(c, o)| o == "a" && *c != thumb_only));
+    assert_eq!(layout.n_changed, 1);
+    let spec: ConstraintSpec<K> = serde_json::from_value(serde_json::json!({ "pin_from_base": ["a"] })).unwrap();
+    assert!(spec.resolve(invalid_base).is_err());
+}
+}
+
+run_n_times! {5,
+#[test]
+fn multichar_outputs_are_chosen() {
+    let rng = &mut thread_rng();
+    // "the " is very common, and "zq" appears just once
+    let mut text: String = (0..300).map(|_| ["the ", "a", "n", "d", "o", "t", "h", "e", " "][rng.gen_range(0..9)]).collect();
+    text.push_str("zq");
+    assert_eq!(ngram_counts("aaa\naa", 3), [("aa".to_string(), 3.0), ("aaa".to_string(), 1.0)].into_iter().collect());
+
+    let outputs = TextStatistics::characters(&text);
+    // each chord costs a bit more than the one before
+    let chord_costs: Vec<f64> = (0..40).map(|i| 1.0 + 0.01 * i as f64).collect();
+    let config = MulticharConfig { max_multichar: 3, ..MulticharConfig::default() };
+    let (new_outputs, chosen) = choose_multichar(&text, outputs.clone(), &chord_costs, &config);
+    assert!(!chosen.is_empty() && chosen.len() <= 3);
+    assert!(chosen.iter().any(|c| c.output.contains("th")));
+    assert!(chosen.iter().all(|c| c.count >= config.min_count && c.saving > 0.0 && !c.output.contains('z')));
+    assert_eq!(new_outputs.len(), outputs.len() + chosen.len());
+    let stats = TextStatistics::from_text(&text, &new_outputs);
+    assert!(chosen.iter().all(|c| stats.count_of(&c.output) == c.count));
+
+    // no room for more outputs
+    let (new_outputs, chosen) = choose_multichar(&text, outputs.clone(), &chord_costs[..outputs.len()], &config);
+    assert!(chosen.is_empty() && new_outputs == outputs);
+    let (_, chosen) = choose_multichar(&text, outputs.clone(), &chord_costs, &MulticharConfig { max_multichar: 0, ..config });
+    assert!(chosen.is_empty());
+}
+}
+
+#[test]
+fn practice_scoring_and_prompts() {
+    let outputs: Vec<String> = ["a", "b", "c", " ", ".", "the "].iter().map(|s| s.to_string()).collect();
+    let segmenter = Segmenter::new(&outputs);
+    // the second line has a character that isn't an output, and the third is split into its sentences
+    let prompts = prompts_from_corpus("the cab\nab x\n\nabc. cba.", &segmenter, 4);
+    assert_eq!(prompts, vec![vec![5, 2, 0, 1], vec![0, 1, 2, 4], vec![2, 1, 0, 4]]);
+    // a line without sentence ends is split between words
+    assert_eq!(prompts_from_corpus("ab ab ab", &segmenter, 4), vec![vec![0, 1], vec![0, 1], vec![0, 1]]);
+
+    // c was left out and a was doubled
+    let typed = |outputs: &[usize]| outputs.iter().map(|&idx| DecodedToken::Output(idx)).collect::<Vec<DecodedToken>>();
+    assert_eq!(score_typing(&[0, 1, 2, 0], &typed(&[0, 1, 0, 0])), vec![true, true, false, true]);
+    assert_eq!(score_typing(&[0, 1], &typed(&[0, 1])), vec![true, true]);
+    // text which isn't an output, typed in place of one, is a mistake
+    assert_eq!(score_typing(&[0, 1, 2], &[DecodedToken::Output(0), DecodedToken::Invalid("x".to_string()), DecodedToken::Output(2)]), vec![true, false, true]);
+    assert!((words_per_minute(50, 60.0) - 10.0).abs() < 1e-9);
+
+    // prompts longer than what can be aligned are refused
+    assert!(PracticeConfig::default().check().is_ok());
+    assert!(PracticeConfig { max_prompt_outputs: MAX_TYPED_OUTPUTS + 1, ..PracticeConfig::default() }.check().is_err());
+}
+
+#[test]
+fn practice_schedules_weak_chords() {
+    let rng = &mut thread_rng();
+    let vocab: Vec<(TwiddlerChord, String)> = distinct_chords(rng, 3).into_iter().zip(["a", "b", "c"]).map(|(c, o)| (c, o.to_string())).collect();
+    let mut progress = PracticeProgress::new(&vocab);
+    // everything is due at the start
+    assert_eq!(progress.prompt_priority(&[0, 1, 1]), 2.0);
+
+    // a is right, b is wrong once
+    progress.record(&[0, 1, 1], &[true, true, false]);
+    assert_eq!((progress.chords[0].level, progress.chords[0].attempts, progress.chords[0].errors), (1, 1, 0));
+    assert_eq!((progress.chords[1].level, progress.chords[1].attempts, progress.chords[1].errors), (0, 2, 1));
+    // so b is due straight away, with a higher priority than c, which hasn't been seen; a has to wait
+    assert_eq!(progress.prompt_priority(&[0]), 0.0);
+    assert!(progress.prompt_priority(&[1]) > progress.prompt_priority(&[2]));
+    progress.record(&[2], &[true]);
+    assert!(progress.prompt_priority(&[0]) > 0.0);
+    assert_eq!(progress.weakest(1)[0].output, "b");
+
+    // progress is kept for the chords whose outputs are unchanged
+    let mut new_vocab = vocab.clone();
+    new_vocab[1].1 = "d".to_string();
+    let updated = PracticeProgress::for_vocab(&new_vocab, Some(progress.clone()));
+    assert_eq!(updated.chords[0], progress.chords[0]);
+    assert_eq!(updated.chords[1].attempts, 0);
+    assert_eq!(updated.n_prompts, 2);
+}
+
+#[test]
+fn practice_logs_transitions() {
+    let rng = &mut thread_rng();
+    let chords = distinct_chords(rng, 3);
+    let (a, b, c) = (chords[0].clone(), chords[1].clone(), chords[2].clone());
+    // text which isn't any chord's output was typed in place of b, so both transitions with it count as errors. the
+    // transitions are timed from what was typed for them, so the 2s before the first chord (reading the prompt) aren't counted
+    let typed = vec![a.clone().into(), TypedChord::Invalid { invalid: "\t".to_string() }, a.clone().into()];
+    let line = PracticeLine::new(vec![a.clone(), b.clone(), a.clone()], typed, Some(vec![2.0, 2.5, 3.25]), 4.0);
+    assert_eq!(line.errors.len(), 1);
+    let transitions = line.transitions();
+    assert_eq!(transitions.len(), 2);
+    assert_eq!(transitions[0].chord_pair, [a.clone(), b.clone()]);
+    assert_eq!(transitions.iter().map(|t| t.time).collect::<Vec<Option<f64>>>(), vec![Some(0.5), Some(0.75)]);
+    assert!(transitions.iter().all(|t| !t.correct));
+
+    // a chord which wasn't typed leaves its transitions without a time
+    let missed = PracticeLine::new(vec![a.clone(), b.clone(), c.clone()], as_typed(&[a.clone(), b.clone()]), Some(vec![1.0, 2.5]), 3.0);
+    assert_eq!(missed.transitions().iter().map(|t| (t.time, t.correct)).collect::<Vec<(Option<f64>, bool)>>(), vec![(Some(1.5), true), (None, false)]);
+
+    // lines logged before the chords were timed spread their time over the switches between the typed chords
+    let untimed = PracticeLine::new(vec![a.clone(), b.clone()], as_typed(&[a.clone(), b.clone()]), None, 3.0);
+    assert_eq!(untimed.transitions()[0].time, Some(3.0));
+    let log = PracticeLog { lines: vec![line, missed, untimed], seed: Some(0), participant: None };
+    let summaries = summarize_transitions(&[log.clone()]);
+    assert_eq!(summaries.iter().map(|s| s.chord_pair.clone()).collect::<Vec<[TwiddlerChord; 2]>>(), vec![[a.clone(), b.clone()], [b.clone(), a.clone()], [b.clone(), c.clone()]]);
+    assert_eq!((summaries[0].n_transitions, summaries[0].n_timed), (3, 3));
+    assert!((summaries[0].mean_time - 5.0 / 3.0).abs() < 1e-9);
+    assert!((summaries[0].accuracy - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!((summaries[2].n_transitions, summaries[2].n_timed, summaries[2].mean_time), (1, 0, 0.0));
+
+    let json = serde_json::to_string(&log).unwrap();
+    let loaded = serde_json::from_str(&json).unwrap();
+    assert_eq!(log, loaded);
+}
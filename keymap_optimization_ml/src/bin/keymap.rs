@@ -0,0 +1,10 @@
+use clap::Parser;
+
+use keymap_optimization_ml::cli::{run, Cli};
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::io::Write;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tch::nn::Module;
+use tch::Tensor;
+use keymap_optimization::keyboard_config::{Chord, GraphicalChord, Key, Layout};
+use keymap_optimization::chord_preferences::N_REPETITIONS_PER_TRIAL;
+use keymap_optimization::chord_preferences::confusion::{csv_field, keys_string};
+use keymap_optimization::chord_preferences::data_cleaning::switching_time;
+
+use crate::chord_samplers::all_chords;
+use crate::layout_costs::{CostWeights, ModelChordCosts};
+use crate::reward_model::{PossibilityModel, PossibilitySpread};
+use crate::train::{chord_to_tensor, predict_pairs};
+
+// every valid chord of a layout with what a model predicts about it, so that we can check what the model believes:
+// the probability that the chord is possible (and how uncertain that is), and the mean switching time and accuracy
+// between it and a few common chords, in both directions
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct CatalogEntry<K: Key, const N: usize, L: Layout<K, N>> {
+    pub chord: Chord<K, N, L>,
+    pub possible: f64,
+    // 1 when the probability that the chord is possible is 1/2, and 0 when it's 0 or 1 (as for the uncertain sampler)
+    pub uncertainty: f64,
+    // how much the members of an ensemble disagree about whether the chord is possible; None for other models
+    pub possible_spread: Option<f64>,
+    // the mean switching time from the common chords to this one, and from this one to the common chords
+    pub switching_time_to: f64,
+    pub switching_time_from: f64,
+    // the mean accuracy of the pairs of this chord with the common chords, in both orders
+    pub accuracy: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum CatalogOrder {
+    // fastest (mean of switching_time_to and switching_time_from) first
+    Time,
+    // most likely to be possible first
+    Possible,
+    // most uncertain first
+    Uncertainty,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(bound = "K: DeserializeOwned, L: DeserializeOwned")]
+pub struct ChordCatalog<K: Key, const N: usize, L: Layout<K, N>> {
+    // the chords the switching times are measured against
+    pub common_chords: Vec<Chord<K, N, L>>,
+    pub entries: Vec<CatalogEntry<K, N, L>>,
+}
+
+pub fn common_chords<K: Key, const N: usize, L: Layout<K, N>, M: Module>(model: &M, n_common: usize) -> Vec<Chord<K, N, L>> {
+    // the n_common chords which the model predicts are cheapest to repeat (with the default cost weights)
+    let costs = ModelChordCosts::<K, N, L, M>::new(model, CostWeights::default());
+    let chords = all_chords::<K, N, L>();
+    let mut ranked: Vec<(Chord<K, N, L>, f64)> = chords.clone().into_iter().zip(costs.repetition_costs(&chords)).collect();
+    ranked.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    ranked.into_iter().take(n_common).map(|(chord, _)| chord).collect()
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> ChordCatalog<K, N, L> {
+    pub fn new<M: Module, P: PossibilityModel>(model: &M, possibility_model: &P, spread_model: Option<&dyn PossibilitySpread>, common_chords: Vec<Chord<K, N, L>>) -> Result<Self, Box<dyn Error>> {
+        if common_chords.is_empty() {
+            return Err("the catalog needs at least one common chord to measure switching times against".into());
+        }
+        let mut entries = Vec::new();
+        // in chunks, so that each batch has a manageable number of pairs
+        for chunk in all_chords::<K, N, L>().chunks(256) {
+            let chords_tensor = Tensor::stack(&chunk.iter().map(chord_to_tensor).collect::<Vec<Tensor>>(), 0);
+            // these unwraps are safe because the outputs have one entry per chord
+            let possible: Vec<f64> = tch::no_grad(|| possibility_model.possible_probabilities(&chords_tensor)).reshape([-1]).iter::<f64>().unwrap().collect();
+            let spread: Option<Vec<f64>> = spread_model.map(|model| tch::no_grad(|| model.possible_probability_spread(&chords_tensor)).reshape([-1]).iter::<f64>().unwrap().collect());
+            // for each chord, the pairs from each common chord to it and then from it to each common chord
+            let pairs: Vec<_> = chunk.iter()
+                .flat_map(|chord| common_chords.iter().map(move |common| (common, chord)).chain(common_chords.iter().map(move |common| (chord, common))))
+                .collect();
+            let predictions = predict_pairs(model, &pairs);
+            for (i, (chord, chord_predictions)) in chunk.iter().zip(predictions.chunks(2 * common_chords.len())).enumerate() {
+                let (to, from) = chord_predictions.split_at(common_chords.len());
+                let mean_time = |predictions: &[[f64; 3]]| predictions.iter().map(|[time, _, _]| switching_time(*time, N_REPETITIONS_PER_TRIAL)).sum::<f64>() / predictions.len() as f64;
+                entries.push(CatalogEntry {
+                    chord: chord.clone(),
+                    possible: possible[i],
+                    uncertainty: 1.0 - (2.0 * possible[i] - 1.0).abs(),
+                    possible_spread: spread.as_ref().map(|spread| spread[i]),
+                    switching_time_to: mean_time(to),
+                    switching_time_from: mean_time(from),
+                    accuracy: chord_predictions.iter().map(|[_, accuracy, _]| *accuracy).sum::<f64>() / chord_predictions.len() as f64,
+                });
+            }
+        }
+        Ok(Self { common_chords, entries })
+    }
+
+    pub fn sort(&mut self, order: CatalogOrder) {
+        let mean_time = |entry: &CatalogEntry<K, N, L>| (entry.switching_time_to + entry.switching_time_from) / 2.0;
+        match order {
+            CatalogOrder::Time => self.entries.sort_by(|a, b| mean_time(a).total_cmp(&mean_time(b))),
+            CatalogOrder::Possible => self.entries.sort_by(|a, b| b.possible.total_cmp(&a.possible)),
+            CatalogOrder::Uncertainty => self.entries.sort_by(|a, b| b.uncertainty.total_cmp(&a.uncertainty)),
+        }
+    }
+
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        // one row per chord, with the chord in its text format and its keys separated by spaces
+        writeln!(writer, "chord,keys,possible,uncertainty,possible_spread,switching_time_to,switching_time_from,accuracy")?;
+        for entry in &self.entries {
+            let keys: Vec<K> = entry.chord.keys().collect();
+            writeln!(writer, "{},{},{},{},{},{},{},{}",
+                     csv_field(entry.chord.to_string()),
+                     csv_field(keys_string(&keys)),
+                     entry.possible,
+                     entry.uncertainty,
+                     entry.possible_spread.map_or(String::new(), |spread| spread.to_string()),
+                     entry.switching_time_to,
+                     entry.switching_time_from,
+                     entry.accuracy)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_csv(&self, path: &str) -> std::io::Result<()> {
+        self.write_csv(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+
+    pub fn write_report<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        // each chord drawn in the graphical format, under its predictions
+        writeln!(writer, "switching times are measured against {} common chords: {}", self.common_chords.len(),
+                 self.common_chords.iter().map(|chord| chord.to_string()).collect::<Vec<String>>().join(" "))?;
+        for entry in &self.entries {
+            writeln!(writer)?;
+            writeln!(writer, "{}: possible {:.3} (uncertainty {:.3}{}), switching time to {:.3}s, from {:.3}s, accuracy {:.3}",
+                     entry.chord, entry.possible, entry.uncertainty,
+                     entry.possible_spread.map_or(String::new(), |spread| format!(", ensemble spread {:.3}", spread)),
+                     entry.switching_time_to, entry.switching_time_from, entry.accuracy)?;
+            writeln!(writer, "{}", GraphicalChord { chord: &entry.chord })?;
+        }
+        Ok(())
+    }
+
+    pub fn save_report(&self, path: &str) -> std::io::Result<()> {
+        self.write_report(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
+}
@@ -1,31 +1,25 @@
-use keymap_optimization::keyboard_config::{Chord, Key, Layout, ChordSampler};
+use keymap_optimization::keyboard_config::{Chord, Key, Layout, ChordSampler, sample_without_replacement};
 use itertools::Itertools;
 use tch::Tensor;
 use crate::train::chord_to_tensor;
-use crate::reward_model::RewardEmbedding;
+use crate::reward_model::PossibilityModel;
 use rand::prelude::SliceRandom;
 
-fn all_chords<K: Key, const N: usize, L: Layout<K, N>>() -> Vec<Chord<K, N, L>> {
-    // generate all 2^16 = 65536 chords and return the valid ones
-    let mut chords = Vec::new();
-    for keys in K::VARIANTS.iter().powerset() {
-        let mut chord = Chord::new();
-        for key in keys.iter() {
-            chord.add_key(**key);
-        }
-        if L::is_valid(&chord) {
-            chords.push(chord);
-        }
-    }
-    chords
+pub fn all_chords<K: Key, const N: usize, L: Layout<K, N>>() -> Vec<Chord<K, N, L>> {
+    // generate all 2^16 = 65536 chords and return the valid ones. they're in the order of the powerset of the keys (by number
+    // of keys), which the samplers rely on to regenerate a vocabulary from its seed
+    (0..N).powerset()
+          .map(|idxs| Chord::from_bits(idxs.into_iter().map(|i| 1 << i).sum()))
+          .filter(L::is_valid)
+          .collect()
 }
 
-pub fn get_possible_probabilities<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(embedder: &E) -> Result<Vec<(Chord<K, N, L>, f64)>, Box<dyn std::error::Error>> {
+pub fn get_possible_probabilities<K: Key, const N: usize, L: Layout<K, N>, P: PossibilityModel>(embedder: &P) -> Result<Vec<(Chord<K, N, L>, f64)>, Box<dyn std::error::Error>> {
     let all_chords: Vec<Chord<K, N, L>> = all_chords::<K, N, L>();
     let all_chords_tensor = Tensor::stack(&all_chords.clone().into_iter().map(|c| chord_to_tensor(&c)).collect::<Vec<Tensor>>(), 0);
 
     // compute the probability of being possible for each chord
-    let (_, _, possible_probs) = embedder.embed_chords(&all_chords_tensor);
+    let possible_probs = embedder.possible_probabilities(&all_chords_tensor);
 
     Ok(all_chords.into_iter()
                  .zip(possible_probs.squeeze()
@@ -39,9 +33,9 @@ pub struct PossibleChordSampler<K: Key, const N: usize, L: Layout<K, N>, R: rand
     chords_with_possible_probs: Vec<(Chord<K, N, L>, f64)>,
 }
 
-impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, E: RewardEmbedding> ChordSampler<K, N, L, R, E> for PossibleChordSampler<K, N, L, R> {
-    fn new(rng: R, embedder: &E) -> Result<Self, Box<dyn std::error::Error>> {
-        let chords_with_possible_probs = match get_possible_probabilities::<K, N, L, E>(embedder) {
+impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, P: PossibilityModel> ChordSampler<K, N, L, R, P> for PossibleChordSampler<K, N, L, R> {
+    fn new(rng: R, embedder: &P) -> Result<Self, Box<dyn std::error::Error>> {
+        let chords_with_possible_probs = match get_possible_probabilities::<K, N, L, P>(embedder) {
             Ok(chords_with_probs) => chords_with_probs,
             Err(e) => return Err(e),
         };
@@ -54,12 +48,17 @@ impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, E: RewardEmbedding>
 
         loop {
             // select a random element of possible_probs
-            let (chord, possible_prob) = self.chords_with_possible_probs.choose(&mut rand::thread_rng()).unwrap();  // unwrap is safe because there are always chords
+            let (chord, possible_prob) = self.chords_with_possible_probs.choose(&mut self.rng).unwrap();  // unwrap is safe because there are always chords
             if self.rng.gen::<f64>() < *possible_prob {
                 return chord.clone()
             }
         }
     }
+
+    fn sample_distinct_chords(&mut self, n: usize) -> Result<Vec<Chord<K, N, L>>, Box<dyn std::error::Error>> {
+        // the same distribution as sample_chord, but without replacement: each chord is weighted by its probability of being possible
+        sample_without_replacement(&mut self.rng, &self.chords_with_possible_probs, n)
+    }
 }
 
 pub struct MostUncertainPossibilityChordSampler<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng> {
@@ -67,9 +66,9 @@ pub struct MostUncertainPossibilityChordSampler<K: Key, const N: usize, L: Layou
     chords_with_possible_probs_sorted: Vec<(Chord<K, N, L>, f64)>,
 }
 
-impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, E: RewardEmbedding> ChordSampler<K, N, L, R, E> for MostUncertainPossibilityChordSampler<K, N, L, R> {
-    fn new(rng: R, embedder: &E) -> Result<Self, Box<dyn std::error::Error>> {
-        let mut chords_with_possible_probs = match get_possible_probabilities::<K, N, L, E>(embedder) {
+impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, P: PossibilityModel> ChordSampler<K, N, L, R, P> for MostUncertainPossibilityChordSampler<K, N, L, R> {
+    fn new(rng: R, embedder: &P) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut chords_with_possible_probs = match get_possible_probabilities::<K, N, L, P>(embedder) {
             Ok(chords_with_probs) => chords_with_probs,
             Err(e) => return Err(e),
         };
@@ -86,7 +85,7 @@ impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, E: RewardEmbedding>
         // consider a binomial distribution with n = 2(m-1) and p = 1/2,
         // shifted by (n/2 - i) = m-1-i so that the mean is i and the variance is n/4 = (m-1)/2.)
         // this is our distribution except we discard any trials which yield an index < 0 or > m-1.
-        let most_uncertain_idx = self.chords_with_possible_probs_sorted.iter().find_position(|(_, p)| *p >= 0.5).map(|(idx, _)| idx).unwrap_or(self.chords_with_possible_probs_sorted.len() - 1);  // i
+        let most_uncertain_idx = self.most_uncertain_idx();  // i
         let binom_n = 2 * (self.chords_with_possible_probs_sorted.len() - 1);
         let binom_p = 0.5;
         // n/2 = m-1 >= i, so we can use usize instead of isize.
@@ -100,5 +99,24 @@ impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng, E: RewardEmbedding>
         let (chord, _prob) = &self.chords_with_possible_probs_sorted[sampled_idx];
         chord.clone()
     }
+
+    fn sample_distinct_chords(&mut self, n: usize) -> Result<Vec<Chord<K, N, L>>, Box<dyn std::error::Error>> {
+        // sampling the binomial distribution from sample_chord without replacement would be awkward, so instead we weight each index
+        // by the density of the normal distribution it approximates (with mean i and variance (m-1)/2) and sample without replacement
+        let most_uncertain_idx = self.most_uncertain_idx() as f64;
+        let variance = ((self.chords_with_possible_probs_sorted.len() - 1) as f64 / 2.0).max(1.0);  // avoid dividing by 0 if there is only one chord
+        let weighted_chords: Vec<(Chord<K, N, L>, f64)> = self.chords_with_possible_probs_sorted.iter()
+                                                              .enumerate()
+                                                              .map(|(idx, (chord, _))| (chord.clone(), (-(idx as f64 - most_uncertain_idx).powi(2) / (2.0 * variance)).exp()))
+                                                              .collect();
+        sample_without_replacement(&mut self.rng, &weighted_chords, n)
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng> MostUncertainPossibilityChordSampler<K, N, L, R> {
+    fn most_uncertain_idx(&self) -> usize {
+        // the index of the chord with the probability >= 1/2 which is closest to 1/2 (or the last chord, if there are none)
+        self.chords_with_possible_probs_sorted.iter().find_position(|(_, p)| *p >= 0.5).map(|(idx, _)| idx).unwrap_or(self.chords_with_possible_probs_sorted.len() - 1)
+    }
 }
 
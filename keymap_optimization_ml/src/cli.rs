@@ -0,0 +1,662 @@
+use std::error::Error;
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::SeedableRng;
+use rand::rngs::StdRng as R;
+use strum::EnumCount;
+use tch::nn::Module;
+use keymap_optimization::keyboard_config::{ChordSampler, ChordTrialUtils, GraphicalChord};
+use keymap_optimization::chord_preferences::{data_collection_keymap_gen, gather_chords};
+use keymap_optimization::chord_preferences::data_cleaning::OutlierConfig;
+use keymap_optimization::chord_preferences::confusion::ConfusionTable;
+use keymap_optimization::chord_preferences::practice::{self, PracticeConfig};
+use keymap_optimization::chord_preferences::session::{self, Provenance};
+use keymap_optimization::layout_optimization::corpus::TextStatistics;
+use keymap_optimization::layout_optimization::multichar::MulticharConfig;
+use keymap_optimization::layout_optimization::constraints::ConstraintSpec;
+use keymap_optimization::layout_optimization::optimizer::OptimizedLayout;
+use keymap_optimization::data_store::{Artifact, DataStore};
+use keymap_optimization::twiddler::{self, TwiddlerKey, TwiddlerLayout, TwiddlerChordTrialUtils, TwiddlerExponentialSampler, CodeConfig};
+
+use crate::catalog::{self, CatalogOrder, ChordCatalog};
+use crate::heuristic_model::HeuristicModel;
+use crate::hyperparameter_search::{self, SearchSpace, SearchStrategy};
+use crate::layout_costs::{self, OptimizeConfig};
+use crate::registry::{AnySampler, EnsembleModel, ModelKind, SamplerKind, SamplerSpec, SingleModel};
+use crate::reward_model::{PossibilityModel, PossibilitySpread, RewardEmbedding, RewardModel};
+use crate::train::{self, TrainConfig};
+
+// the command line interface shared by all the tools: generating configs, gathering data, and training and evaluating models
+
+#[derive(Parser, Debug)]
+#[command(about = "gather chord typing data, train reward models on it, and generate keyboard configs")]
+pub struct Cli {
+    /// the root of the data store, which holds the results files and where generated files are written by default (default:
+    /// $KEYMAP_DATA_DIR, or else the data_dir of the nearest keymap.json, or else ./data)
+    #[arg(long, global = true)]
+    pub data_dir: Option<String>,
+
+    /// the keyboard being used
+    #[arg(long, global = true, value_enum, default_value_t = DeviceProfile::Twiddler)]
+    pub device: DeviceProfile,
+
+    /// seed for all random choices; a random seed is chosen (and printed) if this isn't given
+    #[arg(long, global = true)]
+    pub seed: Option<u64>,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// generate a keyboard config and the decoder for its output
+    GenConfig(GenConfigArgs),
+    /// run the data collection game using a previously generated decoder
+    Gather(GatherArgs),
+    /// train a reward model and compare it to the heuristic model
+    Train(TrainArgs),
+    /// print some chords drawn from a sampler
+    Sample(SampleArgs),
+    /// report the test loss of a model
+    Evaluate(EvaluateArgs),
+    /// cross validate reward models with different hyperparameters and write a leaderboard
+    Search(SearchArgs),
+    /// report the trials which look like outliers (e.g. very slow times), for each participant
+    Outliers(OutliersArgs),
+    /// count which chords are typed in place of which others, across all the results files
+    Confusion(ConfusionArgs),
+    /// choose the chord for each character of a corpus, minimizing the predicted typing cost
+    Optimize(OptimizeArgs),
+    /// list every valid chord with the model's predictions for it, as csv and as a report with the chords drawn out
+    Catalog(CatalogArgs),
+    /// learn a layout by typing lines of a corpus with it, with the chords that need the most practice coming up most often
+    Practice(PracticeArgs),
+    /// check that a config file is the one generated along with a decoder, by regenerating it from the decoder
+    CheckConfig(CheckConfigArgs),
+    /// list the files of one kind in the data store
+    List(ListArgs),
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum DeviceProfile {
+    Twiddler,
+}
+
+#[derive(Args, Debug)]
+pub struct TrainingArgs {
+    /// json file with the training configuration (missing fields, or the whole file, take the defaults)
+    #[arg(long)]
+    pub train_config: Option<String>,
+
+    /// number of training epochs (overrides the training configuration)
+    #[arg(long)]
+    pub epochs: Option<usize>,
+
+    /// learning rate for the optimizer (overrides the training configuration)
+    #[arg(long)]
+    pub lr: Option<f64>,
+}
+
+impl TrainingArgs {
+    pub fn config(&self) -> Result<TrainConfig, Box<dyn Error>> {
+        let mut config = match &self.train_config {
+            Some(path) => TrainConfig::load(path)?,
+            None => TrainConfig::default(),
+        };
+        if let Some(epochs) = self.epochs {
+            config.n_epochs = epochs;
+        }
+        if let Some(lr) = self.lr {
+            config.learning_rate = lr;
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct SamplerArgs {
+    /// how chords are chosen
+    #[arg(long, default_value = "exponential", value_parser = sampler_kind_parser())]
+    pub sampler: SamplerKind,
+
+    /// the model used by the possible and uncertain samplers
+    #[arg(long, default_value = "single", value_parser = model_kind_parser())]
+    pub model: ModelKind,
+
+    #[command(flatten)]
+    pub training: TrainingArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct GenConfigArgs {
+    #[command(flatten)]
+    pub sampler: SamplerArgs,
+
+    /// the directory to write the session package (the config, decoder and manifest) to (default: a timestamped directory
+    /// in the data directory)
+    #[arg(long)]
+    pub package_out: Option<String>,
+
+    /// json file with the design of the strings the chords output, e.g. the characters to use (missing fields, or the
+    /// whole file, take the defaults)
+    #[arg(long)]
+    pub code_config: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct GatherArgs {
+    /// the session package whose config is loaded on the keyboard (or a decoder file generated along with the config)
+    pub decoder: String,
+
+    /// where to write the results (default: a timestamped file in the data directory)
+    #[arg(long)]
+    pub results_out: Option<String>,
+
+    /// who is typing; recorded with the results so that each participant's trials can be compared with each other
+    #[arg(long)]
+    pub participant: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct TrainArgs {
+    #[arg(long, default_value = "single", value_parser = model_kind_parser())]
+    pub model: ModelKind,
+
+    #[command(flatten)]
+    pub training: TrainingArgs,
+
+    /// directory to save the trained weights in, along with the training configuration and seed
+    #[arg(long)]
+    pub checkpoint_dir: Option<String>,
+
+    /// save the trained weights in a timestamped directory in the data store (if --checkpoint-dir isn't given)
+    #[arg(long)]
+    pub save: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SampleArgs {
+    #[command(flatten)]
+    pub sampler: SamplerArgs,
+
+    /// number of chords to print
+    #[arg(long, default_value_t = 10)]
+    pub count: usize,
+}
+
+#[derive(Args, Debug)]
+pub struct EvaluateArgs {
+    #[arg(long, default_value = "single", value_parser = model_kind_parser())]
+    pub model: ModelKind,
+
+    #[command(flatten)]
+    pub training: TrainingArgs,
+}
+
+// the possible values come from the registry, so new samplers and models show up here without changing the cli
+fn sampler_kind_parser() -> impl TypedValueParser<Value = SamplerKind> {
+    // this unwrap is safe because the parser only accepts the names of variants
+    PossibleValuesParser::new(SamplerKind::names()).map(|name| name.parse::<SamplerKind>().unwrap())
+}
+
+fn artifact_parser() -> impl TypedValueParser<Value = Artifact> {
+    // this unwrap is safe because the parser only accepts the names of variants
+    PossibleValuesParser::new(Artifact::names()).map(|name| name.parse::<Artifact>().unwrap())
+}
+
+fn model_kind_parser() -> impl TypedValueParser<Value = ModelKind> {
+    // this unwrap is safe because the parser only accepts the names of variants
+    PossibleValuesParser::new(ModelKind::names()).map(|name| name.parse::<ModelKind>().unwrap())
+}
+
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    #[arg(long, default_value = "single", value_parser = model_kind_parser())]
+    pub model: ModelKind,
+
+    /// the configuration the candidates are based on
+    #[command(flatten)]
+    pub training: TrainingArgs,
+
+    /// json file with the values to try for each hyperparameter (default: a small grid over the model size and optimizer)
+    #[arg(long)]
+    pub search_space: Option<String>,
+
+    /// try this many random candidates from the search space instead of all of them
+    #[arg(long)]
+    pub random: Option<usize>,
+
+    /// number of cross validation folds
+    #[arg(long, default_value_t = 5)]
+    pub folds: usize,
+
+    /// where to write the leaderboard (default: a timestamped file in the data directory)
+    #[arg(long)]
+    pub leaderboard_out: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct OutliersArgs {
+    /// json file with the outlier thresholds (missing fields, or the whole file, take the defaults)
+    #[arg(long)]
+    pub outlier_config: Option<String>,
+
+    /// also write the reports to this json file
+    #[arg(long)]
+    pub report_out: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ConfusionArgs {
+    /// number of the most frequent confusions to print
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+
+    /// write the confusion table to this csv file
+    #[arg(long)]
+    pub csv_out: Option<String>,
+
+    /// write the confusion table to this json file
+    #[arg(long)]
+    pub json_out: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct OptimizeArgs {
+    /// text file whose characters the layout should type well
+    pub corpus: String,
+
+    /// the model which predicts the cost of each transition between chords
+    #[arg(long, default_value = "heuristic", value_parser = model_kind_parser())]
+    pub model: ModelKind,
+
+    #[command(flatten)]
+    pub training: TrainingArgs,
+
+    /// load the model from this checkpoint directory (as saved by `train --checkpoint-dir` or `train --save`, possibly by its
+    /// name in the data directory) instead of training it; the training options are ignored
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+
+    /// json file with the optimizer settings and cost weights (missing fields, or the whole file, take the defaults)
+    #[arg(long)]
+    pub optimize_config: Option<String>,
+
+    /// confusion table (from `confusion --json-out`) used to keep confusable chords away from outputs they could be mistaken for
+    #[arg(long)]
+    pub confusion_table: Option<String>,
+
+    /// an existing layout (as written by optimize) to extend; its outputs stay on their chords unless moving them helps
+    #[arg(long)]
+    pub base_layout: Option<String>,
+
+    /// json file with constraints: pinned outputs, forbidden chords, the maximum chord size, and the maximum number of
+    /// outputs of the base layout which may move (see ConstraintSpec in constraints.rs)
+    #[arg(long)]
+    pub constraints: Option<String>,
+
+    /// also give chords to frequent words and other strings of the corpus, up to the device's limits (the settings are
+    /// `multichar` in the optimize config, which turns this on too)
+    #[arg(long)]
+    pub multichar: bool,
+
+    /// where to write the layout (default: a timestamped file in the data directory)
+    #[arg(long)]
+    pub layout_out: Option<String>,
+
+    /// where to write the config for the layout (default: a timestamped file in the data directory)
+    #[arg(long)]
+    pub config_out: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CatalogArgs {
+    /// the model whose predictions are listed
+    #[arg(long, default_value = "single", value_parser = model_kind_parser())]
+    pub model: ModelKind,
+
+    #[command(flatten)]
+    pub training: TrainingArgs,
+
+    /// load the model from this checkpoint directory (as saved by `train --checkpoint-dir` or `train --save`, possibly by its
+    /// name in the data directory) instead of training it; the training options are ignored
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+
+    /// the number of common chords (the ones the model predicts are easiest to repeat) to measure switching times against
+    #[arg(long, default_value_t = 20)]
+    pub n_common: usize,
+
+    /// the order of the chords
+    #[arg(long, value_enum, default_value_t = CatalogOrder::Time)]
+    pub sort_by: CatalogOrder,
+
+    /// where to write the csv (default: a timestamped file in the data directory)
+    #[arg(long)]
+    pub csv_out: Option<String>,
+
+    /// where to write the report with the chords drawn out (default: a timestamped file in the data directory)
+    #[arg(long)]
+    pub report_out: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct PracticeArgs {
+    /// the layout to practice (as written by optimize); the keyboard should be configured with it
+    pub layout: String,
+
+    /// text file whose lines are typed
+    pub corpus: String,
+
+    /// json file with the learner's progress, which is read (if it exists) and updated (default: practice_progress.json
+    /// in the data directory's results)
+    #[arg(long)]
+    pub progress: Option<String>,
+
+    /// json file with the practice settings (missing fields, or the whole file, take the defaults)
+    #[arg(long)]
+    pub practice_config: Option<String>,
+
+    /// who is typing; recorded with the lines typed, which are saved in the data directory for training
+    #[arg(long)]
+    pub participant: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckConfigArgs {
+    /// the config file (e.g. the one loaded on the keyboard)
+    pub config: String,
+
+    /// the decoder it's meant to go with, or a session package
+    pub decoder: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ListArgs {
+    /// the kind of file
+    #[arg(value_parser = artifact_parser())]
+    pub kind: Artifact,
+}
+
+pub fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let seed = cli.seed.unwrap_or_else(rand::random);
+    println!("seed: {}", seed);
+    let store = DataStore::locate(cli.data_dir.as_deref())?;
+    println!("data directory: {}", store.root().display());
+    match cli.device {
+        DeviceProfile::Twiddler => run_twiddler(cli.command, &store, seed),
+    }
+}
+
+// === twiddler ===
+
+type K = TwiddlerKey;
+type L = TwiddlerLayout;
+type C = TwiddlerChordTrialUtils;
+const N: usize = K::COUNT;
+type Spec = SamplerSpec<K, N, L>;
+type S = AnySampler<K, N, L, R>;
+
+fn sampler_spec(args: &SamplerArgs, store: &DataStore, seed: u64) -> Result<Spec, Box<dyn Error>> {
+    Spec::new(args.sampler, args.model, store, &args.training.config()?, seed)
+}
+
+fn provenance(args: &SamplerArgs) -> Result<Provenance, Box<dyn Error>> {
+    // the model and its training settings are only recorded if the sampler uses them
+    if !args.sampler.uses_model() {
+        return Ok(Provenance { sampler: args.sampler.to_string(), ..Provenance::default() });
+    }
+    Ok(Provenance {
+        sampler: args.sampler.to_string(),
+        model: Some(args.model.to_string()),
+        train_config: Some(serde_json::to_value(args.training.config()?)?),
+    })
+}
+
+fn print_samples<I, S: ChordSampler<K, N, L, R, I>>(initialization_info: &I, seed: u64, count: usize) -> Result<(), Box<dyn Error>> {
+    let mut sampler = S::new(R::seed_from_u64(seed), initialization_info)?;
+    for _ in 0..count {
+        let chord = sampler.sample_chord();
+        println!("{}", chord);
+        println!("{}", GraphicalChord { chord: &chord });
+    }
+    Ok(())
+}
+
+fn optimize<M: Module>(model: &M, args: &OptimizeArgs, store: &DataStore, seed: u64) -> Result<(), Box<dyn Error>> {
+    let mut config = match &args.optimize_config {
+        Some(path) => OptimizeConfig::load(path)?,
+        None => OptimizeConfig::default(),
+    };
+    if args.multichar && config.multichar.is_none() {
+        config.multichar = Some(MulticharConfig::default());
+    }
+    let confusion_table = args.confusion_table.as_deref().map(ConfusionTable::<K, N, L>::load_json).transpose()?;
+    let base_layout = match &args.base_layout {
+        Some(path) => serde_json::from_reader::<_, OptimizedLayout<K, N, L>>(std::fs::File::open(path)?)?.vocab,
+        None => Vec::new(),
+    };
+    let constraints = match &args.constraints {
+        Some(path) => ConstraintSpec::<K>::load(path)?,
+        None => ConstraintSpec::default(),
+    }.resolve(base_layout)?;
+    let text = std::fs::read_to_string(&args.corpus)?;
+    let outputs: Vec<String> = TextStatistics::characters(&text).into_iter().filter(|c| twiddler::is_typeable(c)).collect();
+    let layout = layout_costs::optimize_layout::<K, N, L, M, _>(model, &text, outputs, confusion_table.as_ref(), &constraints, &config, seed)?;
+    println!("cost per character: {} (transitions {}, confusions {})", layout.cost.total, layout.cost.transition, layout.cost.confusion);
+    if !constraints.base_layout.is_empty() {
+        println!("{} outputs of the base layout moved to different chords", layout.n_changed);
+    }
+
+    let layout_path = match &args.layout_out {
+        Some(path) => path.clone(),
+        None => store.new_path(Artifact::Config, "layout", "json")?,
+    };
+    let config_path = match &args.config_out {
+        Some(path) => path.clone(),
+        None => store.new_path(Artifact::Config, "config", "cfg")?,
+    };
+    serde_json::to_writer_pretty(std::fs::File::create(&layout_path)?, &layout)?;
+    std::fs::write(&config_path, twiddler::vocab_to_config(layout.vocab)?)?;
+    println!("wrote layout to {} and config to {}", layout_path, config_path);
+    Ok(())
+}
+
+fn write_catalog<M: Module, P: PossibilityModel>(model: &M, possibility_model: &P, spread_model: Option<&dyn PossibilitySpread>, args: &CatalogArgs, store: &DataStore) -> Result<(), Box<dyn Error>> {
+    let common_chords = catalog::common_chords::<K, N, L, M>(model, args.n_common);
+    let mut catalog = ChordCatalog::<K, N, L>::new(model, possibility_model, spread_model, common_chords)?;
+    catalog.sort(args.sort_by);
+    println!("{} valid chords", catalog.entries.len());
+
+    let csv_path = match &args.csv_out {
+        Some(path) => path.clone(),
+        None => store.new_path(Artifact::Report, "catalog", "csv")?,
+    };
+    let report_path = match &args.report_out {
+        Some(path) => path.clone(),
+        None => store.new_path(Artifact::Report, "catalog", "txt")?,
+    };
+    catalog.save_csv(&csv_path)?;
+    catalog.save_report(&report_path)?;
+    println!("wrote the catalog to {} and {}", csv_path, report_path);
+    Ok(())
+}
+
+fn trained_model<E: RewardEmbedding>(training: &TrainingArgs, checkpoint: Option<&str>, store: &DataStore, seed: u64) -> Result<Box<RewardModel<N, E>>, Box<dyn Error>> {
+    // the model saved in the checkpoint if there is one, or else a newly trained one
+    match checkpoint {
+        Some(checkpoint) => {
+            let dir = store.find(Artifact::Model, checkpoint).ok_or_else(|| format!("can't find the checkpoint {}", checkpoint))?;
+            let (model, info) = train::load_checkpoint::<N, E>(&dir.to_string_lossy())?;
+            match info.best_epoch {
+                Some(best_epoch) => println!("loaded the model from {} (trained for {} epochs with seed {}, with the weights from epoch {})", dir.display(), info.epochs_trained, info.seed, best_epoch),
+                None => println!("loaded the model from {} (trained for {} epochs with seed {})", dir.display(), info.epochs_trained, info.seed),
+            }
+            Ok(model)
+        },
+        None => train::train::<K, N, L, E>(store, &training.config()?, seed),
+    }
+}
+
+fn find_decoder(store: &DataStore, decoder: &str) -> String {
+    // a decoder or session package can be given by its name in the data store; otherwise it's taken as a path
+    store.find(Artifact::Decoder, decoder).map_or_else(|| decoder.to_string(), |path| path.to_string_lossy().to_string())
+}
+
+fn check_twiddler_config(args: &CheckConfigArgs, store: &DataStore) -> Result<(), Box<dyn Error>> {
+    let config = std::fs::read(store.find(Artifact::Config, &args.config).unwrap_or_else(|| args.config.clone().into()))?;
+    let (decoder, _): (C, _) = session::load_decoder(&find_decoder(store, &args.decoder))?;
+    let expected = <C as ChordTrialUtils<K, N, L, R, (), TwiddlerExponentialSampler<R>>>::get_config(&decoder)?;
+    if config == expected {
+        println!("{} matches {}", args.config, args.decoder);
+        return Ok(());
+    }
+    let (only_in_config, only_in_expected) = twiddler::config_differences(&config, &expected)?;
+    println!("{} lines of {} aren't in the config for {}:", only_in_config.len(), args.config, args.decoder);
+    for line in only_in_config.iter().take(10) {
+        println!("    {}", line);
+    }
+    println!("{} lines of the config for {} aren't in {}:", only_in_expected.len(), args.decoder, args.config);
+    for line in only_in_expected.iter().take(10) {
+        println!("    {}", line);
+    }
+    Err(format!("{} doesn't match {}", args.config, args.decoder).into())
+}
+
+fn run_twiddler(command: Command, store: &DataStore, seed: u64) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::GenConfig(args) => {
+            let spec = sampler_spec(&args.sampler, store, seed)?;
+            let code_config: CodeConfig = match &args.code_config {
+                Some(path) => serde_json::from_reader(std::fs::File::open(path)?)?,
+                None => CodeConfig::default(),
+            };
+            data_collection_keymap_gen::run::<K, N, L, Spec, S, C>(&spec, &code_config, seed, provenance(&args.sampler)?, store, args.package_out.as_deref())
+        },
+        Command::Gather(args) => {
+            gather_chords::run::<K, N, L, (), TwiddlerExponentialSampler<R>, C>(&find_decoder(store, &args.decoder), seed, args.participant.as_deref(), store, args.results_out.as_deref())
+        },
+        Command::Train(args) => {
+            let config = args.training.config()?;
+            let checkpoint_dir = match (args.checkpoint_dir, args.save) {
+                (None, true) => Some(store.new_path(Artifact::Model, "model", "")?),
+                (checkpoint_dir, _) => checkpoint_dir,
+            };
+            let checkpoint_dir = checkpoint_dir.as_deref();
+            match args.model {
+                ModelKind::Single => train::run::<K, N, L, SingleModel<N>>(store, &config, seed, checkpoint_dir),
+                ModelKind::Ensemble => train::run::<K, N, L, EnsembleModel<N>>(store, &config, seed, checkpoint_dir),
+                ModelKind::Heuristic => Err("the heuristic model doesn't need training".into()),
+            }
+        },
+        Command::Sample(args) => {
+            print_samples::<Spec, S>(&sampler_spec(&args.sampler, store, seed)?, seed, args.count)
+        },
+        Command::Evaluate(args) => {
+            let config = args.training.config()?;
+            match args.model {
+                ModelKind::Single => { train::compare_to_heuristic::<K, N, L, SingleModel<N>>(store, &config, seed, None)?; },
+                ModelKind::Ensemble => { train::compare_to_heuristic::<K, N, L, EnsembleModel<N>>(store, &config, seed, None)?; },
+                ModelKind::Heuristic => {
+                    let heuristic_loss = train::evaluate::<K, N, L, _>(&HeuristicModel::<K, N, L>::new(), store, &config, seed)?;
+                    println!("test loss of heuristic model: {}", heuristic_loss);
+                },
+            }
+            Ok(())
+        },
+        Command::Search(args) => {
+            let config = args.training.config()?;
+            let space = match &args.search_space {
+                Some(path) => SearchSpace::load(path)?,
+                None => SearchSpace::default(),
+            };
+            let strategy = match args.random {
+                Some(n_candidates) => SearchStrategy::Random { n_candidates },
+                None => SearchStrategy::Grid,
+            };
+            let leaderboard_out = args.leaderboard_out.as_deref();
+            match args.model {
+                ModelKind::Single => hyperparameter_search::run::<K, N, L, SingleModel<N>>(store, &config, &space, strategy, args.folds, seed, leaderboard_out),
+                ModelKind::Ensemble => hyperparameter_search::run::<K, N, L, EnsembleModel<N>>(store, &config, &space, strategy, args.folds, seed, leaderboard_out),
+                ModelKind::Heuristic => Err("the heuristic model has no hyperparameters to search".into()),
+            }
+        },
+        Command::Outliers(args) => {
+            let config: OutlierConfig = match &args.outlier_config {
+                Some(path) => serde_json::from_reader(std::fs::File::open(path)?)?,
+                None => OutlierConfig::default(),
+            };
+            let reports = train::outlier_reports::<K, N, L>(store, &config)?;
+            for report in &reports {
+                print!("{}", report);
+            }
+            if let Some(path) = &args.report_out {
+                serde_json::to_writer_pretty(std::fs::File::create(path)?, &reports)?;
+            }
+            Ok(())
+        },
+        Command::Confusion(args) => {
+            let table = train::confusion_table::<K, N, L>(store)?;
+            println!("{} confusions found in {} trials", table.entries.len(), table.n_trials);
+            for entry in table.entries.iter().take(args.top) {
+                println!("{}", entry);
+            }
+            if let Some(path) = &args.csv_out {
+                table.save_csv(path)?;
+            }
+            if let Some(path) = &args.json_out {
+                table.save_json(path)?;
+            }
+            Ok(())
+        },
+        Command::Optimize(args) => {
+            let checkpoint = args.checkpoint.as_deref();
+            match args.model {
+                ModelKind::Single => optimize(&*trained_model::<SingleModel<N>>(&args.training, checkpoint, store, seed)?, &args, store, seed),
+                ModelKind::Ensemble => optimize(&*trained_model::<EnsembleModel<N>>(&args.training, checkpoint, store, seed)?, &args, store, seed),
+                ModelKind::Heuristic if checkpoint.is_some() => Err("the heuristic model isn't trained, so it has no checkpoints".into()),
+                ModelKind::Heuristic => optimize(&HeuristicModel::<K, N, L>::new(), &args, store, seed),
+            }
+        },
+        Command::Practice(args) => {
+            let config: PracticeConfig = match &args.practice_config {
+                Some(path) => PracticeConfig::load(path)?,
+                None => PracticeConfig::default(),
+            };
+            let layout: OptimizedLayout<K, N, L> = serde_json::from_reader(std::fs::File::open(&args.layout)?)?;
+            let progress_path = match &args.progress {
+                Some(path) => path.clone(),
+                None => store.named_path(Artifact::Results, "practice_progress.json")?,
+            };
+            practice::run(&layout.vocab, &args.corpus, &progress_path, args.participant.as_deref(), store, &config, seed)?;
+            println!("saved progress to {}", progress_path);
+            Ok(())
+        },
+        Command::Catalog(args) => {
+            let checkpoint = args.checkpoint.as_deref();
+            match args.model {
+                ModelKind::Single => {
+                    let model = trained_model::<SingleModel<N>>(&args.training, checkpoint, store, seed)?;
+                    write_catalog(&*model, &model.chord_embedding, None, &args, store)
+                },
+                ModelKind::Ensemble => {
+                    let model = trained_model::<EnsembleModel<N>>(&args.training, checkpoint, store, seed)?;
+                    write_catalog(&*model, &model.chord_embedding, Some(&model.chord_embedding), &args, store)
+                },
+                ModelKind::Heuristic if checkpoint.is_some() => Err("the heuristic model isn't trained, so it has no checkpoints".into()),
+                ModelKind::Heuristic => {
+                    let model = HeuristicModel::<K, N, L>::new();
+                    write_catalog(&model, &model, None, &args, store)
+                },
+            }
+        },
+        Command::CheckConfig(args) => check_twiddler_config(&args, store),
+        Command::List(args) => {
+            for path in store.list(args.kind)? {
+                println!("{}", path.display());
+            }
+            Ok(())
+        },
+    }
+}
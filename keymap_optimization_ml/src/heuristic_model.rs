@@ -0,0 +1,117 @@
+use std::marker::PhantomData;
+use strum::EnumCount;
+use tch::nn::Module;
+use tch::Tensor;
+use keymap_optimization::keyboard_config::{Chord, Finger, Key, Layout};
+
+use crate::reward_model::PossibilityModel;
+use crate::train::tensor_to_chords;
+
+// a hand-tuned model which predicts the same things as the reward model (time, accuracy, and whether the combination is possible)
+// from simple features of the chords. it doesn't need any data, so it can be used to rank chords before enough trials have been
+// collected for the reward model to be usable, and as a baseline to measure how much the reward model actually learns.
+
+// the difficulty of a single chord is a weighted sum of these features
+const DIFFICULTY_PER_EXTRA_KEY: f64 = 0.5;  // each key after the first
+const DIFFICULTY_PER_SAME_FINGER_KEY: f64 = 1.5;  // each key after the first pressed by the same finger
+const DIFFICULTY_PER_THUMB_KEY: f64 = 0.5;  // each thumb (modifier) key
+
+// the probability that a chord is possible is a logistic function of its difficulty, which is 1/2 at POSSIBLE_MIDPOINT
+const POSSIBLE_MIDPOINT: f64 = 4.0;
+const POSSIBLE_SCALE: f64 = 1.0;
+
+// the time (in seconds) for a whole trial grows with the difficulty of the chords and the number of fingers that have to move
+// to switch between them
+const TIME_BASE: f64 = 2.5;
+const TIME_PER_DIFFICULTY: f64 = 0.5;
+const TIME_PER_FINGER_CHANGE: f64 = 0.4;
+
+// similarly, the accuracy shrinks with the difficulty of the chords and the number of fingers that have to move
+const ACCURACY_BASE: f64 = 0.98;
+const ACCURACY_PER_DIFFICULTY: f64 = 0.03;
+const ACCURACY_PER_FINGER_CHANGE: f64 = 0.01;
+
+#[derive(Debug)]
+pub struct HeuristicModel<K: Key, const N: usize, L: Layout<K, N>> {
+    // fn() -> _ so that the model is Send regardless of K and L
+    _marker: PhantomData<fn() -> (K, L)>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> HeuristicModel<K, N, L> {
+    pub fn new() -> Self {
+        Self { _marker: PhantomData }
+    }
+
+    fn keys_per_finger(chord: &Chord<K, N, L>) -> [usize; Finger::COUNT] {
+        let mut counts = [0; Finger::COUNT];
+        for key in chord.keys() {
+            counts[L::finger(key) as usize] += 1;
+        }
+        counts
+    }
+
+    pub fn chord_difficulty(chord: &Chord<K, N, L>) -> f64 {
+        let keys_per_finger = Self::keys_per_finger(chord);
+        let extra_keys = chord.n_keys().saturating_sub(1);
+        let same_finger_keys: usize = keys_per_finger.iter().map(|n| n.saturating_sub(1)).sum();
+        let thumb_keys = keys_per_finger[Finger::Thumb as usize];
+        DIFFICULTY_PER_EXTRA_KEY * extra_keys as f64
+            + DIFFICULTY_PER_SAME_FINGER_KEY * same_finger_keys as f64
+            + DIFFICULTY_PER_THUMB_KEY * thumb_keys as f64
+    }
+
+    pub fn possible_probability(chord: &Chord<K, N, L>) -> f64 {
+        1.0 / (1.0 + ((Self::chord_difficulty(chord) - POSSIBLE_MIDPOINT) / POSSIBLE_SCALE).exp())
+    }
+
+    pub fn n_fingers_changing(chord_1: &Chord<K, N, L>, chord_2: &Chord<K, N, L>) -> usize {
+        // the number of fingers which press or release at least one key when switching from one chord to the other
+        let mut changed = [false; Finger::COUNT];
+        for key in chord_1.symmetric_difference(chord_2).keys() {
+            changed[L::finger(key) as usize] = true;
+        }
+        changed.iter().filter(|&&c| c).count()
+    }
+
+    pub fn predict_pair(chord_pair: &[Chord<K, N, L>; 2]) -> [f64; 3] {
+        // returns [time, accuracy, probability that the combination is possible], in the same order as the reward model output
+        let difficulty = Self::chord_difficulty(&chord_pair[0]) + Self::chord_difficulty(&chord_pair[1]);
+        let fingers_changing = Self::n_fingers_changing(&chord_pair[0], &chord_pair[1]) as f64;
+        let time = TIME_BASE + TIME_PER_DIFFICULTY * difficulty + TIME_PER_FINGER_CHANGE * fingers_changing;
+        let accuracy = (ACCURACY_BASE - ACCURACY_PER_DIFFICULTY * difficulty - ACCURACY_PER_FINGER_CHANGE * fingers_changing).clamp(0.0, 1.0);
+        // as in the reward model, the combination is possible exactly when both chords are
+        let possible = Self::possible_probability(&chord_pair[0]) * Self::possible_probability(&chord_pair[1]);
+        [time, accuracy, possible]
+    }
+}
+
+fn logit(p: f64) -> f64 {
+    // clamped so that the heuristic's (over)confidence can't make the loss infinite
+    let p = p.clamp(1e-6, 1.0 - 1e-6);
+    (p / (1.0 - p)).ln()
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> Module for HeuristicModel<K, N, L> {
+    fn forward(&self, xs: &Tensor) -> Tensor {
+        // the input and output have the same format as for RewardModel: each row of the input is the concatenation of the
+        // two chords of a pair, and the output is [time, accuracy, logit of the probability that the pair is possible]
+        let predictions: Vec<f32> = tensor_to_chords::<K, N, L>(xs)
+            .chunks(2)
+            .flat_map(|pair| {
+                let [time, accuracy, possible] = Self::predict_pair(&[pair[0].clone(), pair[1].clone()]);
+                [time, accuracy, logit(possible)].map(|p| p as f32)
+            })
+            .collect();
+        Tensor::from_slice(&predictions).reshape([-1, 3])
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> PossibilityModel for HeuristicModel<K, N, L> {
+    fn possible_probabilities(&self, chords: &Tensor) -> Tensor {
+        let probabilities: Vec<f32> = tensor_to_chords::<K, N, L>(chords)
+            .iter()
+            .map(|chord| Self::possible_probability(chord) as f32)
+            .collect();
+        Tensor::from_slice(&probabilities).reshape([-1, 1])
+    }
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use itertools::Itertools;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use serde::{Deserialize, Serialize};
+use keymap_optimization::keyboard_config::{Key, Layout};
+use keymap_optimization::data_store::{Artifact, DataStore};
+
+use crate::reward_model::RewardEmbedding;
+use crate::train::{self, TrainConfig};
+
+// a search over the hyperparameters in TrainConfig. each candidate config is scored by cross validation on the training
+// part of the data (the test part is never used, so it can still be used to evaluate the winner), and the candidates are
+// written to a leaderboard sorted by their mean validation loss. training always happens on the cpu.
+
+// the values to try for each hyperparameter. an empty list keeps the value from the base config
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchSpace {
+    pub hidden_dim: Vec<i64>,  // used for all three embeddings (speed, accuracy, possible)
+    pub hidden_num_layers: Vec<i64>,
+    pub hidden_dim_combined: Vec<i64>,  // used for both combiners
+    pub hidden_combined_num_layers: Vec<i64>,
+    pub learning_rate: Vec<f64>,
+    pub weight_decay: Vec<f64>,
+    pub xe_weight: Vec<f64>,
+    pub batch_size: Vec<Option<usize>>,
+}
+
+impl Default for SearchSpace {
+    fn default() -> Self {
+        Self {
+            hidden_dim: vec![4, 8, 16],
+            hidden_num_layers: vec![1, 2],
+            hidden_dim_combined: vec![4, 8],
+            hidden_combined_num_layers: vec![0, 1],
+            learning_rate: vec![1e-3, 3e-3],
+            weight_decay: vec![0.0, 1e-4],
+            xe_weight: vec![],
+            batch_size: vec![],
+        }
+    }
+}
+
+impl SearchSpace {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    fn n_choices(&self) -> [usize; 8] {
+        // the number of values to choose from for each hyperparameter, in the order used by apply
+        [self.hidden_dim.len(), self.hidden_num_layers.len(), self.hidden_dim_combined.len(), self.hidden_combined_num_layers.len(),
+         self.learning_rate.len(), self.weight_decay.len(), self.xe_weight.len(), self.batch_size.len()].map(|n| n.max(1))
+    }
+
+    pub fn size(&self) -> usize {
+        self.n_choices().iter().product()
+    }
+
+    fn apply(&self, base: &TrainConfig, choice: &[usize]) -> TrainConfig {
+        // choice has one index per hyperparameter; an index into an empty list leaves the base value
+        fn pick<T: Clone>(values: &[T], idx: usize, base: &mut T) {
+            if let Some(value) = values.get(idx) {
+                *base = value.clone();
+            }
+        }
+        let mut config = base.clone();
+        for dim in [&mut config.model.hidden_dim_speed, &mut config.model.hidden_dim_accuracy, &mut config.model.hidden_dim_possible] {
+            pick(&self.hidden_dim, choice[0], dim);
+        }
+        pick(&self.hidden_num_layers, choice[1], &mut config.model.hidden_num_layers);
+        for dim in [&mut config.model.hidden_dim_speed_combined, &mut config.model.hidden_dim_accuracy_combined] {
+            pick(&self.hidden_dim_combined, choice[2], dim);
+        }
+        for n_layers in [&mut config.model.hidden_speed_combined_num_layers, &mut config.model.hidden_accuracy_combined_num_layers] {
+            pick(&self.hidden_combined_num_layers, choice[3], n_layers);
+        }
+        pick(&self.learning_rate, choice[4], &mut config.learning_rate);
+        pick(&self.weight_decay, choice[5], &mut config.weight_decay);
+        pick(&self.xe_weight, choice[6], &mut config.xe_weight);
+        pick(&self.batch_size, choice[7], &mut config.batch_size);
+        config
+    }
+
+    pub fn candidates(&self, base: &TrainConfig, strategy: SearchStrategy, seed: u64) -> Vec<TrainConfig> {
+        let grid: Vec<Vec<usize>> = self.n_choices().iter().map(|&n| 0..n).multi_cartesian_product().collect();
+        match strategy {
+            SearchStrategy::Grid => grid.iter().map(|choice| self.apply(base, choice)).collect(),
+            SearchStrategy::Random { n_candidates } => {
+                // distinct points of the grid, so asking for at least the whole grid is the same as a grid search (in a random order)
+                let mut rng = StdRng::seed_from_u64(seed);
+                sample(&mut rng, grid.len(), n_candidates.min(grid.len())).iter().map(|idx| self.apply(base, &grid[idx])).collect()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchStrategy {
+    Grid,
+    Random { n_candidates: usize },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub mean_validation_loss: f64,
+    pub std_validation_loss: f64,
+    pub fold_validation_losses: Vec<f64>,
+    pub config: TrainConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub seed: u64,
+    pub n_folds: usize,
+    pub entries: Vec<LeaderboardEntry>,  // best first
+}
+
+impl Leaderboard {
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        Ok(serde_json::to_writer_pretty(std::fs::File::create(path)?, self)?)
+    }
+
+    pub fn best(&self) -> Option<&LeaderboardEntry> {
+        self.entries.first()
+    }
+}
+
+pub fn search<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(store: &DataStore, base: &TrainConfig, space: &SearchSpace, strategy: SearchStrategy, n_folds: usize, seed: u64) -> Result<Leaderboard, Box<dyn Error>> {
+    let data = train::get_formatted_data::<K, N, L>(store, seed, base)?;
+    let candidates = space.candidates(base, strategy, seed);
+    let mut entries = Vec::new();
+    for (i, config) in candidates.into_iter().enumerate() {
+        println!("candidate {}: {:?}", i, config);
+        let fold_validation_losses = train::cross_validate::<N, E>(&data, &config, n_folds, seed)?;
+        let n = fold_validation_losses.len() as f64;
+        let mean = fold_validation_losses.iter().sum::<f64>() / n;
+        let std = (fold_validation_losses.iter().map(|loss| (loss - mean).powi(2)).sum::<f64>() / n).sqrt();
+        println!("candidate {}: mean validation loss {:<24}, std {:<24}", i, mean as f32, std as f32);
+        entries.push(LeaderboardEntry { rank: 0, mean_validation_loss: mean, std_validation_loss: std, fold_validation_losses, config });
+    }
+    entries.sort_by(|a, b| a.mean_validation_loss.total_cmp(&b.mean_validation_loss));
+    for (rank, entry) in entries.iter_mut().enumerate() {
+        entry.rank = rank + 1;
+    }
+    Ok(Leaderboard { seed, n_folds, entries })
+}
+
+pub fn run<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(store: &DataStore, base: &TrainConfig, space: &SearchSpace, strategy: SearchStrategy, n_folds: usize, seed: u64, leaderboard_path: Option<&str>) -> Result<(), Box<dyn Error>> {
+    // the leaderboard is written to leaderboard_path if it is given, and otherwise to a timestamped file in the store's reports
+    let leaderboard_path = match leaderboard_path {
+        Some(path) => path.to_string(),
+        None => store.new_path(Artifact::Report, "hyperparameter_leaderboard", "json").map_err(|e| format!("Error creating the reports directory: {}", e))?,
+    };
+    let leaderboard = search::<K, N, L, E>(store, base, space, strategy, n_folds, seed).map_err(|e| format!("Error during hyperparameter search: {}", e))?;
+    if let Some(best) = leaderboard.best() {
+        println!("best mean validation loss: {}, with config: {:?}", best.mean_validation_loss, best.config);
+    }
+    leaderboard.save(&leaderboard_path).map_err(|e| format!("Error saving leaderboard: {}", e))?;
+    println!("saved leaderboard to {}", leaderboard_path);
+    Ok(())
+}
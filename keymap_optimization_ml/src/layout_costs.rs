@@ -0,0 +1,137 @@
+use std::error::Error;
+use std::marker::PhantomData;
+use serde::{Deserialize, Serialize};
+use tch::nn::Module;
+use keymap_optimization::keyboard_config::{Chord, Key, Layout};
+use keymap_optimization::chord_preferences::N_REPETITIONS_PER_TRIAL;
+use keymap_optimization::chord_preferences::confusion::ConfusionModel;
+use keymap_optimization::chord_preferences::data_cleaning::switching_time;
+use keymap_optimization::layout_optimization::corpus::TextStatistics;
+use keymap_optimization::layout_optimization::constraints::LayoutConstraints;
+use keymap_optimization::layout_optimization::multichar::{self, MulticharConfig};
+use keymap_optimization::layout_optimization::optimizer::{ChordCostModel, LayoutObjective, OptimizedLayout, OptimizerConfig};
+
+use crate::chord_samplers::all_chords;
+use crate::train::predict_pairs;
+
+// the costs the layout optimizer uses, from the predictions of a model (a reward model or the heuristic model) for chord pairs:
+// the time to switch from one chord to the next, plus penalties for the chance of a mistake and the chance that the
+// combination can't be typed at all
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostWeights {
+    // the cost of each mistake, in seconds
+    pub error_cost: f64,
+    // the cost of a transition which is impossible, in seconds
+    pub impossible_cost: f64,
+}
+
+impl Default for CostWeights {
+    fn default() -> Self {
+        Self { error_cost: 1.0, impossible_cost: 10.0 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OptimizeConfig {
+    pub optimizer: OptimizerConfig,
+    pub costs: CostWeights,
+    // the number of chords the outputs are assigned to (see candidate_chords). this limits the number of outputs, so it
+    // should be raised to give many multi-character outputs chords
+    pub n_candidates: usize,
+    // if given, frequent words and other strings of the corpus get chords of their own where that's predicted to be worth it
+    pub multichar: Option<MulticharConfig>,
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        Self { optimizer: OptimizerConfig::default(), costs: CostWeights::default(), n_candidates: 300, multichar: None }
+    }
+}
+
+impl OptimizeConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+}
+
+pub struct ModelChordCosts<'a, K: Key, const N: usize, L: Layout<K, N>, M: Module> {
+    model: &'a M,
+    weights: CostWeights,
+    _marker: PhantomData<fn() -> (K, L)>,
+}
+
+impl<'a, K: Key, const N: usize, L: Layout<K, N>, M: Module> ModelChordCosts<'a, K, N, L, M> {
+    pub fn new(model: &'a M, weights: CostWeights) -> Self {
+        Self { model, weights, _marker: PhantomData }
+    }
+
+    fn pair_costs(&self, pairs: &[(&Chord<K, N, L>, &Chord<K, N, L>)]) -> Vec<f64> {
+        // the model predicts the time for a whole trial, which alternates between the two chords
+        predict_pairs(self.model, pairs).into_iter().map(|[time, accuracy, possible_logit]| {
+            let possible = 1.0 / (1.0 + (-possible_logit).exp());
+            switching_time(time, N_REPETITIONS_PER_TRIAL)
+                + self.weights.error_cost * (1.0 - accuracy.clamp(0.0, 1.0))
+                + self.weights.impossible_cost * (1.0 - possible)
+        }).collect()
+    }
+
+    pub fn repetition_costs(&self, chords: &[Chord<K, N, L>]) -> Vec<f64> {
+        // the cost of typing each chord twice in a row, as a measure of how easy the chord is by itself
+        chords.chunks(1024).flat_map(|chunk| self.pair_costs(&chunk.iter().map(|c| (c, c)).collect::<Vec<_>>())).collect()
+    }
+}
+
+impl<'a, K: Key, const N: usize, L: Layout<K, N>, M: Module> ChordCostModel<K, N, L> for ModelChordCosts<'a, K, N, L, M> {
+    fn transition_cost(&self, from: &Chord<K, N, L>, to: &Chord<K, N, L>) -> f64 {
+        self.pair_costs(&[(from, to)])[0]
+    }
+
+    fn transition_costs(&self, chords: &[Chord<K, N, L>]) -> Vec<Vec<f64>> {
+        // one batch per starting chord
+        chords.iter().map(|from| self.pair_costs(&chords.iter().map(|to| (from, to)).collect::<Vec<_>>())).collect()
+    }
+}
+
+pub fn candidate_chords<K: Key, const N: usize, L: Layout<K, N>, M: Module>(costs: &ModelChordCosts<K, N, L, M>, n_candidates: usize, constraints: &LayoutConstraints<K, N, L>) -> Vec<(Chord<K, N, L>, f64)> {
+    // the n_candidates valid chords allowed by the constraints which are cheapest to repeat, with those costs, cheapest first.
+    // the optimizer only considers these (and the chords of the pinned outputs and the base layout), since it needs the costs
+    // of the transitions between every pair of candidates
+    let chords: Vec<Chord<K, N, L>> = all_chords::<K, N, L>().into_iter().filter(|c| constraints.allows(c)).collect();
+    let chord_costs = costs.repetition_costs(&chords);
+    let mut ranked: Vec<(Chord<K, N, L>, f64)> = chords.into_iter().zip(chord_costs).collect();
+    ranked.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    ranked.truncate(n_candidates);
+    ranked
+}
+
+pub fn optimize_layout<K: Key, const N: usize, L: Layout<K, N>, M: Module, C: ConfusionModel<K, N, L>>(model: &M, text: &str, outputs: Vec<String>, confusion_model: Option<&C>, constraints: &LayoutConstraints<K, N, L>, config: &OptimizeConfig, seed: u64) -> Result<OptimizedLayout<K, N, L>, Box<dyn Error>> {
+    // outputs are the ones the layout needs (e.g. the characters of the text); multi-character ones are added to them if
+    // config.multichar is given
+    let costs = ModelChordCosts::<K, N, L, M>::new(model, config.costs.clone());
+    let (candidates, chord_costs): (Vec<Chord<K, N, L>>, Vec<f64>) = candidate_chords(&costs, config.n_candidates, constraints).into_iter().unzip();
+    let outputs = match &config.multichar {
+        Some(multichar_config) => {
+            // the outputs of the constraints are in the layout anyway, so they count towards the limits
+            let mut outputs = outputs;
+            for (_, output) in constraints.pinned.iter().chain(&constraints.base_layout) {
+                if !outputs.contains(output) {
+                    outputs.push(output.clone());
+                }
+            }
+            let (outputs, chosen) = multichar::choose_multichar(text, outputs, &chord_costs, multichar_config);
+            println!("giving chords to {} multi-character outputs:", chosen.len());
+            for output in &chosen {
+                println!("  {:?}: typed {} times, saving an estimated {:.1}", output.output, output.count, output.saving);
+            }
+            outputs
+        },
+        None => outputs,
+    };
+    let stats = TextStatistics::from_text(text, &outputs);
+    let objective = LayoutObjective::new(stats, candidates, &costs, confusion_model, config.optimizer.confusion_weight, constraints)?;
+    println!("optimizing the assignment of {} outputs to {} candidate chords", objective.n_outputs(), objective.candidates().len());
+    Ok(objective.optimize(&config.optimizer, seed))
+}
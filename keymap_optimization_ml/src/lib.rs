@@ -1,5 +1,11 @@
 pub mod reward_model;
 pub mod train;
+pub mod hyperparameter_search;
 pub mod chord_samplers;
+pub mod heuristic_model;
+pub mod registry;
+pub mod layout_costs;
+pub mod catalog;
+pub mod cli;
 
 mod tests;
@@ -0,0 +1,135 @@
+use std::error::Error;
+use tch::Tensor;
+use keymap_optimization::keyboard_config::{Chord, ChordSampler, Key, Layout};
+use keymap_optimization::twiddler::TwiddlerExponentialSampler;
+use keymap_optimization::data_store::DataStore;
+
+use crate::chord_samplers::{MostUncertainPossibilityChordSampler, PossibleChordSampler};
+use crate::heuristic_model::HeuristicModel;
+use crate::reward_model::{Ensemble, PossibilityModel, RewardEmbeddingBase, RewardModel};
+use crate::train::{self, TrainConfig};
+
+// the samplers and models which can be chosen at runtime (e.g. from the command line) instead of with type parameters.
+// AnySampler and AnyPossibilityModel dispatch to the generic implementations, so they can be passed to anything which takes
+// a ChordSampler or a PossibilityModel (e.g. data_collection_keymap_gen::run) and one build can use any of them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display, strum_macros::EnumString, strum_macros::IntoStaticStr, strum_macros::VariantArray)]
+#[strum(serialize_all = "kebab-case")]
+pub enum SamplerKind {
+    // repeatedly add a random key with probability 0.6 (restricted to valid chords); doesn't use a model
+    Exponential,
+    // rejection sample valid chords, accepting each with the estimated probability that it is possible
+    Possible,
+    // prefer the chords whose possibility is most uncertain
+    Uncertain,
+}
+
+impl SamplerKind {
+    pub fn names() -> Vec<&'static str> {
+        <Self as strum::VariantArray>::VARIANTS.iter().map(|kind| kind.into()).collect()
+    }
+
+    pub fn uses_model(self) -> bool {
+        self != SamplerKind::Exponential
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::Display, strum_macros::EnumString, strum_macros::IntoStaticStr, strum_macros::VariantArray)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ModelKind {
+    // a single reward model
+    Single,
+    // an ensemble of reward models
+    Ensemble,
+    // the hand-tuned heuristic model, which needs no training
+    Heuristic,
+}
+
+impl ModelKind {
+    pub fn names() -> Vec<&'static str> {
+        <Self as strum::VariantArray>::VARIANTS.iter().map(|kind| kind.into()).collect()
+    }
+}
+
+pub type SingleModel<const N: usize> = RewardEmbeddingBase<N>;
+pub type EnsembleModel<const N: usize> = Ensemble<RewardModel<N, RewardEmbeddingBase<N>>>;
+
+pub enum AnyPossibilityModel<K: Key, const N: usize, L: Layout<K, N>> {
+    Single(SingleModel<N>),
+    Ensemble(EnsembleModel<N>),
+    Heuristic(HeuristicModel<K, N, L>),
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> AnyPossibilityModel<K, N, L> {
+    pub fn new(kind: ModelKind, store: &DataStore, config: &TrainConfig, seed: u64) -> Result<Self, Box<dyn Error>> {
+        // train the chosen model on the results in the store (unless it's the heuristic model, which doesn't need training)
+        Ok(match kind {
+            ModelKind::Single => AnyPossibilityModel::Single(train::train::<K, N, L, SingleModel<N>>(store, config, seed)?.chord_embedding),
+            ModelKind::Ensemble => AnyPossibilityModel::Ensemble(train::train::<K, N, L, EnsembleModel<N>>(store, config, seed)?.chord_embedding),
+            ModelKind::Heuristic => AnyPossibilityModel::Heuristic(HeuristicModel::new()),
+        })
+    }
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> PossibilityModel for AnyPossibilityModel<K, N, L> {
+    fn possible_probabilities(&self, chords: &Tensor) -> Tensor {
+        match self {
+            AnyPossibilityModel::Single(model) => model.possible_probabilities(chords),
+            AnyPossibilityModel::Ensemble(model) => model.possible_probabilities(chords),
+            AnyPossibilityModel::Heuristic(model) => model.possible_probabilities(chords),
+        }
+    }
+}
+
+// the initialization info for AnySampler
+pub struct SamplerSpec<K: Key, const N: usize, L: Layout<K, N>> {
+    pub kind: SamplerKind,
+    // only needed if kind.uses_model()
+    pub model: Option<AnyPossibilityModel<K, N, L>>,
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>> SamplerSpec<K, N, L> {
+    pub fn new(kind: SamplerKind, model_kind: ModelKind, store: &DataStore, config: &TrainConfig, seed: u64) -> Result<Self, Box<dyn Error>> {
+        // only train a model if the sampler is going to use it
+        let model = if kind.uses_model() {
+            Some(AnyPossibilityModel::new(model_kind, store, config, seed)?)
+        } else {
+            None
+        };
+        Ok(SamplerSpec { kind, model })
+    }
+}
+
+pub enum AnySampler<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng> {
+    Exponential(TwiddlerExponentialSampler<R>),
+    Possible(PossibleChordSampler<K, N, L, R>),
+    Uncertain(MostUncertainPossibilityChordSampler<K, N, L, R>),
+}
+
+impl<K: Key, const N: usize, L: Layout<K, N>, R: rand::Rng> ChordSampler<K, N, L, R, SamplerSpec<K, N, L>> for AnySampler<K, N, L, R> {
+    fn new(rng: R, spec: &SamplerSpec<K, N, L>) -> Result<Self, Box<dyn Error>> {
+        let model = || spec.model.as_ref().ok_or_else(|| format!("the {} sampler needs a model", spec.kind));
+        Ok(match spec.kind {
+            SamplerKind::Exponential => AnySampler::Exponential(<TwiddlerExponentialSampler<R> as ChordSampler<K, N, L, R, ()>>::new(rng, &())?),
+            SamplerKind::Possible => AnySampler::Possible(<PossibleChordSampler<K, N, L, R> as ChordSampler<K, N, L, R, AnyPossibilityModel<K, N, L>>>::new(rng, model()?)?),
+            SamplerKind::Uncertain => AnySampler::Uncertain(<MostUncertainPossibilityChordSampler<K, N, L, R> as ChordSampler<K, N, L, R, AnyPossibilityModel<K, N, L>>>::new(rng, model()?)?),
+        })
+    }
+
+    fn sample_chord(&mut self) -> Chord<K, N, L> {
+        match self {
+            AnySampler::Exponential(sampler) => <TwiddlerExponentialSampler<R> as ChordSampler<K, N, L, R, ()>>::sample_chord(sampler),
+            AnySampler::Possible(sampler) => <PossibleChordSampler<K, N, L, R> as ChordSampler<K, N, L, R, AnyPossibilityModel<K, N, L>>>::sample_chord(sampler),
+            AnySampler::Uncertain(sampler) => <MostUncertainPossibilityChordSampler<K, N, L, R> as ChordSampler<K, N, L, R, AnyPossibilityModel<K, N, L>>>::sample_chord(sampler),
+        }
+    }
+
+    fn sample_distinct_chords(&mut self, n: usize) -> Result<Vec<Chord<K, N, L>>, Box<dyn Error>> {
+        // forward this too, since the samplers which use a model override it
+        match self {
+            AnySampler::Exponential(sampler) => <TwiddlerExponentialSampler<R> as ChordSampler<K, N, L, R, ()>>::sample_distinct_chords(sampler, n),
+            AnySampler::Possible(sampler) => <PossibleChordSampler<K, N, L, R> as ChordSampler<K, N, L, R, AnyPossibilityModel<K, N, L>>>::sample_distinct_chords(sampler, n),
+            AnySampler::Uncertain(sampler) => <MostUncertainPossibilityChordSampler<K, N, L, R> as ChordSampler<K, N, L, R, AnyPossibilityModel<K, N, L>>>::sample_distinct_chords(sampler, n),
+        }
+    }
+}
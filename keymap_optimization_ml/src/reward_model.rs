@@ -2,21 +2,45 @@ use tch::nn::{Module, Sequential};
 use tch::{nn, Tensor};
 use itertools::multiunzip;
 use tuple::Map;
+use serde::{Deserialize, Serialize};
 
 // we learn a pair of embeddings: one for accuracy, one for time--such that a function of the embeddings
 // of two chords represents the predicted time and accuracy for alternation between them
 
 // the input is a binary vector representing the keys pressed in the chord; so, its dimension is the number of keys
-// these are the dimensions of the embeddings
-const HIDDEN_DIM_SPEED: i64 = 8;
-const HIDDEN_DIM_ACCURACY: i64 = 8;
-const HIDDEN_DIM_POSSIBLE: i64 = 8;
-const HIDDEN_NUM_LAYERS: i64 = 1;
-const HIDDEN_DIM_SPEED_COMBINED: i64 = 4;
-const HIDDEN_DIM_ACCURACY_COMBINED: i64 = 4;
-const HIDDEN_SPEED_COMBINED_NUM_LAYERS: i64 = 0;
-const HIDDEN_ACCURACY_COMBINED_NUM_LAYERS: i64 = 0;
-const NUM_ENSEMBLE: usize = 10;
+// the architecture is described by ModelConfig (part of the TrainConfig), so that it can be changed without editing constants
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelConfig {
+    // the dimensions of the embeddings
+    pub hidden_dim_speed: i64,
+    pub hidden_dim_accuracy: i64,
+    pub hidden_dim_possible: i64,
+    pub hidden_num_layers: i64,
+    // the dimensions of the networks which combine the embeddings of two chords
+    pub hidden_dim_speed_combined: i64,
+    pub hidden_dim_accuracy_combined: i64,
+    pub hidden_speed_combined_num_layers: i64,
+    pub hidden_accuracy_combined_num_layers: i64,
+    // only used by Ensemble
+    pub num_ensemble: usize,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            hidden_dim_speed: 8,
+            hidden_dim_accuracy: 8,
+            hidden_dim_possible: 8,
+            hidden_num_layers: 1,
+            hidden_dim_speed_combined: 4,
+            hidden_dim_accuracy_combined: 4,
+            hidden_speed_combined_num_layers: 0,
+            hidden_accuracy_combined_num_layers: 0,
+            num_ensemble: 10,
+        }
+    }
+}
 
 fn seq_in_mid_out(vs: &nn::Path, in_dim: i64, mid_dim: i64, out_dim: i64, n_mid_layers: i64) -> Sequential {
     // create a sequential neural network with dimensions:
@@ -35,19 +59,22 @@ fn seq_in_mid_out(vs: &nn::Path, in_dim: i64, mid_dim: i64, out_dim: i64, n_mid_
     net.add(nn::linear(vs, mid_dim, out_dim, Default::default()))
 }
 
-fn embed<const N: usize>(vs: &nn::Path, hidden_dim: i64) -> Sequential {
-    seq_in_mid_out(vs, N as i64, hidden_dim, hidden_dim, HIDDEN_NUM_LAYERS - 1)
+fn embed<const N: usize>(vs: &nn::Path, hidden_dim: i64, config: &ModelConfig) -> Sequential {
+    seq_in_mid_out(vs, N as i64, hidden_dim, hidden_dim, config.hidden_num_layers - 1)
 }
 
+// the third embedding of a chord is the logit of the probability that the chord is possible (not the probability itself),
+// so that the loss can use binary_cross_entropy_with_logits and probabilities near 0 and 1 don't lose precision.
+// possible_probabilities converts it to a probability
 pub trait RewardEmbedding: std::fmt::Debug + std::marker::Send + Sized {
-    fn new(vs: &nn::Path) -> Self;
+    fn new(vs: &nn::Path, config: &ModelConfig) -> Self;
 
     fn tt_to_flat(tt: (Tensor, Tensor, Tensor)) -> Tensor {
         Tensor::cat(&[&tt.0, &tt.1, &tt.2], 1)
     }
 
-    fn flat_to_tt(flat: Tensor) -> (Tensor, Tensor, Tensor) {
-        let split = flat.split_with_sizes(&[HIDDEN_DIM_SPEED, HIDDEN_DIM_ACCURACY, 1], 1);
+    fn flat_to_tt(flat: Tensor, config: &ModelConfig) -> (Tensor, Tensor, Tensor) {
+        let split = flat.split_with_sizes(&[config.hidden_dim_speed, config.hidden_dim_accuracy, 1], 1);
         (split[0].shallow_clone(), split[1].shallow_clone(), split[2].shallow_clone())
     }
 
@@ -58,6 +85,28 @@ pub trait RewardEmbedding: std::fmt::Debug + std::marker::Send + Sized {
     }
 }
 
+// anything which can estimate the probability that each of a batch of chords is possible.
+// this is the only part of a model that the samplers need, so it is also implemented by the heuristic model
+pub trait PossibilityModel {
+    fn possible_probabilities(&self, chords: &Tensor) -> Tensor;
+}
+
+impl<E: RewardEmbedding> PossibilityModel for E {
+    fn possible_probabilities(&self, chords: &Tensor) -> Tensor {
+        let (_, _, is_possible_logit) = self.embed_chords(chords);
+        is_possible_logit.sigmoid()
+    }
+}
+
+pub fn pair_possible_logit(logit_1: &Tensor, logit_2: &Tensor) -> Tensor {
+    // the combination of two chords is possible exactly when both chords are (independently) possible; so, its probability
+    // is the product of theirs. this is computed in log space: log p = log sigmoid(logit_1) + log sigmoid(logit_2), and then
+    // logit = log p - log(1 - p), where log(1 - p) = log(-expm1(log p)) is accurate even when p is close to 1.
+    // log p is kept slightly below 0 so that the logit stays finite when both chords are almost certainly possible
+    let log_p = (logit_1.log_sigmoid() + logit_2.log_sigmoid()).clamp_max(-1e-6);
+    &log_p - (-log_p.expm1()).log()
+}
+
 #[derive(Debug)]
 pub struct RewardEmbeddingBase<const N: usize> {
     speed: Sequential,
@@ -66,11 +115,11 @@ pub struct RewardEmbeddingBase<const N: usize> {
 }
 
 impl<const N: usize> RewardEmbedding for RewardEmbeddingBase<N> {
-    fn new(vs: &nn::Path) -> Self {
+    fn new(vs: &nn::Path, config: &ModelConfig) -> Self {
         Self {
-            speed: embed::<N>(&vs.sub("speed"), HIDDEN_DIM_SPEED),
-            accuracy: embed::<N>(&vs.sub("accuracy"), HIDDEN_DIM_ACCURACY),
-            is_possible: embed::<N>(&vs.sub("is_possible"), HIDDEN_DIM_POSSIBLE).add(nn::linear(vs, HIDDEN_DIM_POSSIBLE, 1, Default::default())).add_fn(|xs| xs.sigmoid()),
+            speed: embed::<N>(&vs.sub("speed"), config.hidden_dim_speed, config),
+            accuracy: embed::<N>(&vs.sub("accuracy"), config.hidden_dim_accuracy, config),
+            is_possible: embed::<N>(&vs.sub("is_possible"), config.hidden_dim_possible, config).add(nn::linear(vs, config.hidden_dim_possible, 1, Default::default())),  // a logit; see RewardEmbedding
         }
     }
 
@@ -93,8 +142,13 @@ pub struct RewardModel<const N: usize, E: RewardEmbedding> {
 pub struct Dataset {
     pub train_input: Tensor,
     pub train_target: Tensor,
+    pub train_weight: Tensor,  // how much each example counts in the loss (e.g. less for outliers); usually all 1
+    // the last n_naturalistic training examples come from practice rather than pair trials. they're only ever trained on:
+    // validation sets and held out folds are drawn from the trials
+    pub n_naturalistic: usize,
     pub test_input: Tensor,
     pub test_target: Tensor,
+    pub test_weight: Tensor,
 }
 
 impl<const N: usize, E: RewardEmbedding> Module for RewardModel<N, E> {
@@ -104,32 +158,39 @@ impl<const N: usize, E: RewardEmbedding> Module for RewardModel<N, E> {
         let (chord_1, chord_2) = (&chords[0], &chords[1]);
 
         let ((emb_1_s, emb_1_a, ip_1), (emb_2_s, emb_2_a, ip_2)) = (self.chord_embedding.embed_chords(&chord_1), self.chord_embedding.embed_chords(&chord_2));
-        let speed = self.speed_combiner.forward(&Tensor::cat(&[&emb_1_s, &emb_2_s], 1)).squeeze();
-        let accuracy = self.accuracy_combiner.forward(&Tensor::cat(&[&emb_1_a, &emb_2_a], 1)).squeeze();
+        let speed = self.speed_combiner.forward(&Tensor::cat(&[&emb_1_s, &emb_2_s], 1)).squeeze_dim(-1);
+        let accuracy = self.accuracy_combiner.forward(&Tensor::cat(&[&emb_1_a, &emb_2_a], 1)).squeeze_dim(-1);
 
-        // whether the combination is possible is entirely dependent on whether its constituent chords are possible
-        let dim_sum = [-1i64];  // the first dimension is the batch size; so, to take the product of all the probabilities individually, we use sum_dim_intlist
-        let is_possible = (ip_1 * ip_2).sum_dim_intlist(&dim_sum[..], false, tch::Kind::Float);
+        // whether the combination is possible is entirely dependent on whether its constituent chords are possible.
+        // like the embeddings, the output is a logit; ip_1 and ip_2 have shape [batch size, 1]
+        let is_possible = pair_possible_logit(&ip_1, &ip_2).squeeze_dim(-1);
 
         Tensor::stack(&[speed, accuracy, is_possible], 1)
     }
 }
 
 impl<const N: usize, E: RewardEmbedding> RewardModel<N, E> {
-    pub fn new(vs: &nn::Path) -> Self {
+    pub fn new(vs: &nn::Path, config: &ModelConfig) -> Self {
         Self {
-            chord_embedding: E::new(&vs.sub("chord_embedding")),
-            speed_combiner: seq_in_mid_out(&vs.sub("speed_combiner"), 2*HIDDEN_DIM_SPEED, HIDDEN_DIM_SPEED_COMBINED, 1, HIDDEN_SPEED_COMBINED_NUM_LAYERS).add_fn(|xs| xs.exp()),  // scale to 0, infinity with exp
-            accuracy_combiner: seq_in_mid_out(&vs.sub("accuracy_combiner"), 2*HIDDEN_DIM_ACCURACY, HIDDEN_DIM_ACCURACY_COMBINED, 1, HIDDEN_ACCURACY_COMBINED_NUM_LAYERS).add_fn(|xs| xs.sigmoid()),  // scale to 0, 1 with sigmoid
+            chord_embedding: E::new(&vs.sub("chord_embedding"), config),
+            speed_combiner: seq_in_mid_out(&vs.sub("speed_combiner"), 2*config.hidden_dim_speed, config.hidden_dim_speed_combined, 1, config.hidden_speed_combined_num_layers).add_fn(|xs| xs.exp()),  // scale to 0, infinity with exp
+            accuracy_combiner: seq_in_mid_out(&vs.sub("accuracy_combiner"), 2*config.hidden_dim_accuracy, config.hidden_dim_accuracy_combined, 1, config.hidden_accuracy_combined_num_layers).add_fn(|xs| xs.sigmoid()),  // scale to 0, 1 with sigmoid
         }
     }
 }
 
-pub fn loss<const N: usize, E: RewardEmbedding>(model: &RewardModel<N, E>, input: &Tensor, target: &Tensor) -> Tensor {
-    // the output is part numerical (speed, accuracy) and part categorical (is_possible).
-    // so, the loss is the mean squared error of the numerical part + (a multiple of) the binary cross entropy of the categorical part
-    const XE_WEIGHT: f64 = 100.0;
-    let output = &model.forward(input);
+// the default weight of the cross entropy part of the loss (see output_loss)
+pub const XE_WEIGHT: f64 = 100.0;
+
+pub fn loss<const N: usize, E: RewardEmbedding>(model: &RewardModel<N, E>, input: &Tensor, target: &Tensor, weight: &Tensor, xe_weight: f64) -> Tensor {
+    output_loss(&model.forward(input), target, weight, xe_weight)
+}
+
+pub fn output_loss(output: &Tensor, target: &Tensor, weight: &Tensor, xe_weight: f64) -> Tensor {
+    // the output is part numerical (speed, accuracy) and part categorical (the logit of is_possible).
+    // so, the loss is the mean squared error of the numerical part + xe_weight * the binary cross entropy of the categorical part
+    // this is separate from loss so that models other than RewardModel (e.g. the heuristic model) can be scored the same way.
+    // the loss of each example is weighted by weight (of shape [batch size]), and the result is the weighted mean
 
     fn split_numeric_categorical(tn: &Tensor) -> (Tensor, Tensor) {
         match tn.split_with_sizes(&[2, 1], 1).as_slice() {
@@ -140,9 +201,9 @@ pub fn loss<const N: usize, E: RewardEmbedding>(model: &RewardModel<N, E>, input
     let (numeric_out, categorical_out) = split_numeric_categorical(output);
     let (numeric_target, categorical_target) = split_numeric_categorical(target);
 
-    let mse_part = numeric_out.mse_loss(&numeric_target, tch::Reduction::Mean);
-    let bce_part = categorical_out.binary_cross_entropy_with_logits::<Tensor>(&categorical_target, None, None, tch::Reduction::Mean);
-    mse_part + XE_WEIGHT * bce_part
+    let mse_part = numeric_out.mse_loss(&numeric_target, tch::Reduction::None).mean_dim(1, false, tch::Kind::Float);
+    let bce_part = categorical_out.binary_cross_entropy_with_logits::<Tensor>(&categorical_target, None, None, tch::Reduction::None).squeeze_dim(1);
+    ((mse_part + xe_weight * bce_part) * weight).sum(tch::Kind::Float) / weight.sum(tch::Kind::Float)
 }
 
 #[derive(Debug)]
@@ -156,12 +217,27 @@ impl<M: Module> Ensemble<M> {
     }
 }
 
+// models made of parts which each estimate whether chords are possible, so that how much the parts disagree measures how
+// uncertain the model is
+pub trait PossibilitySpread {
+    // the standard deviation of the parts' probabilities that each chord is possible, with shape [batch size]
+    fn possible_probability_spread(&self, chords: &Tensor) -> Tensor;
+}
+
+impl<const N: usize, E: RewardEmbedding> PossibilitySpread for Ensemble<RewardModel<N, E>> {
+    fn possible_probability_spread(&self, chords: &Tensor) -> Tensor {
+        let probabilities = self.models.iter().map(|m| m.chord_embedding.possible_probabilities(chords).reshape([-1])).collect::<Vec<Tensor>>();
+        Tensor::stack(&probabilities, 0).std_dim(0, false, false)
+    }
+}
+
 impl<const N: usize, E: RewardEmbedding> RewardEmbedding for Ensemble<RewardModel<N, E>> {
-    fn new(vs: &nn::Path) -> Self {
-        Self { models: (0..NUM_ENSEMBLE).map(|_| Box::new(RewardModel::<N, E>::new(vs))).collect() }
+    fn new(vs: &nn::Path, config: &ModelConfig) -> Self {
+        Self { models: (0..config.num_ensemble).map(|_| Box::new(RewardModel::<N, E>::new(vs, config))).collect() }
     }
 
     fn embed_chords(&self, chords: &Tensor) -> (Tensor, Tensor, Tensor) {
+        // the possibility logits are averaged like the other embeddings, which is the geometric mean of the odds
         let embeddings = self.models.iter().map(|m| m.chord_embedding.embed_chords(chords)).collect::<Vec<(Tensor, Tensor, Tensor)>>();
         multiunzip::<(Vec<Tensor>, Vec<Tensor>, Vec<Tensor>), Vec<(Tensor, Tensor, Tensor)>>(embeddings).map(|ts| Tensor::stack(&ts, 0).mean_dim(0, false, tch::Kind::Float))
     }
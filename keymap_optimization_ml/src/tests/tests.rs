@@ -3,14 +3,32 @@ use strum::EnumCount;
 use rand::rngs::ThreadRng;
 use keymap_optimization::keyboard_config::ChordSampler;
 use keymap_optimization::twiddler::{TwiddlerKey as K, TwiddlerLayout as L, TwiddlerChord};
-use crate::chord_samplers::{get_possible_probabilities, MostUncertainPossibilityChordSampler, PossibleChordSampler};
-use crate::train::train;
-use crate::reward_model::{Ensemble, RewardEmbedding, RewardEmbeddingBase, RewardModel};
+use crate::chord_samplers::{all_chords, get_possible_probabilities, MostUncertainPossibilityChordSampler, PossibleChordSampler};
+use crate::train::{chord_to_tensor, folds, get_formatted_data, predict_pairs, train, train_on_dataset, validation_split, EarlyStopping, LrSchedule, TrainConfig};
+use crate::hyperparameter_search::{SearchSpace, SearchStrategy};
+use crate::reward_model::{pair_possible_logit, Dataset, Ensemble, PossibilityModel, RewardEmbedding, RewardEmbeddingBase, RewardModel};
+use crate::heuristic_model::HeuristicModel;
+use crate::catalog::{common_chords, CatalogOrder, ChordCatalog};
+use crate::cli::{Cli, Command};
+use crate::registry::{AnySampler, ModelKind, SamplerKind, SamplerSpec};
+use clap::Parser;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use tch::Tensor;
+use tch::nn::Module;
+use keymap_optimization::twiddler::TwiddlerExponentialSampler;
+use keymap_optimization::data_store::DataStore;
+use keymap_optimization::chord_preferences::gather_chords::as_typed;
+use keymap_optimization::chord_preferences::practice::{PracticeLine, PracticeLog};
 
 const TEST_RESULTS_PATH: &str = "./src/tests/test_data";
 
-fn train_and_sample<E: RewardEmbedding>(quality_ratio: f64, n_epochs: usize, data_path: &str) {
-    let model = match train::<K, { K::COUNT }, L, E>(data_path, n_epochs) {
+fn test_store() -> DataStore {
+    DataStore::new(TEST_RESULTS_PATH)
+}
+
+fn train_and_sample<E: RewardEmbedding>(quality_ratio: f64, n_epochs: usize, store: &DataStore) {
+    let model = match train::<K, { K::COUNT }, L, E>(store, &TrainConfig { n_epochs, ..Default::default() }, rand::random()) {
         Ok(model) => model,
         Err(e) => return assert!(false, "Error training model: {}", e)
     };
@@ -51,12 +69,12 @@ fn train_and_sample<E: RewardEmbedding>(quality_ratio: f64, n_epochs: usize, dat
 
 #[test]
 fn train_and_sample_single() {
-    train_and_sample::<RewardEmbeddingBase<{ K::COUNT }>>(1.2, 1001, TEST_RESULTS_PATH);
+    train_and_sample::<RewardEmbeddingBase<{ K::COUNT }>>(1.2, 1001, &test_store());
 }
 
 #[test]
 fn train_and_sample_ensemble() {
-    train_and_sample::<Ensemble<RewardModel<{ K::COUNT }, RewardEmbeddingBase<{ K::COUNT }>>>>(2.0, 501, TEST_RESULTS_PATH);
+    train_and_sample::<Ensemble<RewardModel<{ K::COUNT }, RewardEmbeddingBase<{ K::COUNT }>>>>(2.0, 501, &test_store());
 }
 
 fn test_sampler<I, S: ChordSampler<K, { K::COUNT }, L, ThreadRng, I>>(initialization_info: &I) {
@@ -70,6 +88,17 @@ fn test_sampler<I, S: ChordSampler<K, { K::COUNT }, L, ThreadRng, I>>(initializa
         let chord = <S as ChordSampler<K, { K::COUNT }, L, ThreadRng, I>>::sample_chord(&mut sampler);
         println!("{}", chord);
     }
+
+    // sampling many distinct chords should finish quickly and give distinct chords
+    const N_DISTINCT: usize = 1000;
+    let chords = match <S as ChordSampler<K, { K::COUNT }, L, ThreadRng, I>>::sample_distinct_chords(&mut sampler, N_DISTINCT) {
+        Ok(chords) => chords,
+        Err(e) => return assert!(false, "Error sampling distinct chords: {}", e)
+    };
+    assert_eq!(chords.len(), N_DISTINCT);
+    for (i, chord) in chords.iter().enumerate() {
+        assert!(!chords[..i].contains(chord), "chord {} was sampled twice", chord);
+    }
 }
 
 #[test]
@@ -81,7 +110,7 @@ fn test_exponential_sampler() {
 fn test_slow_samplers() {
     type E = RewardEmbeddingBase<{ K::COUNT }>;
     // since we're just checking that nothing panics, we can train the model for a very short time since its performance doesn't matter
-    let embedder = match train::<K, { K::COUNT }, L, E>(TEST_RESULTS_PATH, 101) {
+    let embedder = match train::<K, { K::COUNT }, L, E>(&test_store(), &TrainConfig { n_epochs: 101, ..Default::default() }, rand::random()) {
         Ok(model) => Box::new(model.chord_embedding),
         Err(e) => return assert!(false, "Error training model: {}", e)
     };
@@ -89,3 +118,331 @@ fn test_slow_samplers() {
     test_sampler::<E, MostUncertainPossibilityChordSampler<K, { K::COUNT }, L, ThreadRng>>(&embedder);
     test_sampler::<E, PossibleChordSampler<K, { K::COUNT }, L, ThreadRng>>(&embedder);
 }
+
+#[test]
+fn test_heuristic_sampler() {
+    test_sampler::<HeuristicModel<K, { K::COUNT }, L>, MostUncertainPossibilityChordSampler<K, { K::COUNT }, L, ThreadRng>>(&HeuristicModel::new());
+    test_sampler::<HeuristicModel<K, { K::COUNT }, L>, PossibleChordSampler<K, { K::COUNT }, L, ThreadRng>>(&HeuristicModel::new());
+}
+
+#[test]
+fn heuristic_prefers_fewer_keys() {
+    // adding a key to a chord should never make it easier, and a chord with one key per finger should be easier than
+    // one with several keys on the same finger
+    let mut single = TwiddlerChord::new();
+    single.add_key(K::L1);
+    let mut same_finger = single.clone();
+    same_finger.add_key(K::M1);
+    let mut different_fingers = single.clone();
+    different_fingers.add_key(K::L2);
+
+    type H = HeuristicModel<K, { K::COUNT }, L>;
+    assert!(H::chord_difficulty(&single) < H::chord_difficulty(&different_fingers));
+    assert!(H::chord_difficulty(&different_fingers) < H::chord_difficulty(&same_finger));
+    assert!(H::possible_probability(&single) > H::possible_probability(&same_finger));
+
+    let [slow_time, _, _] = H::predict_pair(&[single.clone(), same_finger.clone()]);
+    let [fast_time, _, _] = H::predict_pair(&[single.clone(), single.clone()]);
+    assert!(fast_time < slow_time);
+}
+
+#[test]
+fn cli_parsing() {
+    let cli = Cli::try_parse_from(["keymap", "--seed", "3", "gen-config", "--sampler", "uncertain", "--model", "ensemble", "--epochs", "11", "--package-out", "session"]).unwrap();
+    assert_eq!(cli.seed, Some(3));
+    match cli.command {
+        Command::GenConfig(args) => {
+            assert_eq!(args.sampler.sampler, SamplerKind::Uncertain);
+            assert_eq!(args.sampler.model, ModelKind::Ensemble);
+            assert_eq!(args.sampler.training.epochs, Some(11));
+            assert_eq!(args.sampler.training.config().unwrap().n_epochs, 11);
+            assert_eq!(args.package_out.as_deref(), Some("session"));
+            assert_eq!(args.code_config, None);
+        },
+        _ => assert!(false, "wrong subcommand"),
+    }
+
+    // global options can come after the subcommand
+    let cli = Cli::try_parse_from(["keymap", "gather", "decoder.json", "--data-dir", "/tmp/data"]).unwrap();
+    assert_eq!(cli.data_dir.as_deref(), Some("/tmp/data"));
+    assert_eq!(cli.seed, None);
+    match cli.command {
+        Command::Gather(args) => assert_eq!(args.decoder, "decoder.json"),
+        _ => assert!(false, "wrong subcommand"),
+    }
+
+    // a saved model can be used instead of training one
+    match Cli::try_parse_from(["keymap", "catalog", "--model", "ensemble", "--checkpoint", "model_1"]).unwrap().command {
+        Command::Catalog(args) => assert_eq!(args.checkpoint.as_deref(), Some("model_1")),
+        _ => assert!(false, "wrong subcommand"),
+    }
+
+    // the decoder is required for gathering, and the choices are checked
+    assert!(Cli::try_parse_from(["keymap", "gather"]).is_err());
+    assert!(Cli::try_parse_from(["keymap", "train", "--model", "nonexistent"]).is_err());
+}
+
+#[test]
+fn registry_samplers() {
+    // every registered name parses back to its variant
+    for name in SamplerKind::names() {
+        assert_eq!(name.parse::<SamplerKind>().unwrap().to_string(), name);
+    }
+    for name in ModelKind::names() {
+        assert_eq!(name.parse::<ModelKind>().unwrap().to_string(), name);
+    }
+
+    // the exponential sampler doesn't need a model, so no training happens here
+    let spec = SamplerSpec::<K, { K::COUNT }, L>::new(SamplerKind::Exponential, ModelKind::Single, &test_store(), &TrainConfig::default(), rand::random()).unwrap();
+    assert!(spec.model.is_none());
+    test_sampler::<_, AnySampler<K, { K::COUNT }, L, ThreadRng>>(&spec);
+
+    // the other samplers do
+    let spec = SamplerSpec::<K, { K::COUNT }, L> { kind: SamplerKind::Possible, model: None };
+    assert!(<AnySampler<K, { K::COUNT }, L, ThreadRng> as ChordSampler<K, { K::COUNT }, L, ThreadRng, _>>::new(rand::thread_rng(), &spec).is_err());
+
+    let spec = SamplerSpec::<K, { K::COUNT }, L>::new(SamplerKind::Uncertain, ModelKind::Heuristic, &test_store(), &TrainConfig::default(), rand::random()).unwrap();
+    test_sampler::<_, AnySampler<K, { K::COUNT }, L, ThreadRng>>(&spec);
+}
+
+#[test]
+fn train_config_json() {
+    // a partial config takes the defaults for everything else
+    let config: TrainConfig = serde_json::from_str(r#"{"n_epochs": 50, "model": {"num_ensemble": 3}, "lr_schedule": {"type": "step", "step_size": 10, "gamma": 0.5}}"#).unwrap();
+    assert_eq!(config.n_epochs, 50);
+    assert_eq!(config.model.num_ensemble, 3);
+    assert_eq!(config.model.hidden_dim_speed, TrainConfig::default().model.hidden_dim_speed);
+    assert_eq!(config.learning_rate, TrainConfig::default().learning_rate);
+
+    // and configs survive being saved
+    let config = TrainConfig { batch_size: Some(32), early_stopping: Some(EarlyStopping::default()), ..config };
+    let roundtrip: TrainConfig = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+    assert_eq!(roundtrip, config);
+}
+
+#[test]
+fn lr_schedules() {
+    assert_eq!(LrSchedule::Constant.learning_rate(1e-3, 500, 1000), 1e-3);
+
+    let step = LrSchedule::Step { step_size: 10, gamma: 0.5 };
+    assert_eq!(step.learning_rate(1.0, 9, 100), 1.0);
+    assert_eq!(step.learning_rate(1.0, 10, 100), 0.5);
+    assert_eq!(step.learning_rate(1.0, 25, 100), 0.25);
+
+    let cosine = LrSchedule::Cosine { min_learning_rate: 0.1 };
+    assert!((cosine.learning_rate(1.0, 0, 101) - 1.0).abs() < 1e-9);
+    assert!((cosine.learning_rate(1.0, 50, 101) - 0.55).abs() < 1e-9);
+    assert!((cosine.learning_rate(1.0, 100, 101) - 0.1).abs() < 1e-9);
+}
+
+#[test]
+fn search_candidates() {
+    let base = TrainConfig::default();
+    // an empty search space except for two hyperparameters
+    let space: SearchSpace = serde_json::from_str(r#"{"hidden_dim": [4, 16], "learning_rate": [1e-2, 1e-3, 1e-4], "hidden_num_layers": [],
+                                                       "hidden_dim_combined": [], "hidden_combined_num_layers": [], "weight_decay": []}"#).unwrap();
+    assert_eq!(space.size(), 6);
+
+    let grid = space.candidates(&base, SearchStrategy::Grid, 0);
+    assert_eq!(grid.len(), 6);
+    for (i, config) in grid.iter().enumerate() {
+        assert!(!grid[..i].contains(config), "the grid has duplicate candidates");
+        assert!([4, 16].contains(&config.model.hidden_dim_speed));
+        assert_eq!(config.model.hidden_dim_speed, config.model.hidden_dim_possible);
+        // the hyperparameters which aren't searched keep their base values
+        assert_eq!(config.model.hidden_num_layers, base.model.hidden_num_layers);
+        assert_eq!(config.weight_decay, base.weight_decay);
+    }
+
+    // random candidates are distinct points of the grid, and depend only on the seed
+    let seed = rand::random();
+    let random = space.candidates(&base, SearchStrategy::Random { n_candidates: 4 }, seed);
+    assert_eq!(random.len(), 4);
+    assert!(random.iter().all(|config| grid.contains(config)));
+    assert_eq!(random, space.candidates(&base, SearchStrategy::Random { n_candidates: 4 }, seed));
+    assert_eq!(space.candidates(&base, SearchStrategy::Random { n_candidates: 100 }, seed).len(), 6);
+}
+
+fn synthetic_dataset(impossible_key: K, n_pairs: usize, seed: u64) -> Dataset {
+    // pairs of random chords, where a pair is impossible exactly when either chord contains impossible_key.
+    // the time grows with the number of keys and the accuracy is constant, so that only the possibility is hard to learn
+    let mut sampler = <TwiddlerExponentialSampler<StdRng> as ChordSampler<K, { K::COUNT }, L, StdRng, ()>>::new(StdRng::seed_from_u64(seed), &()).unwrap();
+    let (input, target): (Vec<Tensor>, Vec<Tensor>) = (0..n_pairs).map(|_| {
+        let pair: [TwiddlerChord; 2] = [sampler.sample_chord(), sampler.sample_chord()];
+        let possible = !pair.iter().any(|chord| chord.contains(impossible_key));
+        let target = if possible { [1.0 + 0.5 * (pair[0].n_keys() + pair[1].n_keys()) as f32, 0.9, 1.0] } else { [0.0, 0.0, 0.0] };
+        (Tensor::concat(&pair.map(|c| chord_to_tensor(&c)), 0), Tensor::from_slice(&target))
+    }).unzip();
+    let n_test = n_pairs / 10;
+    Dataset {
+        train_input: Tensor::stack(&input[n_test..], 0),
+        train_target: Tensor::stack(&target[n_test..], 0),
+        train_weight: Tensor::ones([(n_pairs - n_test) as i64], (tch::Kind::Float, tch::Device::Cpu)),
+        n_naturalistic: 0,
+        test_input: Tensor::stack(&input[..n_test], 0),
+        test_target: Tensor::stack(&target[..n_test], 0),
+        test_weight: Tensor::ones([n_test as i64], (tch::Kind::Float, tch::Device::Cpu)),
+    }
+}
+
+#[test]
+fn learns_impossible_key() {
+    const IMPOSSIBLE_KEY: K = K::L1;
+    let seed = rand::random();
+    println!("seed: {}", seed);
+    let data = synthetic_dataset(IMPOSSIBLE_KEY, 2000, seed);
+    let config = TrainConfig { n_epochs: 1001, learning_rate: 1e-2, ..Default::default() };
+    let model = train_on_dataset::<{ K::COUNT }, RewardEmbeddingBase<{ K::COUNT }>>(&data, &config, seed).unwrap().model;
+
+    // single key chords should be possible, except for the impossible key (and any chord containing it)
+    for key in [K::M1, K::R2, K::L3, K::L1] {
+        let mut chord = TwiddlerChord::new();
+        chord.add_key(key);
+        let probability = model.chord_embedding.possible_probabilities(&chord_to_tensor(&chord).unsqueeze(0)).double_value(&[0, 0]);
+        println!("{}: {}", chord, probability);
+        if key == IMPOSSIBLE_KEY {
+            assert!(probability < 0.2, "chord {} containing the impossible key is predicted possible with probability {}", chord, probability);
+        } else {
+            assert!(probability > 0.8, "chord {} is predicted possible with probability only {}", chord, probability);
+        }
+    }
+
+    // and the predictions for pairs should agree with the data
+    let output = model.forward(&data.test_input);
+    let predicted_possible = output.select(1, 2).sigmoid().gt(0.5);
+    let actually_possible = data.test_target.select(1, 2).gt(0.5);
+    let accuracy = predicted_possible.eq_tensor(&actually_possible).to_kind(tch::Kind::Float).mean(tch::Kind::Float).double_value(&[]);
+    assert!(accuracy > 0.95, "only {} of test pairs were correctly predicted possible or impossible", accuracy);
+}
+
+#[test]
+fn trains_with_batches_and_early_stopping() {
+    // a batch size which leaves a last batch of a single example, and a validation set of a single example
+    let seed = rand::random();
+    println!("seed: {}", seed);
+    let data = synthetic_dataset(K::L1, 22, seed);
+    let early_stopping = EarlyStopping { validation_frac: 0.05, patience: 5, min_delta: 0.0 };
+    let config = TrainConfig { n_epochs: 50, batch_size: Some(2), early_stopping: Some(early_stopping), ..Default::default() };
+    let trained = train_on_dataset::<{ K::COUNT }, RewardEmbeddingBase<{ K::COUNT }>>(&data, &config, seed).unwrap();
+    assert!(trained.epochs_trained >= 1 && trained.epochs_trained <= config.n_epochs);
+    assert!(trained.best_epoch < trained.epochs_trained);
+
+    // and the model can be asked about a single pair
+    let (mut first, mut second) = (TwiddlerChord::new(), TwiddlerChord::new());
+    first.add_key(K::M1);
+    second.add_key(K::R2);
+    let predictions = predict_pairs(&*trained.model, &[(&first, &second)]);
+    assert_eq!(predictions.len(), 1);
+    assert!(predictions[0].iter().all(|p| p.is_finite()));
+}
+
+#[test]
+fn pair_possibility_is_product() {
+    // the pair logit is the logit of the product of the chords' probabilities, including when they are extreme
+    let logits = [-30.0f32, -5.0, -0.5, 0.0, 0.5, 5.0, 30.0];
+    let (logit_1, logit_2): (Vec<f32>, Vec<f32>) = logits.iter().flat_map(|l1| logits.iter().map(move |l2| (*l1, *l2))).unzip();
+    let (logit_1, logit_2) = (Tensor::from_slice(&logit_1).unsqueeze(1), Tensor::from_slice(&logit_2).unsqueeze(1));
+    let pair_probability = pair_possible_logit(&logit_1, &logit_2).sigmoid();
+    let product = logit_1.sigmoid() * logit_2.sigmoid();
+    let max_error = (pair_probability - product).abs().max().double_value(&[]);
+    assert!(max_error < 1e-4, "the pair probability differs from the product by {}", max_error);
+
+    // the heuristic model follows the same contract
+    type H = HeuristicModel<K, { K::COUNT }, L>;
+    let mut sampler = <TwiddlerExponentialSampler<ThreadRng> as ChordSampler<K, { K::COUNT }, L, ThreadRng, ()>>::new(rand::thread_rng(), &()).unwrap();
+    for _ in 0..10 {
+        let pair = [sampler.sample_chord(), sampler.sample_chord()];
+        let output = H::new().forward(&Tensor::concat(&pair.clone().map(|c| chord_to_tensor(&c)), 0).unsqueeze(0));
+        let [_, _, possible] = H::predict_pair(&pair);
+        assert!((output.select(1, 2).sigmoid().double_value(&[0]) - possible).abs() < 1e-4);
+    }
+}
+
+#[test]
+fn chord_catalog() {
+    type H = HeuristicModel<K, { K::COUNT }, L>;
+    let model = H::new();
+    let common = common_chords::<K, { K::COUNT }, L, H>(&model, 3);
+    assert_eq!(common.len(), 3);
+    let mut catalog = ChordCatalog::new(&model, &model, None, common).unwrap();
+    assert_eq!(catalog.entries.len(), all_chords::<K, { K::COUNT }, L>().len());
+    for entry in &catalog.entries {
+        assert!((entry.possible - H::possible_probability(&entry.chord)).abs() < 1e-4);
+        assert!((0.0..=1.0).contains(&entry.uncertainty) && entry.possible_spread.is_none());
+        assert!(entry.switching_time_to > 0.0 && entry.switching_time_from > 0.0);
+    }
+    catalog.sort(CatalogOrder::Possible);
+    assert!(catalog.entries.windows(2).all(|pair| pair[0].possible >= pair[1].possible));
+
+    let mut csv = Vec::new();
+    catalog.write_csv(&mut csv).unwrap();
+    assert_eq!(String::from_utf8(csv).unwrap().lines().count(), catalog.entries.len() + 1);
+    let mut report = Vec::new();
+    catalog.write_report(&mut report).unwrap();
+    assert!(String::from_utf8(report).unwrap().contains(&catalog.entries[0].chord.to_string()));
+
+    let cli = Cli::try_parse_from(["keymap", "catalog", "--model", "heuristic", "--sort-by", "uncertainty", "--n-common", "5"]).unwrap();
+    match cli.command {
+        Command::Catalog(args) => {
+            assert_eq!((args.model, args.sort_by, args.n_common), (ModelKind::Heuristic, CatalogOrder::Uncertainty, 5));
+            assert_eq!(args.csv_out, None);
+        },
+        _ => assert!(false, "wrong subcommand"),
+    }
+}
+
+#[test]
+fn practice_transitions_are_only_trained_on() {
+    // a store with the test results and a practice log, in a directory which is removed afterwards
+    struct TempStore(std::path::PathBuf);
+    impl Drop for TempStore {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+    let seed: u64 = rand::random();
+    let dir = TempStore(std::env::temp_dir().join(format!("practice_split_{}", seed)));
+    std::fs::create_dir_all(&dir.0).unwrap();
+    for entry in std::fs::read_dir(TEST_RESULTS_PATH).unwrap() {
+        let path = entry.unwrap().path();
+        std::fs::copy(&path, dir.0.join(path.file_name().unwrap())).unwrap();
+    }
+    let (mut a, mut b) = (TwiddlerChord::new(), TwiddlerChord::new());
+    a.add_key(K::M1);
+    b.add_key(K::R2);
+    let line = PracticeLine::new(vec![a.clone(), b.clone(), a.clone()], as_typed(&[a.clone(), b.clone(), a.clone()]), Some(vec![1.0, 1.5, 2.0]), 2.5);
+    let log = PracticeLog { lines: vec![line], seed: Some(seed), participant: None };
+    log.save(&dir.0.join("practice_log_1.json").to_string_lossy()).unwrap();
+    let store = DataStore::new(&dir.0);
+
+    // the practice transitions (a to b and b to a) are added to the training set, and the test set is the same without them
+    let without = get_formatted_data::<K, { K::COUNT }, L>(&store, seed, &TrainConfig { naturalistic_weight: 0.0, ..Default::default() }).unwrap();
+    let with = get_formatted_data::<K, { K::COUNT }, L>(&store, seed, &TrainConfig { naturalistic_weight: 0.5, ..Default::default() }).unwrap();
+    assert_eq!(with.train_input.size()[0], without.train_input.size()[0] + 2);
+    assert_eq!((with.n_naturalistic, without.n_naturalistic), (2, 0));
+    assert!(with.test_input.equal(&without.test_input) && with.test_target.equal(&without.test_target));
+}
+
+#[test]
+fn practice_transitions_are_never_held_out() {
+    // of 20 training examples the last 5 are from practice: they always go in train, both when splitting off a validation set
+    // and in every fold, and the trials are each held out exactly once
+    let seed: u64 = rand::random();
+    let (n, n_naturalistic) = (20, 5);
+    let naturalistic: Vec<i64> = (15..20).collect();
+    let (train, validation) = validation_split(n, n_naturalistic, 0.2, &mut StdRng::seed_from_u64(seed));
+    assert_eq!(validation.len(), 3);
+    assert!(validation.iter().all(|idx| *idx < 15));
+    assert!(naturalistic.iter().all(|idx| train.contains(idx)));
+    assert_eq!(train.len() + validation.len(), n);
+
+    let folds = folds(n, n_naturalistic, 4, seed);
+    assert_eq!(folds.len(), 4);
+    let mut held_out: Vec<i64> = folds.iter().flat_map(|(_, fold)| fold.clone()).collect();
+    held_out.sort();
+    assert_eq!(held_out, (0..15).collect::<Vec<i64>>());
+    for (rest, fold) in &folds {
+        assert!(rest.ends_with(&naturalistic));
+        assert_eq!(rest.len() + fold.len(), n);
+    }
+}
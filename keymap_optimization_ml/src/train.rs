@@ -1,114 +1,509 @@
-use tch::nn::OptimizerConfig;
+use tch::nn::{Module, OptimizerConfig};
 use tch::{nn, Tensor};
 use keymap_optimization::keyboard_config::{Chord, Layout, Key};
-use keymap_optimization::chord_preferences::TrialResults;
+use keymap_optimization::chord_preferences::{TrialResults, N_REPETITIONS_PER_TRIAL};
 use keymap_optimization::chord_preferences::gather_chords::{ErrCode, accuracy_from_chord_pair};
+use keymap_optimization::chord_preferences::data_cleaning::{find_outliers, OutlierConfig, OutlierReport};
+use keymap_optimization::chord_preferences::confusion::ConfusionTable;
+use keymap_optimization::chord_preferences::practice::{summarize_transitions, PracticeLog};
+use keymap_optimization::data_store::{Artifact, DataStore};
 use rand::prelude::SliceRandom;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
-use crate::reward_model::{loss, Dataset, RewardEmbedding, RewardModel};
+use crate::reward_model::{loss, output_loss, Dataset, ModelConfig, RewardEmbedding, RewardModel, XE_WEIGHT};
+use crate::heuristic_model::HeuristicModel;
 
 const TEST_FRAC: f64 = 0.1;
 
+// a chord pair with its target (time, accuracy, possible) and its weight in the loss
+type Example<K, const N: usize, L> = ([Chord<K, N, L>; 2], [f32; 3], f32);
+
+// the names of the files in a checkpoint directory
+const CHECKPOINT_WEIGHTS_FILE: &str = "model.ot";
+const CHECKPOINT_CONFIG_FILE: &str = "train_config.json";
+
+// everything which determines how a model is trained (along with the data and the seed).
+// it can be read from json, and is saved with each checkpoint so that the training run can be reproduced.
+// missing fields take their default values, which are the settings that were used before this was configurable, except that
+// the transitions from practice sessions are learned from too (set naturalistic_weight to 0 to leave them out)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainConfig {
+    pub model: ModelConfig,
+    pub n_epochs: usize,
+    pub learning_rate: f64,
+    pub weight_decay: f64,
+    pub xe_weight: f64,  // see output_loss
+    pub lr_schedule: LrSchedule,
+    pub batch_size: Option<usize>,  // None means full-batch steps
+    pub early_stopping: Option<EarlyStopping>,
+    pub log_every: usize,  // epochs between printing the losses
+    pub outliers: OutlierConfig,  // which trials count as outliers
+    pub outlier_handling: OutlierHandling,  // what to do with them
+    // the weight of the transitions from practice sessions, for a pair typed as many times as in a trial (see naturalistic_examples)
+    pub naturalistic_weight: f64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            model: ModelConfig::default(),
+            n_epochs: 2001,
+            learning_rate: 1e-3,
+            weight_decay: 0.0,
+            xe_weight: XE_WEIGHT,
+            lr_schedule: LrSchedule::Constant,
+            batch_size: None,
+            early_stopping: None,
+            log_every: 100,
+            outliers: OutlierConfig::default(),
+            outlier_handling: OutlierHandling::Keep,
+            naturalistic_weight: 0.5,
+        }
+    }
+}
+
+impl TrainConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(serde_json::from_reader(std::fs::File::open(path)?)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(serde_json::to_writer_pretty(std::fs::File::create(path)?, self)?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LrSchedule {
+    Constant,
+    // multiply the learning rate by gamma every step_size epochs
+    Step { step_size: usize, gamma: f64 },
+    // anneal from the initial learning rate to min_learning_rate along half a cosine over the whole run
+    Cosine { min_learning_rate: f64 },
+}
+
+impl LrSchedule {
+    pub fn learning_rate(&self, initial: f64, epoch: usize, n_epochs: usize) -> f64 {
+        match self {
+            LrSchedule::Constant => initial,
+            LrSchedule::Step { step_size, gamma } => initial * gamma.powi((epoch / (*step_size).max(1)) as i32),
+            LrSchedule::Cosine { min_learning_rate } => {
+                let progress = epoch as f64 / (n_epochs.saturating_sub(1)).max(1) as f64;
+                min_learning_rate + 0.5 * (initial - min_learning_rate) * (1.0 + (std::f64::consts::PI * progress).cos())
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OutlierHandling {
+    Keep,
+    Drop,
+    // count each outlier as only this fraction of a trial in the loss
+    DownWeight { weight: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EarlyStopping {
+    // the fraction of the training set held out to decide when to stop (the test set is never used for this)
+    pub validation_frac: f64,
+    // stop after this many epochs without the validation loss improving by at least min_delta
+    pub patience: usize,
+    pub min_delta: f64,
+}
+
+impl Default for EarlyStopping {
+    fn default() -> Self {
+        Self { validation_frac: 0.1, patience: 200, min_delta: 0.0 }
+    }
+}
+
+// what is saved in a checkpoint directory alongside the weights
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointInfo {
+    pub config: TrainConfig,
+    pub seed: u64,
+    pub epochs_trained: usize,
+    // the epoch the weights are from, which is earlier than the last one when training stopped early (not recorded in older
+    // checkpoints)
+    #[serde(default)]
+    pub best_epoch: Option<usize>,
+}
+
 pub fn chord_to_tensor<K: Key, const N: usize, L: Layout<K, N>>(chord: &Chord<K, N, L>) -> Tensor {
     Tensor::f_from_slice(&chord.to_vector().into_iter().map(|c| if c { 1.0 } else { 0.0 }).collect::<Vec<f32>>()).unwrap()
 }
 
-fn load_data<K: Key, const N: usize, L: Layout<K, N>>(results_path: &str) -> Result<TrialResults<K, N, L>, Box<dyn std::error::Error>> {
-    // load the data from all the files chord_preferences_results*.json in RESULTS_PATH
-    println!("loading data from {}", results_path);
-    let files: Vec<std::fs::DirEntry> = std::fs::read_dir(results_path)?
-        .filter(|f|
-            match f {
-                Ok(f) => {
-                    let filename = f.file_name();
-                    let filename = filename.to_string_lossy();
-                    filename.starts_with("chord_preferences_results") && filename.ends_with(".json")
-                }
-                Err(_) => false,
-            })
-        .collect::<Result<Vec<std::fs::DirEntry>, std::io::Error>>()?;
-    let mut all_results = TrialResults::new();
-    for file in files {
-        let results: TrialResults<K, N, L> = serde_json::from_reader(std::fs::File::open(file.path())?)?;
-        all_results.data.extend(results.data);
+pub fn predict_pairs<K: Key, const N: usize, L: Layout<K, N>, M: Module>(model: &M, pairs: &[(&Chord<K, N, L>, &Chord<K, N, L>)]) -> Vec<[f64; 3]> {
+    // the model's prediction for each (first chord, second chord) pair: [time, accuracy, logit of the probability that the
+    // pair is possible]
+    let input = Tensor::stack(&pairs.iter().map(|(first, second)| Tensor::concat(&[chord_to_tensor(*first), chord_to_tensor(*second)], 0)).collect::<Vec<Tensor>>(), 0);
+    let output = tch::no_grad(|| model.forward(&input));
+    // this unwrap is safe because the output is two-dimensional, with one row per pair
+    let predictions: Vec<f64> = output.reshape([-1]).iter::<f64>().unwrap().collect();
+    predictions.chunks(3).map(|prediction| [prediction[0], prediction[1], prediction[2]]).collect()
+}
+
+pub fn tensor_to_chords<K: Key, const N: usize, L: Layout<K, N>>(tensor: &Tensor) -> Vec<Chord<K, N, L>> {
+    // the inverse of chord_to_tensor: reads the tensor (of any shape) as a flat sequence of chords of N keys each
+    // this unwrap is safe because the flattened tensor is one-dimensional
+    let values: Vec<f64> = tensor.reshape([-1]).iter::<f64>().unwrap().collect();
+    values.chunks(N).map(|keys| {
+        let mut chord = Chord::new();
+        for (key, value) in K::VARIANTS.iter().zip(keys) {
+            if *value > 0.5 {
+                chord.add_key(*key);
+            }
+        }
+        chord
+    }).collect()
+}
+
+fn load_data<K: Key, const N: usize, L: Layout<K, N>>(store: &DataStore) -> Result<Vec<TrialResults<K, N, L>>, Box<dyn std::error::Error>> {
+    // load the data from all the results files chord_preferences_results*.json in the store, merging the results of each participant.
+    // files which don't record their participant are treated as coming from separate participants, named after the file
+    println!("loading data from {}", store.root().display());
+    let mut by_participant: Vec<TrialResults<K, N, L>> = Vec::new();
+    for path in store.list_matching(Artifact::Results, "chord_preferences_results", ".json")? {
+        let mut results: TrialResults<K, N, L> = serde_json::from_reader(std::fs::File::open(&path)?)?;
+        let participant = results.participant.get_or_insert_with(|| path.file_name().unwrap_or_default().to_string_lossy().to_string());
+        match by_participant.iter_mut().find(|r| r.participant.as_ref() == Some(participant)) {
+            Some(existing) => existing.data.extend(results.data),
+            None => by_participant.push(results),
+        }
     }
-    Ok(all_results)
+    Ok(by_participant)
 }
 
-fn get_formatted_data<K: Key, const N: usize, L: Layout<K, N>>(results_path: &str) -> Result<Dataset, Box<dyn std::error::Error>> {
-    let results: TrialResults<K, N, L> = load_data::<K, N, L>(results_path)?;
-    let paired: Vec<([Chord<K, N, L>; 2], [f32; 3])> = results.data.into_iter().map(|trial| {
-        match trial.performance {
-            Err(ErrCode::Impossible) => (trial.chord_pair, [0.0, 0.0, 0.0]),
-            Ok(perf) => {
-                let accuracy = accuracy_from_chord_pair(&perf.input, &trial.chord_pair) as f32;
-                (trial.chord_pair, [perf.time as f32, accuracy, 1.0])
-            },
+fn load_practice_logs<K: Key, const N: usize, L: Layout<K, N>>(store: &DataStore) -> Result<Vec<PracticeLog<K, N, L>>, Box<dyn std::error::Error>> {
+    // the files practice_log*.json in the store, which are written by practice sessions
+    let mut logs = Vec::new();
+    for path in store.list_matching(Artifact::Results, "practice_log", ".json")? {
+        logs.push(serde_json::from_reader(std::fs::File::open(&path)?)?);
+    }
+    Ok(logs)
+}
+
+pub(crate) fn naturalistic_examples<K: Key, const N: usize, L: Layout<K, N>>(logs: &[PracticeLog<K, N, L>], weight: f64) -> Vec<Example<K, N, L>> {
+    // an example for each ordered pair of chords typed one after the other in practice, in the same form as a trial: the mean
+    // switching time is scaled up to the time of a trial, and the accuracy is the fraction of transitions typed correctly.
+    // a single switch says less than a trial does, so a pair gets weight once it's been timed as many times as it's switched
+    // in a trial, and proportionally less before that. pairs which were never both typed have no time, so they're left out
+    let switches_per_trial = (2 * N_REPETITIONS_PER_TRIAL - 1) as f64;
+    summarize_transitions(logs).into_iter().filter(|summary| summary.n_timed > 0).map(|summary| {
+        let pair_weight = weight * (summary.n_timed as f64 / switches_per_trial).min(1.0);
+        let target = [(summary.mean_time * switches_per_trial) as f32, summary.accuracy as f32, 1.0];
+        (summary.chord_pair, target, pair_weight as f32)
+    }).collect()
+}
+
+pub fn outlier_reports<K: Key, const N: usize, L: Layout<K, N>>(store: &DataStore, config: &OutlierConfig) -> Result<Vec<OutlierReport>, Box<dyn std::error::Error>> {
+    // one report per participant
+    Ok(load_data::<K, N, L>(store)?.iter().map(|results| find_outliers(results, config)).collect())
+}
+
+pub fn confusion_table<K: Key, const N: usize, L: Layout<K, N>>(store: &DataStore) -> Result<ConfusionTable<K, N, L>, Box<dyn std::error::Error>> {
+    // the confusions of all the participants together
+    Ok(ConfusionTable::from_results(&load_data::<K, N, L>(store)?))
+}
+
+pub(crate) fn get_formatted_data<K: Key, const N: usize, L: Layout<K, N>>(store: &DataStore, seed: u64, config: &TrainConfig) -> Result<Dataset, Box<dyn std::error::Error>> {
+    let (outliers, outlier_handling) = (&config.outliers, &config.outlier_handling);
+    let mut paired: Vec<Example<K, N, L>> = Vec::new();
+    let (mut n_trials, mut n_flagged) = (0, 0);
+    for results in load_data::<K, N, L>(store)? {
+        // outliers are found separately for each participant, since people type at different speeds
+        let report = find_outliers(&results, outliers);
+        n_trials += report.n_trials;
+        n_flagged += report.flagged.len();
+        for (idx, trial) in results.data.into_iter().enumerate() {
+            let weight = match (report.is_flagged(idx), outlier_handling) {
+                (false, _) | (true, OutlierHandling::Keep) => 1.0,
+                (true, OutlierHandling::Drop) => continue,
+                (true, OutlierHandling::DownWeight { weight }) => *weight as f32,
+            };
+            let target = match trial.performance {
+                Err(ErrCode::Impossible) => [0.0, 0.0, 0.0],
+                Ok(perf) => {
+                    let accuracy = accuracy_from_chord_pair(&perf.input, &trial.chord_pair) as f32;
+                    [perf.time as f32, accuracy, 1.0]
+                },
+            };
+            paired.push((trial.chord_pair, target, weight));
         }
-    }).collect();
-    println!("loaded {} trials", paired.len());
+    }
+    println!("loaded {} trials, of which {} were flagged as outliers ({:?})", n_trials, n_flagged, outlier_handling);
+    // the transitions from practice are only trained on, so that the test set stays the controlled pair trials
+    let naturalistic = if config.naturalistic_weight > 0.0 {
+        let logs = load_practice_logs::<K, N, L>(store)?;
+        let examples = naturalistic_examples(&logs, config.naturalistic_weight);
+        println!("loaded {} practice sessions, with transitions between {} pairs of chords", logs.len(), examples.len());
+        examples
+    } else {
+        Vec::new()
+    };
 
-    let (input, target): (Vec<Tensor>, Vec<Tensor>) = paired.into_iter()
-                                                            .map(|(chord_pair, perf)| { Ok((Tensor::concat(&chord_pair.map(|c| chord_to_tensor(&c)), 0),
-                                                                                         Tensor::f_from_slice(&perf)?)) })
-                                                            .collect::<Result<Vec<(Tensor, Tensor)>, tch::TchError>>()?
-                                                            .into_iter()
-                                                            .unzip();
+    let to_tensors = |examples: &[Example<K, N, L>]| -> Result<(Vec<Tensor>, Vec<Tensor>), tch::TchError> {
+        Ok(examples.iter()
+                   .map(|(chord_pair, perf, _)| { Ok((Tensor::concat(&chord_pair.clone().map(|c| chord_to_tensor(&c)), 0),
+                                                      Tensor::f_from_slice(perf)?)) })
+                   .collect::<Result<Vec<(Tensor, Tensor)>, tch::TchError>>()?
+                   .into_iter()
+                   .unzip())
+    };
+    let (input, target) = to_tensors(&paired)?;
+    let weight: Vec<f32> = paired.iter().map(|(_, _, weight)| *weight).collect();
 
-    // split into train and test divisions
+    // split the trials into train and test divisions
     let tot_len = input.len();
     let num_test = (tot_len as f64 * TEST_FRAC).round() as usize;
     // choose num_train random indices
     let mut indices: Vec<usize> = (0..tot_len).collect();
-    indices.shuffle(&mut rand::thread_rng());
-    println!("split into {} training examples, {} test examples", tot_len - num_test, num_test);
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    println!("split into {} training examples ({} of them from practice), {} test examples", tot_len - num_test + naturalistic.len(), naturalistic.len(), num_test);
     let mut train_indices = indices.split_off(num_test);
     train_indices.sort();
     train_indices.reverse();
 
     let mut train_input = Vec::new();
     let mut train_target = Vec::new();
+    let mut train_weight = Vec::new();
     let mut test_input = Vec::new();
     let mut test_target = Vec::new();
-    for (idx, (inp, tar)) in input.into_iter().zip(target.into_iter()).enumerate() {
+    let mut test_weight = Vec::new();
+    for (idx, ((inp, tar), w)) in input.into_iter().zip(target.into_iter()).zip(weight).enumerate() {
         if train_indices.len() > 0 && train_indices[train_indices.len()-1] == idx {
             train_indices.pop();
             train_input.push(inp);
             train_target.push(tar);
+            train_weight.push(w);
         } else {
             test_input.push(inp);
             test_target.push(tar);
+            test_weight.push(w);
         }
     }
+    let (naturalistic_input, naturalistic_target) = to_tensors(&naturalistic)?;
+    train_input.extend(naturalistic_input);
+    train_target.extend(naturalistic_target);
+    train_weight.extend(naturalistic.iter().map(|(_, _, weight)| *weight));
 
-    Ok(Dataset { train_input: Tensor::stack(&train_input, 0), train_target: Tensor::stack(&train_target, 0),
-                 test_input: Tensor::stack(&test_input, 0), test_target: Tensor::stack(&test_target, 0) })
+    Ok(Dataset { train_input: Tensor::stack(&train_input, 0), train_target: Tensor::stack(&train_target, 0), train_weight: Tensor::from_slice(&train_weight),
+                 n_naturalistic: naturalistic.len(),
+                 test_input: Tensor::stack(&test_input, 0), test_target: Tensor::stack(&test_target, 0), test_weight: Tensor::from_slice(&test_weight) })
 }
 
+pub fn train<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(store: &DataStore, config: &TrainConfig, seed: u64) -> Result<Box<RewardModel<N, E>>, Box<dyn std::error::Error>> {
+    // the seed determines the train/test split, the initialization of the model, and the order of the mini-batches
+    let data = get_formatted_data::<K, N, L>(store, seed, config)?;
+    Ok(train_on_dataset::<N, E>(&data, config, seed)?.model)
+}
 
-pub fn train<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(results_path: &str, n_epochs: usize) -> Result<Box<RewardModel<N, E>>, Box<dyn std::error::Error>> {
-    let vs = nn::VarStore::new(tch::Device::Cpu);
-    let model = Box::new(RewardModel::<N, E>::new(&vs.root()));
-    let mut opt = nn::Adam::default().build(&vs, 1e-3)?;
-    let data = get_formatted_data::<K, N, L>(results_path)?;
-    for epoch in 0..n_epochs {
-        // we can process all the data at once since it's quite small
-        let train_loss = loss::<N, E>(&model, &data.train_input, &data.train_target);
-        opt.backward_step(&train_loss);
-        if epoch % 100 == 0 {
-            let test_loss = loss::<N, E>(&model, &data.test_input, &data.test_target);
-            println!("epoch: {:<5} train loss: {:<24}, test loss: {:<24}", epoch, (train_loss.double_value(&[])) as f32, (test_loss.double_value(&[])) as f32);
-        }
-    }
-    Ok(model)
+pub(crate) struct Trained<const N: usize, E: RewardEmbedding> {
+    pub(crate) model: Box<RewardModel<N, E>>,
+    pub(crate) vs: nn::VarStore,  // holds the model's weights
+    pub(crate) epochs_trained: usize,
+    pub(crate) best_epoch: usize,  // the epoch the weights are from
 }
 
-pub fn run<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(results_path: &str) {
-    match train::<K, N, L, E>(results_path, 2001) {
-        Ok(_) => (),
-        Err(e) => {
-            eprintln!("Error during training: {}", e);
-            return;
+// input, target, and weight
+type Examples = (Tensor, Tensor, Tensor);
+
+fn select_examples((input, target, weight): &Examples, indices: &[i64]) -> Examples {
+    let indices = Tensor::from_slice(indices);
+    (input.index_select(0, &indices), target.index_select(0, &indices), weight.index_select(0, &indices))
+}
+
+pub(crate) fn validation_split(n: usize, n_naturalistic: usize, validation_frac: f64, rng: &mut StdRng) -> (Vec<i64>, Vec<i64>) {
+    // the indices of (train, validation) examples, out of n of which the last n_naturalistic come from practice and so always
+    // go in train
+    let n_trials = (n - n_naturalistic) as i64;
+    let n_validation = ((n_trials as f64 * validation_frac).round() as i64).clamp(1, (n_trials - 1).max(1)).min(n_trials) as usize;
+    let mut indices: Vec<i64> = (0..n_trials).collect();
+    indices.shuffle(rng);
+    let train = indices[n_validation..].iter().copied().chain(n_trials..n as i64).collect();
+    indices.truncate(n_validation);
+    (train, indices)
+}
+
+pub(crate) fn folds(n: usize, n_naturalistic: usize, n_folds: usize, seed: u64) -> Vec<(Vec<i64>, Vec<i64>)> {
+    // the indices of (train, held out) examples for each of n_folds folds, out of n of which the last n_naturalistic come from
+    // practice and so are always in train, after the trials. the folds only depend on the seed
+    let n_trials = (n - n_naturalistic) as i64;
+    let mut indices: Vec<i64> = (0..n_trials).collect();
+    indices.shuffle(&mut StdRng::seed_from_u64(seed));
+    (0..n_folds).map(|k| {
+        // every n_folds-th trial, so that there are exactly n_folds folds of nearly equal size
+        let fold: Vec<i64> = indices.iter().skip(k).step_by(n_folds).copied().collect();
+        let rest = indices.iter().filter(|i| !fold.contains(i)).copied().chain(n_trials..n as i64).collect();
+        (rest, fold)
+    }).collect()
+}
+
+pub(crate) fn train_on_dataset<const N: usize, E: RewardEmbedding>(data: &Dataset, config: &TrainConfig, seed: u64) -> Result<Trained<N, E>, Box<dyn std::error::Error>> {
+    tch::manual_seed(seed as i64);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut vs = nn::VarStore::new(tch::Device::Cpu);
+    let model = Box::new(RewardModel::<N, E>::new(&vs.root(), &config.model));
+    let mut opt = nn::Adam { wd: config.weight_decay, ..Default::default() }.build(&vs, config.learning_rate)?;
+
+    let all_train = (data.train_input.shallow_clone(), data.train_target.shallow_clone(), data.train_weight.shallow_clone());
+    let (train, validation) = match &config.early_stopping {
+        Some(early_stopping) => {
+            let (train, validation) = validation_split(all_train.0.size()[0] as usize, data.n_naturalistic, early_stopping.validation_frac, &mut rng);
+            (select_examples(&all_train, &train), Some(select_examples(&all_train, &validation)))
+        },
+        None => (all_train, None),
+    };
+    let n_train = train.0.size()[0];
+
+    // when stopping early, we keep the weights from the epoch with the lowest validation loss in a second var store
+    // (the model built on it is only needed to create the variables to copy into)
+    let mut best_vs = validation.as_ref().map(|_| {
+        let best_vs = nn::VarStore::new(tch::Device::Cpu);
+        RewardModel::<N, E>::new(&best_vs.root(), &config.model);
+        best_vs
+    });
+    let mut best_validation_loss = f64::INFINITY;
+    // the epoch whose weights are in best_vs, if the validation loss was ever below infinity (it's NaN if training diverges)
+    let mut best_epoch: Option<usize> = None;
+
+    let mut epochs_trained = 0;
+    for epoch in 0..config.n_epochs {
+        opt.set_lr(config.lr_schedule.learning_rate(config.learning_rate, epoch, config.n_epochs));
+        let train_loss = match config.batch_size {
+            // the data is quite small, so by default we process all of it at once
+            None => {
+                let train_loss = loss::<N, E>(&model, &train.0, &train.1, &train.2, config.xe_weight);
+                opt.backward_step(&train_loss);
+                train_loss.double_value(&[])
+            },
+            Some(batch_size) => {
+                let mut indices: Vec<i64> = (0..n_train).collect();
+                indices.shuffle(&mut rng);
+                let (mut total_loss, mut total_weight) = (0.0, 0.0);
+                for batch in indices.chunks(batch_size.max(1)) {
+                    let (input, target, weight) = select_examples(&train, batch);
+                    let batch_loss = loss::<N, E>(&model, &input, &target, &weight, config.xe_weight);
+                    opt.backward_step(&batch_loss);
+                    // the batch loss is a weighted mean, so this gives the weighted mean over the whole epoch
+                    let batch_weight = weight.sum(tch::Kind::Float).double_value(&[]);
+                    total_loss += batch_loss.double_value(&[]) * batch_weight;
+                    total_weight += batch_weight;
+                }
+                total_loss / total_weight
+            },
+        };
+        epochs_trained = epoch + 1;
+
+        let validation_loss = validation.as_ref().map(|(input, target, weight)| tch::no_grad(|| loss::<N, E>(&model, input, target, weight, config.xe_weight).double_value(&[])));
+        if epoch % config.log_every.max(1) == 0 {
+            let test_loss = tch::no_grad(|| loss::<N, E>(&model, &data.test_input, &data.test_target, &data.test_weight, config.xe_weight).double_value(&[]));
+            match validation_loss {
+                Some(validation_loss) => println!("epoch: {:<5} train loss: {:<24}, validation loss: {:<24}, test loss: {:<24}", epoch, train_loss as f32, validation_loss as f32, test_loss as f32),
+                None => println!("epoch: {:<5} train loss: {:<24}, test loss: {:<24}", epoch, train_loss as f32, test_loss as f32),
+            }
+        }
+
+        // these unwraps are safe because there is a validation loss exactly when early stopping is configured
+        if let Some(validation_loss) = validation_loss {
+            let early_stopping = config.early_stopping.as_ref().unwrap();
+            if validation_loss < best_validation_loss - early_stopping.min_delta {
+                best_validation_loss = validation_loss;
+                best_epoch = Some(epoch);
+                best_vs.as_mut().unwrap().copy(&vs)?;
+            } else if epoch - best_epoch.unwrap_or(0) >= early_stopping.patience {
+                match best_epoch {
+                    Some(best_epoch) => println!("stopping early at epoch {}; the best validation loss was {} at epoch {}", epoch, best_validation_loss as f32, best_epoch),
+                    None => println!("stopping early at epoch {}; the validation loss never improved", epoch),
+                }
+                break;
+            }
         }
+    }
+    // without a best epoch, best_vs only has the weights it was initialized with, so the last epoch's weights are kept
+    let best_epoch = match (&best_vs, best_epoch) {
+        (Some(best_vs), Some(best_epoch)) => {
+            vs.copy(best_vs)?;
+            best_epoch
+        },
+        _ => epochs_trained.saturating_sub(1),
     };
+    Ok(Trained { model, vs, epochs_trained, best_epoch })
+}
+
+pub(crate) fn cross_validate<const N: usize, E: RewardEmbedding>(data: &Dataset, config: &TrainConfig, n_folds: usize, seed: u64) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+    // k-fold cross validation on the training part of data (the test part is left alone so that it stays unseen).
+    // returns the loss on each held out fold. the folds are made of pair trials, and only depend on the seed, so different
+    // configs are compared on the same folds
+    let n = data.train_input.size()[0] as usize;
+    let n_trials = n - data.n_naturalistic;
+    if n_folds < 2 || n_trials < n_folds {
+        return Err(format!("can't split {} training trials into {} folds", n_trials, n_folds).into());
+    }
+    folds(n, data.n_naturalistic, n_folds, seed).into_iter().map(|(rest, fold)| {
+        let held_out = Tensor::from_slice(&fold);
+        let rest = Tensor::from_slice(&rest);
+        let fold_data = Dataset {
+            train_input: data.train_input.index_select(0, &rest),
+            train_target: data.train_target.index_select(0, &rest),
+            train_weight: data.train_weight.index_select(0, &rest),
+            n_naturalistic: data.n_naturalistic,
+            test_input: data.train_input.index_select(0, &held_out),
+            test_target: data.train_target.index_select(0, &held_out),
+            test_weight: data.train_weight.index_select(0, &held_out),
+        };
+        let trained = train_on_dataset::<N, E>(&fold_data, config, seed)?;
+        Ok(tch::no_grad(|| loss::<N, E>(&trained.model, &fold_data.test_input, &fold_data.test_target, &fold_data.test_weight, config.xe_weight).double_value(&[])))
+    }).collect()
+}
+
+fn save_checkpoint(vs: &nn::VarStore, info: &CheckpointInfo, checkpoint_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(checkpoint_dir)?;
+    let dir = std::path::Path::new(checkpoint_dir);
+    vs.save(dir.join(CHECKPOINT_WEIGHTS_FILE))?;
+    serde_json::to_writer_pretty(std::fs::File::create(dir.join(CHECKPOINT_CONFIG_FILE))?, info)?;
+    println!("saved checkpoint to {}", checkpoint_dir);
+    Ok(())
+}
+
+pub fn load_checkpoint<const N: usize, E: RewardEmbedding>(checkpoint_dir: &str) -> Result<(Box<RewardModel<N, E>>, CheckpointInfo), Box<dyn std::error::Error>> {
+    // the config saved with the weights determines the architecture to load them into
+    let dir = std::path::Path::new(checkpoint_dir);
+    let info: CheckpointInfo = serde_json::from_reader(std::fs::File::open(dir.join(CHECKPOINT_CONFIG_FILE))?)?;
+    let mut vs = nn::VarStore::new(tch::Device::Cpu);
+    let model = Box::new(RewardModel::<N, E>::new(&vs.root(), &info.config.model));
+    vs.load(dir.join(CHECKPOINT_WEIGHTS_FILE))?;
+    Ok((model, info))
+}
+
+pub fn evaluate<K: Key, const N: usize, L: Layout<K, N>, M: Module>(model: &M, store: &DataStore, config: &TrainConfig, seed: u64) -> Result<f64, Box<dyn std::error::Error>> {
+    // the loss of any model with the same output format as RewardModel (e.g. the heuristic model) on the test set.
+    // with the same seed and config, this is the same test set (with the same outlier handling) that train uses
+    let data = get_formatted_data::<K, N, L>(store, seed, config)?;
+    Ok(output_loss(&model.forward(&data.test_input), &data.test_target, &data.test_weight, config.xe_weight).double_value(&[]))
+}
+
+pub fn compare_to_heuristic<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(store: &DataStore, config: &TrainConfig, seed: u64, checkpoint_dir: Option<&str>) -> Result<(f64, f64), Box<dyn std::error::Error>> {
+    // train a model (saving it to checkpoint_dir if given), and compare its test loss to that of the heuristic model on the same test set.
+    // returns (learned model test loss, heuristic model test loss)
+    let data = get_formatted_data::<K, N, L>(store, seed, config)?;
+    let trained = train_on_dataset::<N, E>(&data, config, seed)?;
+    if let Some(checkpoint_dir) = checkpoint_dir {
+        save_checkpoint(&trained.vs, &CheckpointInfo { config: config.clone(), seed, epochs_trained: trained.epochs_trained, best_epoch: Some(trained.best_epoch) }, checkpoint_dir)?;
+    }
+    let learned_loss = loss::<N, E>(&trained.model, &data.test_input, &data.test_target, &data.test_weight, config.xe_weight).double_value(&[]);
+    let heuristic_loss = output_loss(&HeuristicModel::<K, N, L>::new().forward(&data.test_input), &data.test_target, &data.test_weight, config.xe_weight).double_value(&[]);
+    println!("test loss of learned model: {:<24}, test loss of heuristic model: {:<24}", learned_loss as f32, heuristic_loss as f32);
+    Ok((learned_loss, heuristic_loss))
+}
+
+pub fn run<K: Key, const N: usize, L: Layout<K, N>, E: RewardEmbedding>(store: &DataStore, config: &TrainConfig, seed: u64, checkpoint_dir: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    compare_to_heuristic::<K, N, L, E>(store, config, seed, checkpoint_dir).map_err(|e| format!("Error during training: {}", e))?;
+    Ok(())
 }